@@ -0,0 +1,87 @@
+use std::path::Path;
+
+use anyhow::anyhow;
+use hyperdot_core::config::Catalog;
+use hyperdot_node::rpc::JseeRpcClient;
+use hyperdot_node::rpc::JseeRpcClientParams;
+use hyperdot_node::storeage::client::JsonRpcClientParams;
+use hyperdot_node::storeage::client::JsonRpcClinet;
+use hyperdot_node::types::rpc::BlockGaps as BlockGapsRequest;
+use subxt::PolkadotConfig;
+
+/// Report the blocks missing from the storage nodes of chain
+/// compared with the chain finalized head.
+#[derive(Debug, clap::Parser)]
+pub struct BlockGaps {
+    /// The catalog config path.
+    #[clap(long)]
+    catalog: String,
+    /// The chain name in catalog.
+    #[clap(long)]
+    chain: String,
+    /// The first block number to check, default is the lowest
+    /// block number in storage.
+    #[clap(long)]
+    start: Option<u64>,
+}
+
+impl BlockGaps {
+    pub fn execute(self) -> anyhow::Result<()> {
+        let rt = tokio::runtime::Runtime::new()?;
+        rt.block_on(self.report())
+    }
+
+    async fn report(self) -> anyhow::Result<()> {
+        let catalog = Catalog::try_from(Path::new(&self.catalog))
+            .map_err(|err| anyhow!("init catalog error: {}", err))?;
+        let chain = catalog
+            .chain
+            .iter()
+            .find(|chain| chain.name == self.chain)
+            .ok_or(anyhow!("chain({}) not found in catalog", self.chain))?;
+
+        let client =
+            JseeRpcClient::<PolkadotConfig>::async_new(&chain.url, &JseeRpcClientParams::default())
+                .await
+                .map_err(|err| anyhow!("{}: new rpc client error: {}", chain.name, err))?;
+        let online = client.get_online();
+        let head_hash = online.rpc().finalized_head().await?;
+        let head = online.blocks().at(head_hash).await?.header().number as u64;
+        println!("{}: finalized head #{}", chain.name, head);
+
+        for node_name in chain.storage_nodes.iter().flatten() {
+            let node_cfg = catalog
+                .storage
+                .get_node_config(node_name)
+                .ok_or(anyhow!("storage node({}) not found in catalog", node_name))?;
+            let node_client = JsonRpcClinet::new(&node_cfg.rpc.endpoint(), JsonRpcClientParams {
+                tls: node_cfg.rpc.client_tls.clone(),
+            })?;
+            let response = node_client
+                .block_gaps(BlockGapsRequest {
+                    chain: chain.name.clone(),
+                    start: self.start,
+                    end: head,
+                })
+                .await
+                .map_err(|err| anyhow!("{}: find block gaps error: {}", node_name, err))?;
+
+            let missing: u64 = response
+                .gaps
+                .iter()
+                .map(|gap| gap.end - gap.start + 1)
+                .sum();
+            println!(
+                "{}: {} gaps, {} blocks missing",
+                node_name,
+                response.gaps.len(),
+                missing
+            );
+            for gap in response.gaps.iter() {
+                println!("  #{}..#{}", gap.start, gap.end);
+            }
+        }
+
+        Ok(())
+    }
+}
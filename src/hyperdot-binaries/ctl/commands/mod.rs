@@ -1,3 +1,7 @@
+mod block_gaps;
 mod metadata_codegen;
+mod pg_migrate;
 
+pub use block_gaps::BlockGaps;
 pub use metadata_codegen::MetadataCodegen;
+pub use pg_migrate::PgMigrate;
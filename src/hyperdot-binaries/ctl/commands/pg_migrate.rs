@@ -0,0 +1,95 @@
+use std::path::Path;
+
+use anyhow::anyhow;
+use hyperdot_core::config::Catalog;
+use hyperdot_node::storeage::PgMigrator;
+
+/// Create the postgres databases of a storage node and apply the
+/// pending schema migrations.
+#[derive(Debug, clap::Parser)]
+pub struct PgMigrate {
+    /// The catalog config path.
+    #[clap(long)]
+    catalog: String,
+    /// The storage node name in catalog.
+    #[clap(long)]
+    node: String,
+    /// Only migrate the database of the chain, default is all the
+    /// enabled chains of the node.
+    #[clap(long)]
+    chain: Option<String>,
+    /// Print the pending migrations without applying them.
+    #[clap(long)]
+    dry_run: bool,
+}
+
+impl PgMigrate {
+    pub fn execute(self) -> anyhow::Result<()> {
+        let rt = tokio::runtime::Runtime::new()?;
+        rt.block_on(self.migrate())
+    }
+
+    async fn migrate(self) -> anyhow::Result<()> {
+        let catalog = Catalog::try_from(Path::new(&self.catalog))
+            .map_err(|err| anyhow!("init catalog error: {}", err))?;
+        let node_cfg = catalog
+            .storage
+            .get_node_config(&self.node)
+            .ok_or(anyhow!("storage node({}) not found in catalog", self.node))?;
+
+        for postgres in node_cfg
+            .data_engines
+            .iter()
+            .filter_map(|de| de.postgres.as_ref())
+        {
+            for support_chain in postgres.support_chains.iter() {
+                if !support_chain.enabled {
+                    continue;
+                }
+                if self
+                    .chain
+                    .as_ref()
+                    .is_some_and(|chain| chain != &support_chain.name)
+                {
+                    continue;
+                }
+
+                let connection = postgres
+                    .connections
+                    .iter()
+                    .find(|c| c.name == support_chain.use_connection)
+                    .ok_or(anyhow!(
+                        "{}: connection({}) not found in postgres.connections",
+                        support_chain.name,
+                        support_chain.use_connection
+                    ))?;
+                let migrator = PgMigrator::new(connection, &support_chain.dbname);
+
+                if self.dry_run {
+                    let pending = migrator.pending().await?;
+                    println!(
+                        "{}: dbname({}) {} pending migrations",
+                        support_chain.name,
+                        support_chain.dbname,
+                        pending.len()
+                    );
+                    for (version, name) in pending {
+                        println!("  V{:04}__{}", version, name);
+                    }
+                    continue;
+                }
+
+                let applied = migrator.run().await?;
+                println!(
+                    "{}: dbname({}) applied {} migrations, schema version {}",
+                    support_chain.name,
+                    support_chain.dbname,
+                    applied.len(),
+                    PgMigrator::latest_version()
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
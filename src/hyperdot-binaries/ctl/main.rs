@@ -2,7 +2,9 @@ use anyhow::anyhow;
 use anyhow::Result;
 use clap::CommandFactory;
 use clap::Parser;
+use commands::BlockGaps;
 use commands::MetadataCodegen;
+use commands::PgMigrate;
 
 mod commands;
 
@@ -20,12 +22,20 @@ enum Cmd {
     /// Generate runtime metadata
     #[clap(name = "metadata-codegen")]
     MetadataCodegen(MetadataCodegen),
+    /// Report the missing blocks of chain in storage nodes
+    #[clap(name = "block-gaps")]
+    BlockGaps(BlockGaps),
+    /// Create the postgres databases of storage node and apply migrations
+    #[clap(name = "pg-migrate")]
+    PgMigrate(PgMigrate),
 }
 
 fn main() -> anyhow::Result<()> {
     let args = Args::try_parse()?;
     match args.cmd {
         Some(Cmd::MetadataCodegen(cmd)) => cmd.execute(),
+        Some(Cmd::BlockGaps(cmd)) => cmd.execute(),
+        Some(Cmd::PgMigrate(cmd)) => cmd.execute(),
         None => {
             Args::command().print_long_help()?;
             // Note: clap uses an exit code of 2 when CLI parsing fails
@@ -2,6 +2,7 @@ use std::path::Path;
 
 use anyhow::anyhow;
 use clap::Parser;
+use hyperdot_core::config::BackfillConfig;
 use hyperdot_core::config::Catalog;
 use hyperdot_node::streaming::etl;
 // use hyperdot_node::streaming::jsonrpc::server::JsonRpcServerParams;
@@ -19,6 +20,27 @@ struct AppArgs {
     /// The catalog config path.
     #[arg(long)]
     catalog: String,
+    /// Override the backfill range of chain, format is
+    /// `<chain>=<start>..<end>` or `<chain>=<start>..`.
+    #[arg(long, value_parser = parse_backfill)]
+    backfill: Vec<(String, BackfillConfig)>,
+    /// The directory of sync checkpoints.
+    #[arg(long, default_value = "checkpoints")]
+    checkpoint_dir: String,
+    /// The directory of outboxes, the blocks not delivered to storage
+    /// nodes are kept here.
+    #[arg(long, default_value = "outbox")]
+    outbox_dir: String,
+}
+
+fn parse_backfill(s: &str) -> Result<(String, BackfillConfig), String> {
+    let (chain, range) = s
+        .split_once('=')
+        .ok_or(format!("backfill({}) expected <chain>=<start>..<end>", s))?;
+    let backfill = range
+        .parse::<BackfillConfig>()
+        .map_err(|err| err.to_string())?;
+    Ok((chain.to_string(), backfill))
 }
 
 #[tokio::main]
@@ -30,9 +52,25 @@ async fn main() -> anyhow::Result<()> {
         .try_init()?;
 
     let args = AppArgs::parse();
-    let catalog = Catalog::try_from(Path::new(&args.catalog))
+    let mut catalog = Catalog::try_from(Path::new(&args.catalog))
         .map_err(|err| anyhow!("init catalog error: {}", err))?;
-    let mut controller = etl::StreamingController::async_new(catalog).await?;
+    for (chain_name, backfill) in args.backfill.into_iter() {
+        let chain = catalog
+            .chain
+            .iter_mut()
+            .find(|chain| chain.name == chain_name)
+            .ok_or(anyhow!(
+                "backfill chain({}) not found in catalog",
+                chain_name
+            ))?;
+        chain.backfill = Some(backfill);
+    }
+    let mut controller = etl::StreamingController::async_new(
+        catalog,
+        Path::new(&args.checkpoint_dir),
+        Path::new(&args.outbox_dir),
+    )
+    .await?;
     controller.start().await?;
     controller.stopped().await?;
     Ok(())
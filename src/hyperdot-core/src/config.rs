@@ -6,15 +6,63 @@ use serde::Serialize;
 use super::types::DataEngineInfo;
 use crate::types::ChainKind;
 
+/// The certificates of a TLS listener, in PEM files.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerTlsConfig {
+    /// The certificate chain path.
+    pub cert: String,
+    /// The private key path.
+    pub key: String,
+    /// The CA certificates path to verify the client certificates. If
+    /// none, the clients are not asked for certificates.
+    pub client_ca: Option<String>,
+}
+
+/// The TLS options of a client, in PEM files.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ClientTlsConfig {
+    /// The CA certificates path to verify the server certificate. Default
+    /// is the system root certificates.
+    pub ca: Option<String>,
+    /// The client certificate chain path, for the servers verifying the
+    /// clients with `client_ca`.
+    pub cert: Option<String>,
+    /// The private key path of `cert`.
+    pub key: Option<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StorageRpcConfig {
     pub url: String,
     pub scheme: Option<String>,
+    /// The wire encoding of blocks sent to the node, `scale` or `json`.
+    /// Fallback to json if the node not supports it. Default is scale.
+    pub encoding: Option<String>,
+    /// Serve the json-rpc over TLS if some, the clients should use the
+    /// wss scheme.
+    pub tls: Option<ServerTlsConfig>,
+    /// The TLS options of the clients connecting with the wss scheme,
+    /// e.g. the speaker. Default is the system root certificates without
+    /// a client certificate.
+    pub client_tls: Option<ClientTlsConfig>,
+}
+
+impl StorageRpcConfig {
+    /// Get the endpoint with scheme, the default scheme is ws.
+    pub fn endpoint(&self) -> String {
+        self.scheme
+            .as_ref()
+            .map_or(format!("ws://{}", self.url), |s| {
+                format!("{}://{}", s, self.url)
+            })
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StorageApiServerConfig {
     pub url: String,
+    /// Serve the http api over TLS if some.
+    pub tls: Option<ServerTlsConfig>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -24,6 +72,20 @@ pub struct StorageNodeConfig {
     pub rpc: StorageRpcConfig,
     pub apiserver: StorageApiServerConfig,
     pub data_engines: Vec<DataEngineInfo>,
+    /// The max blocks sent to the node in one write_block call.
+    /// Default is 64.
+    pub write_batch_size: Option<usize>,
+    /// The max milliseconds waiting for blocks to fill up a batch
+    /// before sending. Default is 200.
+    pub write_batch_timeout: Option<u64>,
+    /// The times a request rejected by the node as never writable is
+    /// retried before moved to the dead letters of the outbox. The other
+    /// failures, e.g. the node or its database is unavailable, are
+    /// retried without limit. Default is 10.
+    pub max_delivery_attempts: Option<u32>,
+    /// The max requests kept in the outbox of the node, writing blocks
+    /// waits for the delivery once it's full. Default is 4096.
+    pub outbox_capacity: Option<usize>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -60,15 +122,85 @@ pub struct PolkadotRuntime {
     pub config: String,
 }
 
+/// The historical block range extracted before following the
+/// finalized head.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackfillConfig {
+    /// The first block number to backfill.
+    pub start: u64,
+    /// The last block number to backfill. If none, backfill until
+    /// the finalized head at startup.
+    pub end: Option<u64>,
+}
+
+impl std::str::FromStr for BackfillConfig {
+    type Err = anyhow::Error;
+
+    /// Parse from `<start>..<end>` or `<start>..`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s.split_once("..").ok_or(anyhow::anyhow!(
+            "backfill range({}) expected <start>..<end>",
+            s
+        ))?;
+        let start = start
+            .parse::<u64>()
+            .map_err(|err| anyhow::anyhow!("backfill range({}) start invalid: {}", s, err))?;
+        let end = match end {
+            "" => None,
+            end => Some(
+                end.parse::<u64>()
+                    .map_err(|err| anyhow::anyhow!("backfill range({}) end invalid: {}", s, err))?,
+            ),
+        };
+
+        if let Some(end) = end {
+            if end < start {
+                return Err(anyhow::anyhow!("backfill range({}) end less than start", s));
+            }
+        }
+
+        Ok(Self { start, end })
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChainConfig {
     pub id: usize,
     pub name: String,
     pub url: String,
+    /// The fallback rpc endpoints, rotated with `url` when the
+    /// connection broken.
+    pub fallback_urls: Option<Vec<String>>,
     pub kind: ChainKind,
     pub polkadot_runtime: Option<PolkadotRuntime>,
     pub storage_nodes: Option<Vec<String>>,
     pub enabled: bool,
+    /// If set, the historical blocks are extracted before
+    /// following the finalized head.
+    pub backfill: Option<BackfillConfig>,
+    /// The interval seconds of detecting and repairing the missing
+    /// blocks in storage nodes, default is 60, 0 disables it.
+    pub gap_repair_interval: Option<u64>,
+    /// If true, the unfinalized best blocks are streamed too, and
+    /// flip to finalized when finality catches up. Default is false.
+    pub follow_best: Option<bool>,
+    /// The max blocks fetched and decoded at once when syncing a
+    /// range of blocks, such as backfill. Default is 8.
+    pub extract_concurrency: Option<usize>,
+    /// The max blocks queued between extracting and writing to storage
+    /// nodes, the extracting waits when it is full. Default is 256.
+    pub block_queue_size: Option<usize>,
+}
+
+impl ChainConfig {
+    /// Get all rpc endpoints of the chain, the `url` is the first.
+    pub fn endpoints(&self) -> Vec<String> {
+        let mut endpoints = vec![self.url.clone()];
+        if let Some(fallback_urls) = self.fallback_urls.as_ref() {
+            endpoints.extend(fallback_urls.iter().cloned());
+        }
+        endpoints
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -224,4 +356,18 @@ mod tests {
         let catalog: Catalog = serde_json::from_str(config).unwrap();
         println!("{:?}", catalog)
     }
+
+    #[test]
+    fn test_parse_backfill() {
+        let backfill: BackfillConfig = "10..20".parse().unwrap();
+        assert_eq!(backfill.start, 10);
+        assert_eq!(backfill.end, Some(20));
+
+        let backfill: BackfillConfig = "10..".parse().unwrap();
+        assert_eq!(backfill.start, 10);
+        assert_eq!(backfill.end, None);
+
+        assert!("20..10".parse::<BackfillConfig>().is_err());
+        assert!("10".parse::<BackfillConfig>().is_err());
+    }
 }
@@ -7,6 +7,7 @@ use super::types::ChainKind;
 use super::types::EngineInfo;
 use super::types::PostgresRows;
 use super::types::PostgresTableInfo;
+use super::types::QueryOptions;
 
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub enum ResponseCode {
@@ -63,6 +64,11 @@ pub struct ListDataEngineResponse {
     pub engines: HashMap<String, EngineInfo>,
 }
 
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct HealthResponse {
+    pub meta: ResponseMetadata,
+}
+
 #[derive(Default, Clone, Serialize, Deserialize)]
 pub struct GetPostgresSchemeRequest {
     pub chain: String,
@@ -83,10 +89,17 @@ pub struct QueryPostgresRequest {
     pub chain: String,
     /// What query it is.
     pub query: String,
+    /// The bind parameters and the page of the query.
+    #[serde(flatten)]
+    pub options: QueryOptions,
 }
 
 #[derive(Default, Clone, Deserialize, Serialize)]
 pub struct QueryPostgresResponse {
     pub meta: ResponseMetadata,
     pub rows: PostgresRows,
+    /// The rows of the whole query, only if `with_total` is requested.
+    pub total: Option<usize>,
+    /// If true the next page starts at `offset + rows.len`.
+    pub has_more: bool,
 }
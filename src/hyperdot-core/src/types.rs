@@ -30,6 +30,11 @@ impl ToString for ChainKind {
 #[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
 pub enum DataEngineKind {
     Postgres,
+    DuckDB,
+    ClickHouse,
+    Parquet,
+    InfluxDB,
+    SQLite,
 }
 
 impl Default for DataEngineKind {
@@ -42,6 +47,27 @@ impl ToString for DataEngineKind {
     fn to_string(&self) -> String {
         match *self {
             Self::Postgres => "postgres".to_string(),
+            Self::DuckDB => "duckdb".to_string(),
+            Self::ClickHouse => "clickhouse".to_string(),
+            Self::Parquet => "parquet".to_string(),
+            Self::InfluxDB => "influxdb".to_string(),
+            Self::SQLite => "sqlite".to_string(),
+        }
+    }
+}
+
+impl std::str::FromStr for DataEngineKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "postgres" => Ok(Self::Postgres),
+            "duckdb" => Ok(Self::DuckDB),
+            "clickhouse" => Ok(Self::ClickHouse),
+            "parquet" => Ok(Self::Parquet),
+            "influxdb" => Ok(Self::InfluxDB),
+            "sqlite" => Ok(Self::SQLite),
+            _ => Err(anyhow::anyhow!("{} data engine not support", s)),
         }
     }
 }
@@ -72,6 +98,30 @@ pub struct PostgresDataEngineConnection {
     pub host: String,
     /// The postgres connection port.
     pub port: u16,
+    /// The max connections of the query pool of each chain using the
+    /// connection. Default is 8.
+    pub pool_size: Option<usize>,
+    /// The seconds to wait for a pooled connection before giving up.
+    /// Default is 30.
+    pub pool_timeout_secs: Option<u64>,
+    /// The TLS mode, `disable`, `prefer`, `require` or `verify-full`.
+    /// `prefer` and `require` encrypt without verifying the server
+    /// certificate. Default is disable.
+    pub sslmode: Option<String>,
+    /// The CA certificates path (PEM) to verify the server certificate
+    /// with `verify-full`. Default is the system root certificates.
+    pub sslrootcert: Option<String>,
+    /// The client certificate path (PEM), for the servers requiring
+    /// client certificates.
+    pub sslcert: Option<String>,
+    /// The client private key path (PEM) of `sslcert`.
+    pub sslkey: Option<String>,
+    /// The role running the ad-hoc queries of the query api, it should
+    /// only be granted `SELECT`. The ad-hoc queries are refused if none.
+    pub query_username: Option<String>,
+    /// The password of `query_username`, not sent if none, e.g. the role
+    /// is authenticated by the client certificate.
+    pub query_password: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -82,12 +132,216 @@ pub struct PostgresDataEngine {
     /// The postgres support chains
     /// Note: connections[i] + support_chains[i].dbname = real connection.
     pub support_chains: Vec<PostgresDataEngineForChain>,
+    /// The milliseconds an ad-hoc query could run before canceled.
+    /// Default is 30000.
+    pub query_timeout_ms: Option<u64>,
+    /// The max rows an ad-hoc query returns, the rest are dropped.
+    /// Default is 10000.
+    pub query_max_rows: Option<usize>,
+    /// The leading keywords of the allowed ad-hoc queries. Default is
+    /// `SELECT`, `WITH`, `VALUES`, `TABLE`, `EXPLAIN` and `SHOW`.
+    pub query_statements: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DuckDBDataEngineForChain {
+    /// The chain alias name.
+    pub name: String,
+    /// The database file of the chain, `:memory:` for in-memory database.
+    pub path: String,
+    /// If true the storage enabled
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DuckDBDataEngine {
+    /// The duckdb support chains, a database file per chain.
+    pub support_chains: Vec<DuckDBDataEngineForChain>,
+    /// The milliseconds an ad-hoc query could run before interrupted.
+    /// Default is 30000.
+    pub query_timeout_ms: Option<u64>,
+    /// The max rows an ad-hoc query returns, the rest are dropped.
+    /// Default is 10000.
+    pub query_max_rows: Option<usize>,
+    /// The leading keywords of the allowed ad-hoc queries. Default is
+    /// `SELECT`, `WITH`, `VALUES`, `FROM`, `EXPLAIN`, `DESCRIBE`, `SHOW`
+    /// and `SUMMARIZE`.
+    pub query_statements: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClickHouseDataEngineForChain {
+    /// The chain alias name.
+    pub name: String,
+    /// The chain database name, created if not exists.
+    pub database: String,
+    /// If true the storage enabled
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClickHouseDataEngine {
+    /// The clickhouse http interface url, e.g. `http://localhost:8123`.
+    pub url: String,
+    /// The clickhouse username, default user if none.
+    pub username: Option<String>,
+    /// The clickhouse password.
+    pub password: Option<String>,
+    /// The number of blocks in a table partition, default 1000000.
+    pub partition_blocks: Option<u64>,
+    /// The clickhouse support chains, a database per chain.
+    pub support_chains: Vec<ClickHouseDataEngineForChain>,
+    /// The milliseconds an ad-hoc query could run before cancelled, sent as
+    /// the whole seconds of `max_execution_time`. Default is 30000.
+    pub query_timeout_ms: Option<u64>,
+    /// The max rows an ad-hoc query returns, the rest are dropped.
+    /// Default is 10000.
+    pub query_max_rows: Option<usize>,
+    /// The leading keywords of the allowed ad-hoc queries. Default is
+    /// `SELECT`, `WITH`, `EXPLAIN`, `DESCRIBE`, `DESC` and `SHOW`.
+    pub query_statements: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParquetDataEngineForChain {
+    /// The chain alias name, also the directory of the chain files.
+    pub name: String,
+    /// If true the storage enabled
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParquetDataEngine {
+    /// The root directory of the files, which are written to
+    /// `{root}/{chain}/{table}/date={date}/part-{n}.parquet`.
+    pub root: String,
+    /// Roll the file once it has the number of rows, default 1000000.
+    pub max_file_rows: Option<usize>,
+    /// Roll the file once it's opened for the seconds, default 3600.
+    pub max_file_secs: Option<u64>,
+    /// The parquet support chains.
+    pub support_chains: Vec<ParquetDataEngineForChain>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InfluxDBDataEngineForChain {
+    /// The chain alias name, also the `chain` tag of points.
+    pub name: String,
+    /// The bucket of the chain points.
+    pub bucket: String,
+    /// If true the storage enabled
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InfluxDBDataEngine {
+    /// The influxdb url, e.g. `http://localhost:8086`.
+    pub url: String,
+    /// The organization of the buckets.
+    pub org: String,
+    /// The api token, no authorization if none.
+    pub token: Option<String>,
+    /// The influxdb support chains.
+    pub support_chains: Vec<InfluxDBDataEngineForChain>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SQLiteDataEngineForChain {
+    /// The chain alias name.
+    pub name: String,
+    /// The database file of the chain, `:memory:` for in-memory database.
+    pub path: String,
+    /// If true the storage enabled
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SQLiteDataEngine {
+    /// The sqlite support chains, a database file per chain.
+    pub support_chains: Vec<SQLiteDataEngineForChain>,
+    /// The milliseconds an ad-hoc query could run before interrupted.
+    /// Default is 30000.
+    pub query_timeout_ms: Option<u64>,
+    /// The max rows an ad-hoc query returns, the rest are dropped.
+    /// Default is 10000.
+    pub query_max_rows: Option<usize>,
+    /// The leading keywords of the allowed ad-hoc queries. Default is
+    /// `SELECT`, `WITH`, `VALUES` and `EXPLAIN`.
+    pub query_statements: Option<Vec<String>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DataEngineInfo {
     pub kind: DataEngineKind,
     pub postgres: Option<PostgresDataEngine>,
+    pub duckdb: Option<DuckDBDataEngine>,
+    pub clickhouse: Option<ClickHouseDataEngine>,
+    pub parquet: Option<ParquetDataEngine>,
+    pub influxdb: Option<InfluxDBDataEngine>,
+    pub sqlite: Option<SQLiteDataEngine>,
+}
+
+impl DataEngineInfo {
+    /// The enabled chains of the configured engines, paired with the kind
+    /// of their engine.
+    pub fn support_chains(&self) -> Vec<(&str, DataEngineKind)> {
+        let mut chains = vec![];
+        if let Some(engine) = self.postgres.as_ref() {
+            chains.extend(
+                engine
+                    .support_chains
+                    .iter()
+                    .filter(|sc| sc.enabled)
+                    .map(|sc| (sc.name.as_str(), DataEngineKind::Postgres)),
+            );
+        }
+        if let Some(engine) = self.duckdb.as_ref() {
+            chains.extend(
+                engine
+                    .support_chains
+                    .iter()
+                    .filter(|sc| sc.enabled)
+                    .map(|sc| (sc.name.as_str(), DataEngineKind::DuckDB)),
+            );
+        }
+        if let Some(engine) = self.clickhouse.as_ref() {
+            chains.extend(
+                engine
+                    .support_chains
+                    .iter()
+                    .filter(|sc| sc.enabled)
+                    .map(|sc| (sc.name.as_str(), DataEngineKind::ClickHouse)),
+            );
+        }
+        if let Some(engine) = self.parquet.as_ref() {
+            chains.extend(
+                engine
+                    .support_chains
+                    .iter()
+                    .filter(|sc| sc.enabled)
+                    .map(|sc| (sc.name.as_str(), DataEngineKind::Parquet)),
+            );
+        }
+        if let Some(engine) = self.influxdb.as_ref() {
+            chains.extend(
+                engine
+                    .support_chains
+                    .iter()
+                    .filter(|sc| sc.enabled)
+                    .map(|sc| (sc.name.as_str(), DataEngineKind::InfluxDB)),
+            );
+        }
+        if let Some(engine) = self.sqlite.as_ref() {
+            chains.extend(
+                engine
+                    .support_chains
+                    .iter()
+                    .filter(|sc| sc.enabled)
+                    .map(|sc| (sc.name.as_str(), DataEngineKind::SQLite)),
+            );
+        }
+        chains
+    }
 }
 
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
@@ -151,6 +405,33 @@ pub struct PostgresRows {
     pub rows: Vec<serde_json::Map<String, serde_json::Value>>,
 }
 
+/// The bind parameters and the page of a query.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct QueryOptions {
+    /// The values bound to `$1..$n` in order, converted to the parameter
+    /// types of the statement.
+    #[serde(default)]
+    pub params: Vec<serde_json::Value>,
+    /// The max rows of the page, capped by the max rows of the engine.
+    pub limit: Option<usize>,
+    /// The rows skipped before the page.
+    #[serde(default)]
+    pub offset: usize,
+    /// If true the rows of the whole query are counted.
+    #[serde(default)]
+    pub with_total: bool,
+}
+
+/// A page of the rows of a query.
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct PostgresRowsPage {
+    pub rows: PostgresRows,
+    /// The rows of the whole query, if it's counted.
+    pub total: Option<usize>,
+    /// If true there are more rows after the page.
+    pub has_more: bool,
+}
+
 impl TryFrom<Vec<Row>> for PostgresRows {
     type Error = anyhow::Error;
     fn try_from(rows: Vec<Row>) -> Result<Self, Self::Error> {
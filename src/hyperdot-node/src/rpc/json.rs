@@ -15,9 +15,11 @@ use jsonrpsee::types::SubscriptionId;
 use serde_json;
 use serde_json::value::RawValue;
 use subxt::error::RpcError;
+use subxt::rpc::types::RuntimeVersion;
 use subxt::rpc::RpcClientT;
 use subxt::rpc::RpcSubscription;
 use subxt::Config;
+use subxt::Metadata;
 use subxt::OnlineClient;
 
 /// Constrant for polkadot main network endpoints
@@ -96,6 +98,13 @@ pub struct JseeRpcClientParams {
     ///
     /// This function panics if `max` is 0
     pub max_buffer_capacity_per_subscription: usize,
+
+    /// The backoff after all endpoints failed to connect, doubled
+    /// for each round (default is 1s).
+    pub min_reconnect_backoff: Duration,
+
+    /// The max backoff of reconnection (default is 60s).
+    pub max_reconnect_backoff: Duration,
 }
 
 impl Default for JseeRpcClientParams {
@@ -104,17 +113,27 @@ impl Default for JseeRpcClientParams {
             connection_timeout: Duration::from_secs(1),
             max_concurrent_requests: 256,
             max_buffer_capacity_per_subscription: 1024,
+            min_reconnect_backoff: Duration::from_secs(1),
+            max_reconnect_backoff: Duration::from_secs(60),
         }
     }
 }
 
 /// Wrap jsonrpsee
-#[derive(Clone)]
 pub struct JseeRpcClient<C: Config> {
     pub(crate) inner: Arc<WrapJsonrpseeClient>,
     pub(crate) online: OnlineClient<C>,
 }
 
+impl<C: Config> Clone for JseeRpcClient<C> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            online: self.online.clone(),
+        }
+    }
+}
+
 impl<C> JseeRpcClient<C>
 where C: Config
 {
@@ -138,12 +157,77 @@ where C: Config
         })
     }
 
+    /// Connect to the endpoints in rotation from `start`, and return the
+    /// index of the connected endpoint with client. After all endpoints
+    /// failed in a round, sleep with backoff before the next round. If
+    /// `max_rounds` is none, retry forever.
+    pub async fn async_new_failover(
+        urls: &[String],
+        start: usize,
+        params: &JseeRpcClientParams,
+        max_rounds: Option<usize>,
+    ) -> AnyResult<(usize, Self)> {
+        if urls.is_empty() {
+            return Err(anyhow::anyhow!("rpc endpoints is empty"));
+        }
+
+        let mut backoff = params.min_reconnect_backoff;
+        let mut round = 0;
+        loop {
+            for i in 0..urls.len() {
+                let index = (start + i) % urls.len();
+                match Self::async_new(&urls[index], params).await {
+                    Err(err) => {
+                        tracing::warn!("🔌 connect rpc endpoint({}) error: {}", urls[index], err)
+                    }
+                    Ok(client) => {
+                        tracing::info!("🔌 rpc endpoint({}) connected", urls[index]);
+                        return Ok((index, client));
+                    }
+                }
+            }
+
+            round += 1;
+            if let Some(max_rounds) = max_rounds {
+                if round >= max_rounds {
+                    return Err(anyhow::anyhow!(
+                        "all rpc endpoints({:?}) not available",
+                        urls
+                    ));
+                }
+            }
+
+            tracing::warn!(
+                "🔌 all rpc endpoints not available, retry after {:?}",
+                backoff
+            );
+            tokio::time::sleep(backoff).await;
+            backoff = std::cmp::min(backoff * 2, params.max_reconnect_backoff);
+        }
+    }
+
     /// Get online client.
     #[inline]
     pub fn get_online(&self) -> OnlineClient<C> {
         self.online.clone()
     }
 
+    /// New online client sharing the connection, but decoding with
+    /// the given runtime version and metadata.
+    pub fn online_with(
+        &self,
+        runtime_version: RuntimeVersion,
+        metadata: Metadata,
+    ) -> AnyResult<OnlineClient<C>> {
+        OnlineClient::<C>::from_rpc_client_with(
+            self.online.genesis_hash(),
+            runtime_version,
+            metadata,
+            self.inner.clone(),
+        )
+        .map_err(|err| anyhow::anyhow!("{}", err))
+    }
+
     /// Checks if the client is connected to the target.
     #[inline]
     pub fn is_connected(&self) -> bool {
@@ -169,4 +253,20 @@ mod tests {
         .unwrap();
         assert_eq!(cli.is_connected(), true)
     }
+
+    #[tokio::test]
+    async fn test_rpc_client_failover_unavailable() {
+        let urls = vec![
+            "ws://127.0.0.1:1".to_string(),
+            "ws://127.0.0.1:2".to_string(),
+        ];
+        let res = JseeRpcClient::<PolkadotConfig>::async_new_failover(
+            &urls,
+            1,
+            &JseeRpcClientParams::default(),
+            Some(2),
+        )
+        .await;
+        assert!(res.is_err())
+    }
 }
@@ -1,33 +1,182 @@
+use std::sync::Arc;
+
 use anyhow::Result as AnyResult;
+use hyperdot_core::config::ClientTlsConfig;
+use jsonrpsee::core::client::ClientBuilder;
 use jsonrpsee::core::client::ClientT;
+use jsonrpsee::core::client::Subscription;
+use jsonrpsee::core::client::SubscriptionClientT;
+use jsonrpsee::core::traits::ToRpcParams;
 use jsonrpsee::http_client::HttpClient;
 use jsonrpsee::http_client::HttpClientBuilder;
+use jsonrpsee::rpc_params;
+use jsonrpsee::ws_client::WsClient;
+use jsonrpsee::ws_client::WsClientBuilder;
+use serde::de::DeserializeOwned;
+use tokio::sync::Mutex;
 
+use super::transport;
+use crate::types::rpc::BlockAck;
+use crate::types::rpc::BlockGaps;
+use crate::types::rpc::BlockGapsResponse;
+use crate::types::rpc::EncodedWriteBlock;
+use crate::types::rpc::SubscribeBlockAcks;
+use crate::types::rpc::WireEncoding;
+use crate::types::rpc::WireEncodingsResponse;
 use crate::types::rpc::WriteBlock;
 // use crate::types::rpc::WriteBlockRequest;
 use crate::types::rpc::WriteBlockResponse;
 
-pub struct JsonRpcClientParams {}
-impl Default for JsonRpcClientParams {
-    fn default() -> Self {
-        Self {}
-    }
+#[derive(Default)]
+pub struct JsonRpcClientParams {
+    /// The TLS options of the wss connections, the jsonrpsee defaults if
+    /// none.
+    pub tls: Option<ClientTlsConfig>,
+}
+
+/// The transport chosen by the scheme of url, `ws` and `wss` keep a
+/// persistent connection, others request over http.
+enum Transport {
+    Http(Box<HttpClient>),
+    /// Connected at the first request, and reconnected once the
+    /// connection is lost.
+    Ws {
+        url: String,
+        tls: Option<ClientTlsConfig>,
+        client: Mutex<Option<Arc<WsClient>>>,
+    },
 }
 
 pub struct JsonRpcClinet {
     params: JsonRpcClientParams,
-    client: HttpClient,
+    transport: Transport,
 }
 
 impl JsonRpcClinet {
     pub fn new(url: &str, params: JsonRpcClientParams) -> AnyResult<Self> {
-        let client = HttpClientBuilder::default().build(url)?;
+        if params.tls.is_some() && !url.starts_with("wss://") {
+            return Err(anyhow::anyhow!(
+                "{}: client tls requires the wss scheme",
+                url
+            ));
+        }
+
+        let transport = if url.starts_with("ws://") || url.starts_with("wss://") {
+            Transport::Ws {
+                url: url.to_string(),
+                tls: params.tls.clone(),
+                client: Mutex::new(None),
+            }
+        } else {
+            Transport::Http(Box::new(HttpClientBuilder::default().build(url)?))
+        };
+
+        Ok(Self { params, transport })
+    }
+
+    /// Get the connected websocket client, connect if not connected.
+    async fn ws_client(
+        url: &str,
+        tls: Option<&ClientTlsConfig>,
+        client: &Mutex<Option<Arc<WsClient>>>,
+    ) -> Result<Arc<WsClient>, jsonrpsee::core::Error> {
+        let mut client = client.lock().await;
+        if let Some(connected) = client.as_ref().filter(|c| c.is_connected()) {
+            return Ok(connected.clone());
+        }
+
+        let connected = match tls {
+            None => WsClientBuilder::default().build(url).await?,
+            Some(tls) => {
+                let (sender, receiver) = transport::connect_tls(url, tls)
+                    .await
+                    .map_err(jsonrpsee::core::Error::Transport)?;
+                ClientBuilder::default().build_with_tokio(sender, receiver)
+            }
+        };
+        let connected = Arc::new(connected);
+        tracing::info!("🔌 storage node json-rpc({}) connected", url);
+        *client = Some(connected.clone());
+        Ok(connected)
+    }
 
-        Ok(Self { params, client })
+    async fn request<R, P>(&self, method: &str, params: P) -> Result<R, jsonrpsee::core::Error>
+    where
+        R: DeserializeOwned,
+        P: ToRpcParams + Send,
+    {
+        match &self.transport {
+            Transport::Http(client) => client.request(method, params).await,
+            Transport::Ws { url, tls, client } => {
+                Self::ws_client(url, tls.as_ref(), client)
+                    .await?
+                    .request(method, params)
+                    .await
+            }
+        }
     }
 
     pub async fn write_block2(&self, request: WriteBlock) -> anyhow::Result<WriteBlockResponse> {
-        let response = self.client.request("write_block", request).await?;
+        let response = self.request("write_block", request).await?;
+        Ok(response)
+    }
+
+    pub async fn write_block_encoded(
+        &self,
+        request: EncodedWriteBlock,
+    ) -> anyhow::Result<WriteBlockResponse> {
+        let response = self.request("write_block_encoded", request).await?;
+        Ok(response)
+    }
+
+    /// Negotiate the wire encoding with the storage node. Use the
+    /// preferred encoding if the node supports it, otherwise json. The
+    /// node not having `wire_encodings` method only supports json.
+    pub async fn negotiate_encoding(
+        &self,
+        preferred: WireEncoding,
+    ) -> anyhow::Result<WireEncoding> {
+        if preferred == WireEncoding::Json {
+            return Ok(WireEncoding::Json);
+        }
+
+        match self
+            .request::<WireEncodingsResponse, _>("wire_encodings", rpc_params![])
+            .await
+        {
+            Ok(response) if response.encodings.contains(&preferred) => Ok(preferred),
+            Ok(_) | Err(jsonrpsee::core::Error::Call(_)) => Ok(WireEncoding::Json),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Subscribe the acknowledgements of blocks stored by the node, only
+    /// available over websocket.
+    pub async fn subscribe_block_acks(
+        &self,
+        request: SubscribeBlockAcks,
+    ) -> anyhow::Result<Subscription<BlockAck>> {
+        match &self.transport {
+            Transport::Http(_) => Err(anyhow::anyhow!(
+                "block acks subscription requires websocket transport"
+            )),
+            Transport::Ws { url, tls, client } => {
+                let subscription = Self::ws_client(url, tls.as_ref(), client)
+                    .await?
+                    .subscribe("subscribe_block_acks", request, "unsubscribe_block_acks")
+                    .await?;
+                Ok(subscription)
+            }
+        }
+    }
+
+    /// Checks if the acknowledgements of blocks can be subscribed.
+    pub fn is_websocket(&self) -> bool {
+        matches!(self.transport, Transport::Ws { .. })
+    }
+
+    pub async fn block_gaps(&self, request: BlockGaps) -> anyhow::Result<BlockGapsResponse> {
+        let response = self.request("block_gaps", request).await?;
         Ok(response)
     }
 }
@@ -1,4 +1,5 @@
 mod jsonrpc;
+mod transport;
 
 pub use jsonrpc::JsonRpcClientParams;
 pub use jsonrpc::JsonRpcClinet;
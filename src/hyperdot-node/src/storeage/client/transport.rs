@@ -0,0 +1,129 @@
+use std::time::Duration;
+
+use anyhow::anyhow;
+use futures::io::BufReader;
+use futures::io::BufWriter;
+use http::Uri;
+use hyperdot_core::config::ClientTlsConfig;
+use jsonrpsee::core::async_trait;
+use jsonrpsee::core::client::ReceivedMessage;
+use jsonrpsee::core::client::TransportReceiverT;
+use jsonrpsee::core::client::TransportSenderT;
+use jsonrpsee_core::TEN_MB_SIZE_BYTES;
+use rustls::pki_types::ServerName;
+use soketto::connection;
+use soketto::data::ByteSlice125;
+use soketto::handshake::client::Client as HandshakeClient;
+use soketto::handshake::client::ServerResponse;
+use soketto::Data;
+use soketto::Incoming;
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_util::compat::Compat;
+use tokio_util::compat::TokioAsyncReadCompatExt;
+
+use crate::storeage::tls;
+
+/// The max seconds of connecting and the websocket handshake, the same as
+/// the jsonrpsee websocket client.
+const CONNECT_TIMEOUT_SECS: u64 = 10;
+
+type TlsIo = BufReader<BufWriter<Compat<TlsStream<TcpStream>>>>;
+
+/// The sending end of the websocket over TLS.
+pub(crate) struct TlsSender {
+    inner: connection::Sender<TlsIo>,
+}
+
+/// The receiving end of the websocket over TLS.
+pub(crate) struct TlsReceiver {
+    inner: connection::Receiver<TlsIo>,
+}
+
+#[async_trait]
+impl TransportSenderT for TlsSender {
+    type Error = connection::Error;
+
+    async fn send(&mut self, body: String) -> Result<(), Self::Error> {
+        self.inner.send_text(body).await?;
+        self.inner.flush().await
+    }
+
+    async fn send_ping(&mut self) -> Result<(), Self::Error> {
+        let empty: &[u8] = &[];
+        let data = ByteSlice125::try_from(empty).expect("empty ping data");
+        self.inner.send_ping(data).await?;
+        self.inner.flush().await
+    }
+
+    async fn close(&mut self) -> Result<(), Self::Error> {
+        self.inner.close().await
+    }
+}
+
+#[async_trait]
+impl TransportReceiverT for TlsReceiver {
+    type Error = connection::Error;
+
+    async fn receive(&mut self) -> Result<ReceivedMessage, Self::Error> {
+        loop {
+            let mut message = vec![];
+            match self.inner.receive(&mut message).await? {
+                Incoming::Data(Data::Text(_)) => {
+                    return String::from_utf8(message)
+                        .map(ReceivedMessage::Text)
+                        .map_err(|err| connection::Error::Utf8(err.utf8_error()))
+                }
+                Incoming::Data(Data::Binary(_)) => return Ok(ReceivedMessage::Bytes(message)),
+                Incoming::Pong(_) => return Ok(ReceivedMessage::Pong),
+                _ => continue,
+            }
+        }
+    }
+}
+
+/// Connect the websocket of the wss url over TLS with the client TLS
+/// options, which the jsonrpsee websocket client can't take.
+pub(crate) async fn connect_tls(
+    url: &str,
+    cfg: &ClientTlsConfig,
+) -> anyhow::Result<(TlsSender, TlsReceiver)> {
+    let uri = url
+        .parse::<Uri>()
+        .map_err(|err| anyhow!("invalid url {}: {}", url, err))?;
+    if uri.scheme_str() != Some("wss") {
+        return Err(anyhow!("{}: client tls requires the wss scheme", url));
+    }
+    let host = uri.host().ok_or(anyhow!("{}: no host", url))?;
+    let port = uri.port_u16().unwrap_or(443);
+    let resource = uri.path_and_query().map_or("/", |path| path.as_str());
+    let server_name = ServerName::try_from(host.to_string())
+        .map_err(|err| anyhow!("{}: invalid server name: {}", url, err))?;
+    let connector = tls::client_tls_connector(cfg)?;
+
+    let connect = async {
+        let stream = TcpStream::connect((host, port)).await?;
+        let stream = connector.connect(server_name, stream).await?;
+        let io = BufReader::new(BufWriter::new(stream.compat()));
+        let host_header = format!("{}:{}", host, port);
+        let mut client = HandshakeClient::new(io, &host_header, resource);
+        match client.handshake().await? {
+            ServerResponse::Accepted { .. } => {}
+            ServerResponse::Rejected { status_code } => {
+                return Err(anyhow!("websocket handshake rejected: {}", status_code))
+            }
+            ServerResponse::Redirect { status_code, .. } => {
+                return Err(anyhow!("websocket redirect not supported: {}", status_code))
+            }
+        }
+
+        let mut builder = client.into_builder();
+        builder.set_max_message_size(TEN_MB_SIZE_BYTES as usize);
+        let (sender, receiver) = builder.finish();
+        Ok((TlsSender { inner: sender }, TlsReceiver { inner: receiver }))
+    };
+    tokio::time::timeout(Duration::from_secs(CONNECT_TIMEOUT_SECS), connect)
+        .await
+        .map_err(|_| anyhow!("{}: connect timeout", url))?
+        .map_err(|err: anyhow::Error| anyhow!("{}: connect error: {}", url, err))
+}
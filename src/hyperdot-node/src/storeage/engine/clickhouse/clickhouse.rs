@@ -0,0 +1,707 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use hyperdot_core::types::ChainKind;
+use hyperdot_core::types::ClickHouseDataEngine;
+use hyperdot_core::types::DataEngineKind;
+use hyperdot_core::types::PostgresColumnDataType;
+use hyperdot_core::types::PostgresRows;
+use hyperdot_core::types::PostgresRowsPage;
+use hyperdot_core::types::PostgresTableInfo;
+use hyperdot_core::types::QueryOptions;
+use serde::Deserialize;
+
+use super::super::engine::ChainBlocks;
+use super::super::engine::DataEngine;
+use super::super::sandbox::PageQuery;
+use super::super::sandbox::QueryLimits;
+use super::client::ClickHouseClient;
+use super::writer::substrate_schema;
+use super::writer::SubstrateWriter;
+
+const DEFAULT_PARTITION_BLOCKS: u64 = 1_000_000;
+
+const SCHEMA_COLUMNS_STMT: &str = r#"
+SELECT table, name, type FROM system.columns
+WHERE database = currentDatabase()
+ORDER BY table, position
+"#;
+
+const DEFAULT_QUERY_STATEMENTS: [&str; 6] =
+    ["SELECT", "WITH", "EXPLAIN", "DESCRIBE", "DESC", "SHOW"];
+
+/// The statements paged and counted as a subquery.
+const PAGED_STATEMENTS: [&str; 2] = ["SELECT", "WITH"];
+
+/// The settings of query, the result is in `JSONCompact` format, the 64
+/// bits integers are numbers as the postgres rows, and the tables are
+/// read with `FINAL` so the replaced rows are not seen.
+const QUERY_SETTINGS: [(&str, &str); 3] = [
+    ("default_format", "JSONCompact"),
+    ("output_format_json_quote_64bit_integers", "0"),
+    ("final", "1"),
+];
+
+#[derive(Deserialize)]
+struct JsonCompactColumn {
+    name: String,
+    #[serde(rename = "type")]
+    data_type: String,
+}
+
+/// The result of query in `JSONCompact` format.
+#[derive(Deserialize)]
+struct JsonCompactResult {
+    meta: Vec<JsonCompactColumn>,
+    data: Vec<Vec<serde_json::Value>>,
+}
+
+/// The clickhouse engine over http interface, a database per chain.
+pub struct ClickHouseEngine {
+    client: ClickHouseClient,
+    // support chain name map to database
+    databases: HashMap<String, String>,
+    limits: QueryLimits,
+}
+
+impl ClickHouseEngine {
+    pub async fn new(engine: ClickHouseDataEngine) -> anyhow::Result<Self> {
+        let client = ClickHouseClient::new(&engine.url, engine.username, engine.password);
+        let schema = substrate_schema(
+            engine
+                .partition_blocks
+                .unwrap_or(DEFAULT_PARTITION_BLOCKS)
+                .max(1),
+        );
+
+        let mut databases = HashMap::new();
+        for support_chain in engine.support_chains.iter() {
+            if !support_chain.enabled {
+                tracing::info!(
+                    "💁 {}: skipped not enabled for clickhouse data engine",
+                    support_chain.name
+                );
+                continue;
+            }
+
+            let create_database =
+                format!("CREATE DATABASE IF NOT EXISTS `{}`", support_chain.database);
+            client
+                .execute(None, &create_database)
+                .await
+                .map_err(|err| {
+                    anyhow!(
+                        "{}: create clickhouse database({}) error: {}",
+                        support_chain.name,
+                        support_chain.database,
+                        err
+                    )
+                })?;
+            for stmt in schema.iter() {
+                client
+                    .execute(Some(&support_chain.database), stmt)
+                    .await
+                    .map_err(|err| {
+                        anyhow!(
+                            "{}: create clickhouse tables error: {}",
+                            support_chain.name,
+                            err
+                        )
+                    })?;
+            }
+
+            tracing::info!(
+                "🏠 {}: clickhouse data engine using database {}",
+                support_chain.name,
+                support_chain.database,
+            );
+            databases.insert(support_chain.name.clone(), support_chain.database.clone());
+        }
+
+        let limits = QueryLimits::new(
+            engine.query_timeout_ms,
+            engine.query_max_rows,
+            engine.query_statements.as_ref(),
+            &DEFAULT_QUERY_STATEMENTS,
+        );
+        Ok(Self {
+            client,
+            databases,
+            limits,
+        })
+    }
+
+    fn get_database(&self, chain: &str) -> anyhow::Result<&str> {
+        self.databases
+            .get(chain)
+            .map(|database| database.as_str())
+            .ok_or(anyhow!("ClickHouse not support chain({})", chain))
+    }
+
+    async fn query_compact(&self, database: &str, sql: &str) -> anyhow::Result<JsonCompactResult> {
+        let mut params = vec![("database", database)];
+        params.extend(QUERY_SETTINGS);
+        let text = self.client.post(&params, sql.to_string()).await?;
+        serde_json::from_str(&text).map_err(|err| anyhow!("invalid JSONCompact result: {}", err))
+    }
+
+    /// Check the ad-hoc sql is allowed and has no `FORMAT` clause, return
+    /// the leading keyword.
+    fn check(&self, sql: &str) -> anyhow::Result<String> {
+        let keyword = self.limits.check(sql)?;
+        if let Some(format) = format_clause(sql) {
+            return Err(anyhow!(
+                "FORMAT {} clause not supported, the rows are returned in JSONCompact",
+                format
+            ));
+        }
+        Ok(keyword)
+    }
+
+    /// Run the checked ad-hoc sql in readonly mode, the query is stopped by
+    /// the server after the timeout, and at most `max_rows` rows are
+    /// returned. The `readonly` setting is the last one, the settings before
+    /// it are still allowed.
+    async fn query_limited(
+        &self,
+        database: &str,
+        sql: &str,
+        max_rows: usize,
+    ) -> anyhow::Result<JsonCompactResult> {
+        // The max_execution_time of clickhouse is in seconds.
+        let timeout = self
+            .limits
+            .timeout
+            .as_millis()
+            .div_ceil(1000)
+            .max(1)
+            .to_string();
+        let max_result_rows = max_rows.to_string();
+        let mut params = vec![("database", database)];
+        params.extend(QUERY_SETTINGS);
+        params.extend([
+            ("max_execution_time", timeout.as_str()),
+            ("max_result_rows", max_result_rows.as_str()),
+            ("result_overflow_mode", "break"),
+            ("readonly", "1"),
+        ]);
+        let text = self.client.post(&params, sql.to_string()).await?;
+        let mut result: JsonCompactResult = serde_json::from_str(&text)
+            .map_err(|err| anyhow!("invalid JSONCompact result: {}", err))?;
+        // The overflow breaks at the block of rows, not the exact row.
+        result.data.truncate(max_rows);
+        Ok(result)
+    }
+}
+
+/// Convert the `JSONCompact` result to the rows as the postgres rows.
+fn compact_rows(result: JsonCompactResult) -> PostgresRows {
+    let columns = result
+        .meta
+        .iter()
+        .map(|column| column.name.clone())
+        .collect::<Vec<_>>();
+    let column_types = result
+        .meta
+        .iter()
+        .map(|column| column_type(&column.data_type))
+        .collect();
+    let rows = result
+        .data
+        .into_iter()
+        .map(|values| columns.iter().cloned().zip(values).collect())
+        .collect::<Vec<serde_json::Map<_, _>>>();
+    PostgresRows {
+        columns,
+        len: rows.len(),
+        column_types,
+        rows,
+    }
+}
+
+/// The format of the trailing `FORMAT` clause of the sql, the quoted
+/// strings, identifiers and comments are skipped.
+fn format_clause(sql: &str) -> Option<String> {
+    // The words of sql, none for the other tokens.
+    let mut tokens: Vec<Option<String>> = vec![];
+    let chars = sql.chars().collect::<Vec<_>>();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '\'' || c == '"' || c == '`' {
+            i += 1;
+            while i < chars.len() && chars[i] != c {
+                i += if chars[i] == '\\' { 2 } else { 1 };
+            }
+            i += 1;
+            tokens.push(None);
+        } else if c == '-' && chars.get(i + 1) == Some(&'-') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && chars.get(i + 1) == Some(&'*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                i += 1;
+            }
+            i += 2;
+        } else if c.is_alphanumeric() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Some(chars[start..i].iter().collect()));
+        } else {
+            if !c.is_whitespace() && c != ';' {
+                tokens.push(None);
+            }
+            i += 1;
+        }
+    }
+
+    match tokens.as_slice() {
+        [.., Some(keyword), Some(format)] if keyword.eq_ignore_ascii_case("FORMAT") => {
+            Some(format.clone())
+        }
+        _ => None,
+    }
+}
+
+/// Map the clickhouse type to the postgres column type, the `Nullable`
+/// and `LowCardinality` wrappers are ignored.
+fn column_type(data_type: &str) -> PostgresColumnDataType {
+    let mut data_type = data_type;
+    for wrapper in ["Nullable(", "LowCardinality("] {
+        if let Some(inner) = data_type
+            .strip_prefix(wrapper)
+            .and_then(|t| t.strip_suffix(')'))
+        {
+            data_type = inner;
+        }
+    }
+    if let Some(inner) = data_type
+        .strip_prefix("Array(")
+        .and_then(|t| t.strip_suffix(')'))
+    {
+        return match column_type(inner) {
+            PostgresColumnDataType::BOOL => PostgresColumnDataType::BOOL_ARRAY,
+            PostgresColumnDataType::SMALLINT => PostgresColumnDataType::SMALLINT_ARRAY,
+            PostgresColumnDataType::INT => PostgresColumnDataType::INT_ARRAY,
+            PostgresColumnDataType::BIGINT => PostgresColumnDataType::BIGINT_ARRAY,
+            PostgresColumnDataType::TEXT => PostgresColumnDataType::TEXT_ARRAY,
+            PostgresColumnDataType::FLOAT4 => PostgresColumnDataType::FLOAT4_ARRAY,
+            PostgresColumnDataType::Float8 => PostgresColumnDataType::FLOAT8_ARRAY,
+            _ => PostgresColumnDataType::Invalid,
+        };
+    }
+
+    match data_type {
+        "Bool" => PostgresColumnDataType::BOOL,
+        "Int8" | "Int16" | "UInt8" => PostgresColumnDataType::SMALLINT,
+        "Int32" | "UInt16" => PostgresColumnDataType::INT,
+        "Int64" | "UInt32" => PostgresColumnDataType::BIGINT,
+        "UInt64" | "Int128" | "UInt128" | "Int256" | "UInt256" => PostgresColumnDataType::NUMERIC,
+        "Float32" => PostgresColumnDataType::FLOAT4,
+        "Float64" => PostgresColumnDataType::Float8,
+        "String" | "UUID" | "Date" | "Date32" => PostgresColumnDataType::TEXT,
+        t if t.starts_with("Decimal") => PostgresColumnDataType::NUMERIC,
+        t if t.starts_with("FixedString") || t.starts_with("DateTime") => {
+            PostgresColumnDataType::TEXT
+        }
+        _ => PostgresColumnDataType::Invalid,
+    }
+}
+
+#[async_trait::async_trait]
+impl DataEngine for ClickHouseEngine {
+    fn name(&self) -> String {
+        "ClickHouse".to_string()
+    }
+
+    fn kind(&self) -> DataEngineKind {
+        DataEngineKind::ClickHouse
+    }
+
+    async fn supports_chain(&self, chain: &str, kind: &ChainKind) -> bool {
+        matches!(kind, ChainKind::Polkadot) && self.databases.contains_key(chain)
+    }
+
+    async fn write_block(&self, chain: &str, blocks: &ChainBlocks) -> anyhow::Result<()> {
+        let database = self.get_database(chain)?;
+        match blocks {
+            ChainBlocks::Polkadot(blocks) => {
+                SubstrateWriter::write_blocks(&self.client, database, blocks).await
+            }
+        }
+        .map_err(|err| anyhow!("{}: write blocks error: {}", chain, err))
+    }
+
+    async fn schema(&self, chain: &str) -> anyhow::Result<HashMap<String, Vec<PostgresTableInfo>>> {
+        let database = self.get_database(chain)?;
+        let result = self.query_compact(database, SCHEMA_COLUMNS_STMT).await?;
+        let mut tables: HashMap<String, Vec<PostgresTableInfo>> = HashMap::new();
+        for row in result.data.iter() {
+            match row.as_slice() {
+                [serde_json::Value::String(table), serde_json::Value::String(name), serde_json::Value::String(data_type)] => {
+                    tables
+                        .entry(table.clone())
+                        .or_default()
+                        .push(PostgresTableInfo {
+                            column_name: name.clone(),
+                            data_type: data_type.clone(),
+                        })
+                }
+                _ => return Err(anyhow!("{}: invalid clickhouse columns row", chain)),
+            }
+        }
+        Ok(tables)
+    }
+
+    async fn health(&self) -> anyhow::Result<()> {
+        self.client
+            .execute(None, "SELECT 1")
+            .await
+            .map_err(|err| anyhow!("clickhouse not available: {}", err))?;
+        Ok(())
+    }
+
+    async fn query(&self, chain: &str, sql: &str) -> anyhow::Result<PostgresRows> {
+        let database = self.get_database(chain)?;
+        let result = async {
+            self.check(sql)?;
+            self.query_limited(database, sql, self.limits.max_rows)
+                .await
+        }
+        .await
+        .map_err(|err| {
+            anyhow!(
+                "ClickHouse data engine run sql for chain({}) error:{}",
+                chain,
+                err
+            )
+        })?;
+        Ok(compact_rows(result))
+    }
+
+    async fn query_page(
+        &self,
+        chain: &str,
+        sql: &str,
+        options: &QueryOptions,
+    ) -> anyhow::Result<PostgresRowsPage> {
+        if !options.params.is_empty() {
+            return Err(anyhow!(
+                "{}: {} data engine not support query parameters",
+                chain,
+                self.name()
+            ));
+        }
+
+        let database = self.get_database(chain)?;
+        async {
+            let keyword = self.check(sql)?;
+            let query = PageQuery::new(
+                &keyword,
+                sql,
+                options,
+                self.limits.max_rows,
+                &PAGED_STATEMENTS,
+            );
+            let result = self
+                .query_limited(database, &query.sql, query.fetch_rows())
+                .await?;
+            let total = match query.count.as_ref() {
+                None => None,
+                Some(count) => {
+                    let result = self.query_limited(database, count, 1).await?;
+                    let total = result
+                        .data
+                        .first()
+                        .and_then(|row| row.first())
+                        .and_then(serde_json::Value::as_u64)
+                        .ok_or(anyhow!("invalid count result"))?;
+                    Some(total as usize)
+                }
+            };
+            Ok(query.page(compact_rows(result), total))
+        }
+        .await
+        .map_err(|err: anyhow::Error| {
+            anyhow!(
+                "ClickHouse data engine run sql for chain({}) error:{}",
+                chain,
+                err
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    use axum::extract::Query;
+    use axum::extract::State;
+    use axum::http::StatusCode;
+    use axum::routing::post;
+    use axum::Router;
+    use hyperdot_core::types::ClickHouseDataEngineForChain;
+
+    use super::*;
+    use crate::storeage::engine::testing::new_block;
+
+    type Requests = Arc<Mutex<Vec<(HashMap<String, String>, String)>>>;
+
+    /// Record the requests and respond the `JSONCompact` result of the
+    /// queries, as the http interface of clickhouse.
+    async fn mock_handler(
+        State(requests): State<Requests>,
+        Query(params): Query<HashMap<String, String>>,
+        body: String,
+    ) -> (StatusCode, String) {
+        requests.lock().unwrap().push((params, body.clone()));
+        if body.ends_with("FORMAT TSV") {
+            // The event of a stored fork not in the written blocks.
+            match body.contains("FROM events") {
+                true => (StatusCode::OK, "2-5\t2\n".to_string()),
+                false => (StatusCode::OK, "".to_string()),
+            }
+        } else if body.starts_with("SELECT count(*) FROM (") {
+            let result = serde_json::json!({
+                "meta": [{"name": "count()", "type": "UInt64"}],
+                "data": [[2]],
+            });
+            (StatusCode::OK, result.to_string())
+        } else if body.contains("system.columns") {
+            let result = serde_json::json!({
+                "meta": [
+                    {"name": "table", "type": "String"},
+                    {"name": "name", "type": "String"},
+                    {"name": "type", "type": "String"},
+                ],
+                "data": [
+                    ["blocks", "number", "UInt64"],
+                    ["blocks", "hash", "String"],
+                    ["events", "values", "Nullable(String)"],
+                ],
+            });
+            (StatusCode::OK, result.to_string())
+        } else if body.contains("FROM events") {
+            let result = serde_json::json!({
+                "meta": [
+                    {"name": "block_number", "type": "UInt64"},
+                    {"name": "event_name", "type": "LowCardinality(String)"},
+                    {"name": "count()", "type": "UInt64"},
+                ],
+                "data": [[1, "ExtrinsicSuccess", 2], [2, "ExtrinsicSuccess", 1]],
+                "rows": 2,
+            });
+            (StatusCode::OK, result.to_string())
+        } else if body.contains("no_such_table") {
+            (
+                StatusCode::NOT_FOUND,
+                "Code: 60. DB::Exception: Table no_such_table does not exist.".to_string(),
+            )
+        } else {
+            (StatusCode::OK, "".to_string())
+        }
+    }
+
+    async fn mock_server() -> (String, Requests) {
+        let requests = Requests::default();
+        let app = Router::new()
+            .route("/", post(mock_handler))
+            .with_state(requests.clone());
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+        (url, requests)
+    }
+
+    async fn new_engine(url: &str) -> ClickHouseEngine {
+        ClickHouseEngine::new(ClickHouseDataEngine {
+            url: url.to_string(),
+            username: Some("hyperdot".to_string()),
+            password: None,
+            partition_blocks: Some(1000),
+            query_timeout_ms: Some(1500),
+            query_max_rows: Some(1),
+            query_statements: None,
+            support_chains: vec![ClickHouseDataEngineForChain {
+                name: "polkadot".to_string(),
+                database: "polkadot".to_string(),
+                enabled: true,
+            }],
+        })
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_clickhouse_write_blocks() {
+        let (url, requests) = mock_server().await;
+        let engine = new_engine(&url).await;
+        {
+            let requests = requests.lock().unwrap();
+            assert_eq!(requests.len(), 5);
+            assert_eq!(requests[0].1, "CREATE DATABASE IF NOT EXISTS `polkadot`");
+            assert_eq!(requests[1].0["database"], "polkadot");
+            assert!(requests[1].1.contains("PARTITION BY intDiv(number, 1000)"));
+        }
+        assert!(
+            engine
+                .supports_chain("polkadot", &ChainKind::Polkadot)
+                .await
+        );
+        assert!(!engine.supports_chain("kusama", &ChainKind::Polkadot).await);
+        requests.lock().unwrap().clear();
+
+        let mut blocks = vec![];
+        for number in 1..=2 {
+            let mut block = new_block(number, true);
+            let events = block.body.events.as_mut().unwrap();
+            let mut event = events[0].clone();
+            event.id = format!("{}-1", number);
+            event.event_index = 1;
+            events.push(event);
+            blocks.push(block);
+        }
+        engine
+            .write_block("polkadot", &ChainBlocks::Polkadot(blocks))
+            .await
+            .unwrap();
+
+        let requests = requests.lock().unwrap();
+        // The stale rows of 4 tables, and inserts of the events and blocks
+        // in one batch per table, no mutation.
+        assert_eq!(requests.len(), 6);
+        assert!(requests[..4]
+            .iter()
+            .all(|(_, body)| body.starts_with("SELECT")));
+        let (params, body) = &requests[4];
+        assert_eq!(params["query"], "INSERT INTO events FORMAT JSONEachRow");
+        let events = body
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(events.len(), 5);
+        assert_eq!(events[3]["id"], "2-1");
+        assert_eq!(events[3]["values"], r#"{"weight":2}"#);
+        assert_eq!(events[3]["is_deleted"], 0);
+        assert_eq!(events[4]["id"], "2-5");
+        assert_eq!(events[4]["is_deleted"], 1);
+        assert_eq!(events[4]["version"], events[3]["version"]);
+        let (params, body) = &requests[5];
+        assert_eq!(params["query"], "INSERT INTO blocks FORMAT JSONEachRow");
+        assert_eq!(body.lines().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_clickhouse_query() {
+        let (url, requests) = mock_server().await;
+        let engine = new_engine(&url).await;
+        engine.health().await.unwrap();
+
+        let rows = engine
+            .query(
+                "polkadot",
+                "SELECT block_number, event_name, count() FROM events GROUP BY block_number, event_name",
+            )
+            .await
+            .unwrap();
+        // The rows over the max are dropped.
+        assert_eq!(rows.len, 1);
+        assert_eq!(rows.columns, vec!["block_number", "event_name", "count()"]);
+        assert!(matches!(rows.column_types[..], [
+            PostgresColumnDataType::NUMERIC,
+            PostgresColumnDataType::TEXT,
+            PostgresColumnDataType::NUMERIC
+        ]));
+        assert_eq!(rows.rows[0]["count()"], serde_json::json!(2));
+        let (params, _) = requests.lock().unwrap().last().cloned().unwrap();
+        assert_eq!(params["database"], "polkadot");
+        assert_eq!(params["default_format"], "JSONCompact");
+        assert_eq!(params["readonly"], "1");
+        assert_eq!(params["final"], "1");
+        assert_eq!(params["max_execution_time"], "2");
+        assert_eq!(params["max_result_rows"], "1");
+
+        let sent = requests.lock().unwrap().len();
+        for sql in [
+            "DROP TABLE events",
+            "INSERT INTO events SELECT * FROM events",
+            "SELECT * FROM events FORMAT CSV",
+        ] {
+            assert!(engine.query("polkadot", sql).await.is_err(), "{}", sql);
+        }
+        assert_eq!(requests.lock().unwrap().len(), sent);
+
+        let page = engine
+            .query_page(
+                "polkadot",
+                "SELECT block_number, event_name, count() FROM events GROUP BY block_number, event_name;",
+                &QueryOptions {
+                    with_total: true,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(page.rows.len, 1);
+        assert_eq!(page.total, Some(2));
+        assert!(page.has_more);
+        {
+            let requests = requests.lock().unwrap();
+            let (params, body) = &requests[requests.len() - 2];
+            assert!(body.ends_with(") AS page LIMIT 2 OFFSET 0"), "{}", body);
+            assert_eq!(params["max_result_rows"], "2");
+            assert_eq!(params["readonly"], "1");
+        }
+        assert!(engine
+            .query_page(
+                "polkadot",
+                "SELECT * FROM events FORMAT CSV",
+                &QueryOptions::default()
+            )
+            .await
+            .is_err());
+
+        let tables = engine.schema("polkadot").await.unwrap();
+        assert_eq!(tables.len(), 2);
+        assert_eq!(tables["blocks"].len(), 2);
+        assert_eq!(tables["events"][0].data_type, "Nullable(String)");
+
+        match engine
+            .query("polkadot", "SELECT * FROM no_such_table")
+            .await
+        {
+            Ok(_) => panic!("query of not existed table should fail"),
+            Err(err) => assert!(err
+                .to_string()
+                .contains("Table no_such_table does not exist")),
+        }
+        assert!(engine.query("kusama", "SELECT 1").await.is_err());
+    }
+
+    #[test]
+    fn test_format_clause() {
+        assert_eq!(
+            format_clause("SELECT 1 FORMAT CSV;").as_deref(),
+            Some("CSV")
+        );
+        assert_eq!(
+            format_clause("select 1 format JSONEachRow -- rows").as_deref(),
+            Some("JSONEachRow")
+        );
+        assert_eq!(format_clause("SELECT format FROM t"), None);
+        assert_eq!(format_clause("SELECT 'FORMAT CSV'"), None);
+        assert_eq!(format_clause("SELECT 1 /* FORMAT CSV */"), None);
+        assert_eq!(format_clause("SELECT `format` AS `CSV`"), None);
+    }
+}
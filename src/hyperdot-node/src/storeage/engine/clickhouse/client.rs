@@ -0,0 +1,90 @@
+use anyhow::anyhow;
+use hyper::client::HttpConnector;
+use hyper::Body;
+use hyper::Client;
+use hyper::Method;
+use hyper::Request;
+
+/// The client of the clickhouse http interface.
+pub(crate) struct ClickHouseClient {
+    url: String,
+    username: Option<String>,
+    password: Option<String>,
+    client: Client<HttpConnector>,
+}
+
+impl ClickHouseClient {
+    pub(crate) fn new(url: &str, username: Option<String>, password: Option<String>) -> Self {
+        Self {
+            url: url.to_string(),
+            username,
+            password,
+            client: Client::new(),
+        }
+    }
+
+    /// Post the body with the url parameters, e.g. `database`, `query`
+    /// and settings. The inserted rows are sent as body and the insert
+    /// statement as `query` parameter. Returns the response text, the
+    /// error message of clickhouse if not success.
+    pub(crate) async fn post(
+        &self,
+        params: &[(&str, &str)],
+        body: String,
+    ) -> anyhow::Result<String> {
+        let mut url = url::Url::parse(&self.url)?;
+        url.query_pairs_mut().extend_pairs(params.iter());
+
+        let mut request = Request::builder().method(Method::POST).uri(url.as_str());
+        if let Some(username) = self.username.as_ref() {
+            request = request.header("X-ClickHouse-User", username);
+        }
+        if let Some(password) = self.password.as_ref() {
+            request = request.header("X-ClickHouse-Key", password);
+        }
+
+        let response = self.client.request(request.body(Body::from(body))?).await?;
+        let status = response.status();
+        let bytes = hyper::body::to_bytes(response.into_body()).await?;
+        let text = String::from_utf8_lossy(&bytes).to_string();
+        if !status.is_success() {
+            return Err(anyhow!("clickhouse response {}: {}", status, text.trim()));
+        }
+        Ok(text)
+    }
+
+    /// Run the statement in database.
+    pub(crate) async fn execute(
+        &self,
+        database: Option<&str>,
+        stmt: &str,
+    ) -> anyhow::Result<String> {
+        match database {
+            None => self.post(&[], stmt.to_string()).await,
+            Some(database) => self.post(&[("database", database)], stmt.to_string()).await,
+        }
+    }
+
+    /// Insert the rows in `JSONEachRow` format to the table of database.
+    pub(crate) async fn insert(
+        &self,
+        database: &str,
+        table: &str,
+        rows: Vec<serde_json::Value>,
+    ) -> anyhow::Result<()> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+
+        let query = format!("INSERT INTO {} FORMAT JSONEachRow", table);
+        let body = rows
+            .iter()
+            .map(|row| row.to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+        self.post(&[("database", database), ("query", &query)], body)
+            .await
+            .map_err(|err| anyhow!("insert {} rows to {} error: {}", rows.len(), table, err))?;
+        Ok(())
+    }
+}
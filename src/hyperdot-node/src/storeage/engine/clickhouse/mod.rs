@@ -0,0 +1,5 @@
+mod clickhouse;
+mod client;
+mod writer;
+
+pub use clickhouse::ClickHouseEngine;
@@ -0,0 +1,436 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use anyhow::anyhow;
+use serde_json::json;
+
+use super::client::ClickHouseClient;
+use crate::types::block::polkadot_chain;
+
+/// The ReplacingMergeTree tables of the substrate schema, partitioned by
+/// the range of `{partition_blocks}` blocks. The bytes are stored as hex
+/// strings and the json values as text. A row replaces the rows of the
+/// same key with a lower `version`, and the rows marked `is_deleted` are
+/// removed, so the tables are read with `FINAL`. Requires clickhouse 23.2
+/// or later.
+const SUBSTRATE_SCHEMA: [&str; 4] = [
+    r#"
+CREATE TABLE IF NOT EXISTS blocks (
+    number UInt64,
+    timestamp UInt64,
+    hash String,
+    parent_hash String,
+    extrinsics_root String,
+    state_root String,
+    is_finalized Bool,
+    validator Nullable(String),
+    spec_version UInt32,
+    version UInt64,
+    is_deleted UInt8
+) ENGINE = ReplacingMergeTree(version, is_deleted)
+PARTITION BY intDiv(number, {partition_blocks})
+ORDER BY number
+"#,
+    r#"
+CREATE TABLE IF NOT EXISTS extrinsics (
+    id String,
+    block_number UInt64,
+    block_timestamp UInt64,
+    extrinsic_hash String,
+    is_signed Bool,
+    mod_name LowCardinality(String),
+    call_name LowCardinality(String),
+    result Bool,
+    call_params Nullable(String),
+    version UInt64,
+    is_deleted UInt8
+) ENGINE = ReplacingMergeTree(version, is_deleted)
+PARTITION BY intDiv(block_number, {partition_blocks})
+ORDER BY (block_number, id)
+"#,
+    r#"
+CREATE TABLE IF NOT EXISTS events (
+    id String,
+    block_number UInt64,
+    block_timestamp UInt64,
+    extrinsic_index UInt32,
+    extrinsic_id String,
+    mod_name LowCardinality(String),
+    event_name LowCardinality(String),
+    event_index UInt32,
+    phase UInt16,
+    extrinsic_hash String,
+    values Nullable(String),
+    version UInt64,
+    is_deleted UInt8
+) ENGINE = ReplacingMergeTree(version, is_deleted)
+PARTITION BY intDiv(block_number, {partition_blocks})
+ORDER BY (block_number, id)
+"#,
+    r#"
+CREATE TABLE IF NOT EXISTS block_logs (
+    id String,
+    block_number UInt64,
+    type String,
+    data Nullable(String),
+    engine Nullable(String),
+    version UInt64,
+    is_deleted UInt8
+) ENGINE = ReplacingMergeTree(version, is_deleted)
+PARTITION BY intDiv(block_number, {partition_blocks})
+ORDER BY (block_number, id)
+"#,
+];
+
+/// The tables of block rows, `blocks` is the last one to be inserted, so a
+/// block is stored only if its rows are all written.
+const CHILD_TABLES: [&str; 3] = ["block_logs", "extrinsics", "events"];
+
+/// The bit of the row version set for the finalized blocks, so a finalized
+/// row always replaces the unfinalized one, whenever they are written.
+const FINALIZED_VERSION: u64 = 1 << 63;
+
+pub(crate) fn substrate_schema(partition_blocks: u64) -> Vec<String> {
+    SUBSTRATE_SCHEMA
+        .iter()
+        .map(|stmt| stmt.replace("{partition_blocks}", &partition_blocks.to_string()))
+        .collect()
+}
+
+fn hex_string(bytes: &[u8]) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
+
+/// The key of the row in its table, the block number of `blocks`.
+fn row_key(row: &serde_json::Value) -> String {
+    match row["id"].as_str() {
+        Some(id) => id.to_string(),
+        None => row["number"].to_string(),
+    }
+}
+
+pub(crate) struct SubstrateWriter;
+
+impl SubstrateWriter {
+    /// The version of the rows written at `now` microseconds.
+    fn version(is_finalized: bool, now: u64) -> u64 {
+        match is_finalized {
+            true => FINALIZED_VERSION | now,
+            false => now & !FINALIZED_VERSION,
+        }
+    }
+
+    /// The condition of the block numbers whose rows are replaced by the
+    /// batch. The rows of the blocks in batch are replaced, and the
+    /// unfinalized blocks from the first unfinalized block in batch are
+    /// rolled back, the same as the postgres writer.
+    fn stale_condition(blocks: &[polkadot_chain::Block]) -> String {
+        let numbers = blocks
+            .iter()
+            .map(|block| block.header.block_number.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        match blocks
+            .iter()
+            .filter(|block| !block.header.is_finished)
+            .map(|block| block.header.block_number)
+            .min()
+        {
+            None => format!("IN ({})", numbers),
+            Some(start) => format!(
+                "IN ({}) OR {{column}} IN (SELECT number FROM blocks FINAL WHERE number >= {} AND NOT is_finalized)",
+                numbers, start
+            ),
+        }
+    }
+
+    /// The queries of the keys and block numbers of the stored rows which
+    /// could be replaced by the batch, for each table in `TSV` format.
+    pub(crate) fn stale_stmts(blocks: &[polkadot_chain::Block]) -> Vec<(&'static str, String)> {
+        let condition = Self::stale_condition(blocks);
+        let mut stmts = CHILD_TABLES
+            .iter()
+            .map(|table| {
+                (
+                    *table,
+                    format!(
+                        "SELECT id, block_number FROM {} FINAL WHERE block_number {} FORMAT TSV",
+                        table,
+                        condition.replace("{column}", "block_number")
+                    ),
+                )
+            })
+            .collect::<Vec<_>>();
+        stmts.push((
+            "blocks",
+            format!(
+                "SELECT toString(number), number FROM blocks FINAL WHERE number {} FORMAT TSV",
+                condition.replace("{column}", "number")
+            ),
+        ));
+        stmts
+    }
+
+    /// The rows marking the stale rows of table deleted, the stale rows
+    /// not replaced by the rows of batch. They have the version of their
+    /// block in batch, so the finalized rows are never deleted by an
+    /// unfinalized block, or the version of the rolled back unfinalized
+    /// blocks.
+    pub(crate) fn tombstone_rows(
+        table: &str,
+        stale: &str,
+        rows: &[serde_json::Value],
+        blocks: &[polkadot_chain::Block],
+        now: u64,
+    ) -> anyhow::Result<Vec<serde_json::Value>> {
+        let keys = rows.iter().map(row_key).collect::<HashSet<_>>();
+        let versions = blocks
+            .iter()
+            .map(|block| {
+                (
+                    block.header.block_number,
+                    Self::version(block.header.is_finished, now),
+                )
+            })
+            .collect::<HashMap<_, _>>();
+
+        let mut tombstones = vec![];
+        for line in stale.lines().filter(|line| !line.is_empty()) {
+            let (key, number) = line
+                .split_once('\t')
+                .and_then(|(key, number)| Some((key, number.parse::<u64>().ok()?)))
+                .ok_or(anyhow!("invalid stale row of {}: {}", table, line))?;
+            if keys.contains(key) {
+                continue;
+            }
+
+            let version = versions
+                .get(&number)
+                .copied()
+                .unwrap_or(Self::version(false, now));
+            tombstones.push(match table {
+                "blocks" => json!({ "number": number, "version": version, "is_deleted": 1 }),
+                _ => json!({
+                    "id": key,
+                    "block_number": number,
+                    "version": version,
+                    "is_deleted": 1,
+                }),
+            });
+        }
+        Ok(tombstones)
+    }
+
+    fn header_row(header: &polkadot_chain::Header, version: u64) -> serde_json::Value {
+        json!({
+            "number": header.block_number,
+            "timestamp": header.block_timestamp,
+            "hash": hex_string(&header.block_hash),
+            "parent_hash": hex_string(&header.parent_hash),
+            "extrinsics_root": hex_string(&header.extrinsics_root),
+            "state_root": hex_string(&header.state_root),
+            "is_finalized": header.is_finished,
+            "validator": header.validator.as_ref().map(|v| hex_string(v)),
+            "spec_version": header.spec_version,
+            "version": version,
+            "is_deleted": 0,
+        })
+    }
+
+    fn log_row(log: &polkadot_chain::Log, version: u64) -> serde_json::Value {
+        json!({
+            "id": log.id,
+            "block_number": log.block_number,
+            "type": log.r#type,
+            "data": log.data.as_ref().map(|v| hex_string(v)),
+            "engine": log.engine,
+            "version": version,
+            "is_deleted": 0,
+        })
+    }
+
+    fn extrinsic_row(ext: &polkadot_chain::Extrinsic, version: u64) -> serde_json::Value {
+        json!({
+            "id": ext.id,
+            "block_number": ext.block_number,
+            "block_timestamp": ext.block_timestamp,
+            "extrinsic_hash": hex_string(&ext.extrinsic_hash),
+            "is_signed": ext.signature.is_some(),
+            "mod_name": ext.mod_name,
+            "call_name": ext.call_name,
+            "result": ext.result,
+            "call_params": ext.call_params.as_ref().map(|v| v.to_string()),
+            "version": version,
+            "is_deleted": 0,
+        })
+    }
+
+    fn event_row(event: &polkadot_chain::Event, version: u64) -> serde_json::Value {
+        json!({
+            "id": event.id,
+            "block_number": event.block_number,
+            "block_timestamp": event.block_timestamp,
+            "extrinsic_index": event.extrinsic_index,
+            "extrinsic_id": event.extrinsic_id,
+            "mod_name": event.mod_name,
+            "event_name": event.event_name,
+            "event_index": event.event_index,
+            "phase": event.phase,
+            "extrinsic_hash": hex_string(&event.extrinsic_hash),
+            "values": event.values.as_ref().map(|v| v.to_string()),
+            "version": version,
+            "is_deleted": 0,
+        })
+    }
+
+    /// The rows of each table in the order to be inserted, written at
+    /// `now` microseconds.
+    pub(crate) fn rows(
+        blocks: &[polkadot_chain::Block],
+        now: u64,
+    ) -> Vec<(&'static str, Vec<serde_json::Value>)> {
+        let mut logs = vec![];
+        let mut extrinsics = vec![];
+        let mut events = vec![];
+        let mut headers = vec![];
+        for block in blocks.iter() {
+            let version = Self::version(block.header.is_finished, now);
+            logs.extend(
+                block
+                    .logs
+                    .iter()
+                    .flatten()
+                    .map(|log| Self::log_row(log, version)),
+            );
+            extrinsics.extend(
+                block
+                    .body
+                    .extrinsics
+                    .iter()
+                    .flatten()
+                    .map(|ext| Self::extrinsic_row(ext, version)),
+            );
+            events.extend(
+                block
+                    .body
+                    .events
+                    .iter()
+                    .flatten()
+                    .map(|event| Self::event_row(event, version)),
+            );
+            headers.push(Self::header_row(&block.header, version));
+        }
+        vec![
+            ("block_logs", logs),
+            ("extrinsics", extrinsics),
+            ("events", events),
+            ("blocks", headers),
+        ]
+    }
+
+    /// Write the batch of blocks with one insert per table. Clickhouse has
+    /// no transaction, the rows of the batch replace the stored rows by
+    /// their version and the stale rows are marked deleted, so the retry of
+    /// a partially written batch is idempotent.
+    pub(crate) async fn write_blocks(
+        client: &ClickHouseClient,
+        database: &str,
+        blocks: &[polkadot_chain::Block],
+    ) -> anyhow::Result<()> {
+        if blocks.is_empty() {
+            return Ok(());
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_micros() as u64);
+        let mut stale = HashMap::new();
+        for (table, stmt) in Self::stale_stmts(blocks) {
+            stale.insert(table, client.execute(Some(database), &stmt).await?);
+        }
+        for (table, mut rows) in Self::rows(blocks, now) {
+            let tombstones = Self::tombstone_rows(table, &stale[table], &rows, blocks, now)?;
+            rows.extend(tombstones);
+            client.insert(database, table, rows).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_blocks() -> Vec<polkadot_chain::Block> {
+        let mut blocks = vec![];
+        for number in 5..=7 {
+            let mut block = polkadot_chain::Block::default();
+            block.header.block_number = number;
+            block.header.is_finished = number < 6;
+            blocks.push(block);
+        }
+        blocks
+    }
+
+    #[test]
+    fn test_stale_stmts() {
+        let blocks = new_blocks();
+        let stmts = SubstrateWriter::stale_stmts(&blocks);
+        assert_eq!(stmts.len(), 4);
+        assert_eq!(stmts[0], (
+            "block_logs",
+            "SELECT id, block_number FROM block_logs FINAL WHERE block_number IN (5, 6, 7) OR block_number IN (SELECT number FROM blocks FINAL WHERE number >= 6 AND NOT is_finalized) FORMAT TSV".to_string()
+        ));
+        assert_eq!(
+            stmts[3].1,
+            "SELECT toString(number), number FROM blocks FINAL WHERE number IN (5, 6, 7) OR number IN (SELECT number FROM blocks FINAL WHERE number >= 6 AND NOT is_finalized) FORMAT TSV"
+        );
+
+        let stmts = SubstrateWriter::stale_stmts(&blocks[..1]);
+        assert_eq!(
+            stmts[2].1,
+            "SELECT id, block_number FROM events FINAL WHERE block_number IN (5) FORMAT TSV"
+        );
+    }
+
+    #[test]
+    fn test_tombstone_rows() {
+        let blocks = new_blocks();
+        let now = 1_000;
+        let rows = SubstrateWriter::rows(&blocks, now);
+        let (table, headers) = &rows[3];
+        assert_eq!(*table, "blocks");
+        assert_eq!(headers[0]["version"], json!(FINALIZED_VERSION | now));
+        assert_eq!(headers[1]["version"], json!(now));
+
+        // The stored blocks 5 to 7 are replaced, the rolled back 8 and 9
+        // are deleted.
+        let tombstones = SubstrateWriter::tombstone_rows(
+            "blocks",
+            "5\t5\n6\t6\n8\t8\n9\t9\n",
+            headers,
+            &blocks,
+            now,
+        )
+        .unwrap();
+        assert_eq!(tombstones, vec![
+            json!({ "number": 8, "version": now, "is_deleted": 1 }),
+            json!({ "number": 9, "version": now, "is_deleted": 1 }),
+        ]);
+
+        // The extra events of the stored forks are deleted, with the
+        // version of their block, the finalized one is never deleted by an
+        // unfinalized block.
+        let tombstones =
+            SubstrateWriter::tombstone_rows("events", "5-3\t5\n6-3\t6\n", &[], &blocks, now)
+                .unwrap();
+        assert_eq!(tombstones[0]["version"], json!(FINALIZED_VERSION | now));
+        assert_eq!(tombstones[1]["id"], json!("6-3"));
+        assert_eq!(tombstones[1]["version"], json!(now));
+        assert!(SubstrateWriter::version(true, 0) > SubstrateWriter::version(false, now));
+
+        assert!(SubstrateWriter::tombstone_rows("events", "6-3", &[], &blocks, now).is_err());
+    }
+}
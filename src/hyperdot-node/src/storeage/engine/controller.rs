@@ -1,34 +1,60 @@
-use std::any::Any;
 use std::collections::HashMap;
+use std::fmt;
 use std::sync::Arc;
 
 use anyhow::anyhow;
 use hyperdot_core::config::StorageConfig;
 use hyperdot_core::config::StorageNodeConfig;
-use hyperdot_core::types::ChainKind;
 use hyperdot_core::types::DataEngineInfo;
 use hyperdot_core::types::DataEngineKind;
+use hyperdot_core::types::PostgresRowsPage;
+use hyperdot_core::types::PostgresTableInfo;
+use hyperdot_core::types::QueryOptions;
 // use hyperdot_common_config::PublicChain;
 // use hyperdot_common_config::StorageConfig;
 // use hyperdot_common_config::StorageNodeConfig;
 use tokio::sync::RwLock;
 
+use super::clickhouse;
+#[cfg(feature = "duckdb")]
+use super::duck;
+use super::engine::ChainBlocks;
 use super::engine::DataEngine;
+use super::filesink;
+use super::influxdb;
 use super::pg;
+use super::sqlite;
 // use super::url::parse_storage_ops;
 use super::PgEngine;
+use crate::types::rpc::BlockGaps;
+use crate::types::rpc::BlockRange;
 use crate::types::rpc::WriteBlock;
 // use crate::types::BlockDescribe;
 
+/// The write request can never be written by the node, retrying it is
+/// useless, e.g. no data engine stores the chain.
+#[derive(Debug)]
+pub struct WriteBlockRejected(pub String);
+
+impl fmt::Display for WriteBlockRejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for WriteBlockRejected {}
+
 /// Data engione controller.
 pub struct Controller {
     pg_engine: Option<Arc<PgEngine>>,
+    sqlite_engine: Option<Arc<sqlite::SQLiteEngine>>,
     engines: RwLock<Vec<Arc<dyn DataEngine>>>,
 }
 
 impl Controller {
     pub async fn async_new(engines_info: Vec<DataEngineInfo>) -> anyhow::Result<Self> {
         let mut pg_engine = None;
+        let mut sqlite_engine = None;
         let mut dyn_engines = vec![];
         for engine_info in engines_info.iter() {
             match engine_info.kind {
@@ -41,11 +67,64 @@ impl Controller {
                         dyn_engines.push(engine);
                     }
                 },
+                #[cfg(feature = "duckdb")]
+                DataEngineKind::DuckDB => match engine_info.duckdb.as_ref() {
+                    None => return Err(anyhow!("duckdb data-engine config is none")),
+                    Some(duckdb_cfg) => {
+                        let engine = Arc::new(duck::DuckDBEngine::new(duckdb_cfg.clone()).await?);
+                        let engine: Arc<dyn DataEngine> = engine;
+                        dyn_engines.push(engine);
+                    }
+                },
+                #[cfg(not(feature = "duckdb"))]
+                DataEngineKind::DuckDB => {
+                    return Err(anyhow!(
+                        "duckdb data-engine not supported, build with feature duckdb"
+                    ))
+                }
+                DataEngineKind::ClickHouse => match engine_info.clickhouse.as_ref() {
+                    None => return Err(anyhow!("clickhouse data-engine config is none")),
+                    Some(clickhouse_cfg) => {
+                        let engine = Arc::new(
+                            clickhouse::ClickHouseEngine::new(clickhouse_cfg.clone()).await?,
+                        );
+                        let engine: Arc<dyn DataEngine> = engine;
+                        dyn_engines.push(engine);
+                    }
+                },
+                DataEngineKind::Parquet => match engine_info.parquet.as_ref() {
+                    None => return Err(anyhow!("parquet data-engine config is none")),
+                    Some(parquet_cfg) => {
+                        let engine =
+                            Arc::new(filesink::FileSinkEngine::new(parquet_cfg.clone()).await?);
+                        let engine: Arc<dyn DataEngine> = engine;
+                        dyn_engines.push(engine);
+                    }
+                },
+                DataEngineKind::SQLite => match engine_info.sqlite.as_ref() {
+                    None => return Err(anyhow!("sqlite data-engine config is none")),
+                    Some(sqlite_cfg) => {
+                        let engine = Arc::new(sqlite::SQLiteEngine::new(sqlite_cfg.clone()).await?);
+                        sqlite_engine = Some(engine.clone());
+                        let engine: Arc<dyn DataEngine> = engine;
+                        dyn_engines.push(engine);
+                    }
+                },
+                DataEngineKind::InfluxDB => match engine_info.influxdb.as_ref() {
+                    None => return Err(anyhow!("influxdb data-engine config is none")),
+                    Some(influxdb_cfg) => {
+                        let engine =
+                            Arc::new(influxdb::InfluxDBEngine::new(influxdb_cfg.clone()).await?);
+                        let engine: Arc<dyn DataEngine> = engine;
+                        dyn_engines.push(engine);
+                    }
+                },
             }
         }
 
         Ok(Self {
             pg_engine,
+            sqlite_engine,
             engines: RwLock::new(dyn_engines),
         })
     }
@@ -60,40 +139,96 @@ impl Controller {
         }
     }
 
-    pub async fn write_block(&self, mut req: WriteBlock) -> anyhow::Result<()> {
+    /// Find the missing blocks of the chain, by the postgres engine or the
+    /// sqlite engine if no postgres.
+    pub async fn block_gaps(&self, req: BlockGaps) -> anyhow::Result<Vec<BlockRange>> {
+        match (self.pg_engine.as_ref(), self.sqlite_engine.as_ref()) {
+            (Some(pg_engine), _) => pg_engine.block_gaps(&req.chain, req.start, req.end).await,
+            (None, Some(sqlite_engine)) => {
+                sqlite_engine
+                    .block_gaps(&req.chain, req.start, req.end)
+                    .await
+            }
+            (None, None) => Err(anyhow!("no data engine supports block gaps in controller")),
+        }
+    }
+
+    /// Get the engine of kind.
+    pub async fn get_engine(&self, kind: &DataEngineKind) -> anyhow::Result<Arc<dyn DataEngine>> {
+        let rl = self.engines.read().await;
+        rl.iter()
+            .find(|engine| engine.kind() == *kind)
+            .cloned()
+            .ok_or(anyhow!(
+                "{} data engine not found in controller",
+                kind.to_string()
+            ))
+    }
+
+    /// Run query sql with the bind parameters for a page of rows by the
+    /// engine of kind.
+    pub async fn query(
+        &self,
+        kind: &DataEngineKind,
+        chain: &str,
+        sql: &str,
+        options: &QueryOptions,
+    ) -> anyhow::Result<PostgresRowsPage> {
+        self.get_engine(kind)
+            .await?
+            .query_page(chain, sql, options)
+            .await
+    }
+
+    /// Get the tables schema of chain in the engine of kind.
+    pub async fn schema(
+        &self,
+        kind: &DataEngineKind,
+        chain: &str,
+    ) -> anyhow::Result<HashMap<String, Vec<PostgresTableInfo>>> {
+        self.get_engine(kind).await?.schema(chain).await
+    }
+
+    /// Checks all engines are available.
+    pub async fn health(&self) -> anyhow::Result<()> {
+        let engines = self.engines.read().await.clone();
+        for engine in engines.iter() {
+            engine
+                .health()
+                .await
+                .map_err(|err| anyhow!("engine({}): {}", engine.name(), err))?;
+        }
+        Ok(())
+    }
+
+    pub async fn write_block(&self, req: WriteBlock) -> anyhow::Result<()> {
         // TODO: filter block at here.
         let engines = {
             let rl = self.engines.read().await;
             rl.clone()
         };
 
-        /// One vec of blocks per datae ngine
-        let engines_num = engines.len();
-        let mut vblocks: Vec<Vec<Box<dyn Any + Send + Sync>>> = match req.chain_kind {
-            ChainKind::Ethereum => unimplemented!(),
-            ChainKind::Polkadot => {
-                if req.polkadot_blocks.is_none() {
-                    return Err(anyhow::anyhow!("polkadot chain polkadot block not found"));
-                }
+        let chain = req.chain.clone();
+        let blocks =
+            ChainBlocks::try_from(req).map_err(|err| WriteBlockRejected(err.to_string()))?;
+        let kind = blocks.kind();
 
-                let mut vblocks = vec![];
-                let blocks = req.polkadot_blocks.take().unwrap();
-                for _ in 0..engines_num {
-                    let mut bs: Vec<Box<dyn Any + Send + Sync>> = vec![];
-                    for block in blocks.iter() {
-                        bs.push(Box::new(block.clone()))
-                    }
-                    vblocks.push(bs)
-                }
-                vblocks
+        // Write to all engines even if some failed, and return the error
+        // so that the speaker retries the batch.
+        let mut result = Ok(());
+        let mut written = 0;
+        for engine in engines.iter() {
+            if !engine.supports_chain(&chain, &kind).await {
+                continue;
             }
-        };
 
-        for (i, engine) in engines.iter().enumerate() {
-            let blocks = vblocks.swap_remove(i);
-            match engine.write_block(req.chain.clone(), blocks).await {
+            written += 1;
+            match engine.write_block(&chain, &blocks).await {
                 Err(err) => {
                     tracing::error!("🍼 engine({}) write_block error: {}", engine.name(), err);
+                    if result.is_ok() {
+                        result = Err(anyhow::anyhow!("engine({}): {}", engine.name(), err));
+                    }
                     continue;
                 }
                 Ok(_) => {
@@ -102,6 +237,14 @@ impl Controller {
             }
         }
 
-        Ok(())
+        if written == 0 {
+            return Err(WriteBlockRejected(format!(
+                "{}: no data engine supports the chain",
+                chain
+            ))
+            .into());
+        }
+
+        result
     }
 }
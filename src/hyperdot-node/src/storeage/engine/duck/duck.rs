@@ -0,0 +1,510 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use anyhow::anyhow;
+use duckdb::arrow::datatypes::DataType;
+use duckdb::types::ValueRef;
+use duckdb::Config;
+use duckdb::Connection;
+use hyperdot_core::types::ChainKind;
+use hyperdot_core::types::DataEngineKind;
+use hyperdot_core::types::DuckDBDataEngine;
+use hyperdot_core::types::PostgresColumnDataType;
+use hyperdot_core::types::PostgresRows;
+use hyperdot_core::types::PostgresRowsPage;
+use hyperdot_core::types::PostgresTableInfo;
+use hyperdot_core::types::QueryOptions;
+
+use super::super::engine::ChainBlocks;
+use super::super::engine::DataEngine;
+use super::super::sandbox::PageQuery;
+use super::super::sandbox::QueryLimits;
+use super::writer::SubstrateWriter;
+use super::writer::SUBSTRATE_SCHEMA;
+
+const SCHEMA_COLUMNS_STMT: &str = r#"
+SELECT table_name, column_name, data_type FROM information_schema.columns
+WHERE table_schema = 'main'
+ORDER BY table_name, ordinal_position
+"#;
+
+const DEFAULT_QUERY_STATEMENTS: [&str; 8] = [
+    "SELECT",
+    "WITH",
+    "VALUES",
+    "FROM",
+    "EXPLAIN",
+    "DESCRIBE",
+    "SHOW",
+    "SUMMARIZE",
+];
+
+/// The statements paged and counted as a subquery.
+const PAGED_STATEMENTS: [&str; 4] = ["SELECT", "WITH", "VALUES", "FROM"];
+
+/// The embedded duckdb engine, a database per chain. The connection is
+/// synchronous, so it's used in blocking threads.
+///
+/// The databases are opened without the access to external files and
+/// with the configuration locked. The ad-hoc queries run on their own
+/// connections in read-only transactions, and are interrupted on timeout.
+pub struct DuckDBEngine {
+    connections: HashMap<String, Arc<Mutex<Connection>>>,
+    /// The connections cloned for the ad-hoc queries, one per query.
+    readers: HashMap<String, Arc<Mutex<Connection>>>,
+    limits: QueryLimits,
+}
+
+impl DuckDBEngine {
+    pub async fn new(engine: DuckDBDataEngine) -> anyhow::Result<Self> {
+        let mut connections = HashMap::new();
+        let mut readers = HashMap::new();
+        for support_chain in engine.support_chains.iter() {
+            if !support_chain.enabled {
+                tracing::info!(
+                    "💁 {}: skipped not enabled for duckdb data engine",
+                    support_chain.name
+                );
+                continue;
+            }
+
+            let path = support_chain.path.clone();
+            let (conn, reader) = tokio::task::spawn_blocking(move || {
+                let config = Config::default()
+                    .enable_external_access(false)?
+                    .enable_autoload_extension(false)?
+                    .with("lock_configuration", "true")?;
+                let conn = Connection::open_with_flags(&path, config)?;
+                conn.execute_batch(SUBSTRATE_SCHEMA)?;
+                let reader = conn.try_clone()?;
+                anyhow::Ok((conn, reader))
+            })
+            .await?
+            .map_err(|err| {
+                anyhow!(
+                    "{}: open duckdb({}) error: {}",
+                    support_chain.name,
+                    support_chain.path,
+                    err
+                )
+            })?;
+
+            tracing::info!(
+                "🦆 {}: duckdb data engine opened at {}",
+                support_chain.name,
+                support_chain.path,
+            );
+            connections.insert(support_chain.name.clone(), Arc::new(Mutex::new(conn)));
+            readers.insert(support_chain.name.clone(), Arc::new(Mutex::new(reader)));
+        }
+
+        Ok(Self {
+            connections,
+            readers,
+            limits: QueryLimits::new(
+                engine.query_timeout_ms,
+                engine.query_max_rows,
+                engine.query_statements.as_ref(),
+                &DEFAULT_QUERY_STATEMENTS,
+            ),
+        })
+    }
+
+    /// Run the function with the connection of chain in a blocking thread.
+    async fn with_conn<T, F>(&self, chain: &str, f: F) -> anyhow::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> anyhow::Result<T> + Send + 'static,
+    {
+        let conn = self
+            .connections
+            .get(chain)
+            .cloned()
+            .ok_or(anyhow!("DuckDB not support chain({})", chain))?;
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn.lock().unwrap();
+            f(&mut conn)
+        })
+        .await?
+    }
+
+    /// Run the function with a new connection of chain in a read-only
+    /// transaction in a blocking thread, it's interrupted on timeout.
+    async fn with_reader<T, F>(&self, chain: &str, f: F) -> anyhow::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> anyhow::Result<T> + Send + 'static,
+    {
+        let reader = self
+            .readers
+            .get(chain)
+            .ok_or(anyhow!("DuckDB not support chain({})", chain))?;
+        let conn = reader.lock().unwrap().try_clone()?;
+        let interrupt = conn.interrupt_handle();
+        let mut task = tokio::task::spawn_blocking(move || {
+            conn.execute_batch("BEGIN TRANSACTION READ ONLY")?;
+            let result = f(&conn);
+            let _ = conn.execute_batch("ROLLBACK");
+            result
+        });
+        match tokio::time::timeout(self.limits.timeout, &mut task).await {
+            Ok(result) => result?,
+            Err(_) => {
+                interrupt.interrupt();
+                let _ = task.await;
+                Err(anyhow!(
+                    "query canceled after {}ms",
+                    self.limits.timeout.as_millis()
+                ))
+            }
+        }
+    }
+}
+
+fn column_type(data_type: &DataType) -> PostgresColumnDataType {
+    match data_type {
+        DataType::Boolean => PostgresColumnDataType::BOOL,
+        DataType::Int8 | DataType::Int16 | DataType::UInt8 => PostgresColumnDataType::SMALLINT,
+        DataType::Int32 | DataType::UInt16 => PostgresColumnDataType::INT,
+        DataType::Int64 | DataType::UInt32 => PostgresColumnDataType::BIGINT,
+        DataType::UInt64 | DataType::Decimal128(..) | DataType::Decimal256(..) => {
+            PostgresColumnDataType::NUMERIC
+        }
+        DataType::Float32 => PostgresColumnDataType::FLOAT4,
+        DataType::Float64 => PostgresColumnDataType::Float8,
+        DataType::Utf8 | DataType::LargeUtf8 => PostgresColumnDataType::TEXT,
+        DataType::Binary | DataType::LargeBinary => PostgresColumnDataType::BYTEA,
+        _ => PostgresColumnDataType::Invalid,
+    }
+}
+
+/// Convert the value to json in the same way as postgres rows.
+fn json_value(value: ValueRef<'_>) -> serde_json::Value {
+    use serde_json::Value;
+    match value {
+        ValueRef::Null => Value::Null,
+        ValueRef::Boolean(v) => Value::Bool(v),
+        ValueRef::TinyInt(v) => Value::from(v),
+        ValueRef::SmallInt(v) => Value::from(v),
+        ValueRef::Int(v) => Value::from(v),
+        ValueRef::BigInt(v) => Value::from(v),
+        ValueRef::UTinyInt(v) => Value::from(v),
+        ValueRef::USmallInt(v) => Value::from(v),
+        ValueRef::UInt(v) => Value::from(v),
+        ValueRef::UBigInt(v) => Value::String(v.to_string()),
+        ValueRef::HugeInt(v) => Value::String(v.to_string()),
+        ValueRef::Decimal(v) => Value::String(v.to_string()),
+        ValueRef::Float(v) => Value::from(v),
+        ValueRef::Double(v) => Value::from(v),
+        ValueRef::Text(v) => Value::String(String::from_utf8_lossy(v).to_string()),
+        ValueRef::Blob(v) => Value::Array(v.iter().map(|b| Value::from(*b)).collect()),
+        other => Value::String(format!("{:?}", other)),
+    }
+}
+
+/// Query at most `max_rows` rows, the rest are dropped.
+fn query_rows(conn: &Connection, sql: &str, max_rows: usize) -> anyhow::Result<PostgresRows> {
+    let mut stmt = conn.prepare(sql)?;
+    let mut values = vec![];
+    {
+        let mut rows = stmt.query([])?;
+        while values.len() < max_rows {
+            let row = match rows.next()? {
+                None => break,
+                Some(row) => row,
+            };
+            let column_count = row.as_ref().column_count();
+            let mut row_values = Vec::with_capacity(column_count);
+            for i in 0..column_count {
+                row_values.push(json_value(row.get_ref(i)?));
+            }
+            values.push(row_values);
+        }
+    }
+
+    let columns = stmt.column_names();
+    let column_types = (0..columns.len())
+        .map(|i| column_type(&stmt.column_type(i)))
+        .collect();
+    let rows = values
+        .into_iter()
+        .map(|row_values| columns.iter().cloned().zip(row_values).collect())
+        .collect::<Vec<serde_json::Map<_, _>>>();
+    Ok(PostgresRows {
+        columns,
+        len: rows.len(),
+        column_types,
+        rows,
+    })
+}
+
+#[async_trait::async_trait]
+impl DataEngine for DuckDBEngine {
+    fn name(&self) -> String {
+        "DuckDB".to_string()
+    }
+
+    fn kind(&self) -> DataEngineKind {
+        DataEngineKind::DuckDB
+    }
+
+    async fn supports_chain(&self, chain: &str, kind: &ChainKind) -> bool {
+        matches!(kind, ChainKind::Polkadot) && self.connections.contains_key(chain)
+    }
+
+    /// Write the batch of blocks for chain in one transaction.
+    async fn write_block(&self, chain: &str, blocks: &ChainBlocks) -> anyhow::Result<()> {
+        let blocks = blocks.clone();
+        self.with_conn(chain, move |conn| match &blocks {
+            ChainBlocks::Polkadot(blocks) => SubstrateWriter::write_blocks(conn, blocks),
+        })
+        .await
+        .map_err(|err| anyhow!("{}: write blocks error: {}", chain, err))
+    }
+
+    async fn schema(&self, chain: &str) -> anyhow::Result<HashMap<String, Vec<PostgresTableInfo>>> {
+        self.with_conn(chain, |conn| {
+            let mut stmt = conn.prepare(SCHEMA_COLUMNS_STMT)?;
+            let mut rows = stmt.query([])?;
+            let mut tables: HashMap<String, Vec<PostgresTableInfo>> = HashMap::new();
+            while let Some(row) = rows.next()? {
+                tables
+                    .entry(row.get(0)?)
+                    .or_default()
+                    .push(PostgresTableInfo {
+                        column_name: row.get(1)?,
+                        data_type: row.get(2)?,
+                    });
+            }
+            Ok(tables)
+        })
+        .await
+    }
+
+    async fn health(&self) -> anyhow::Result<()> {
+        for chain in self.connections.keys() {
+            self.with_conn(chain, |conn| {
+                conn.execute_batch("SELECT 1")?;
+                Ok(())
+            })
+            .await
+            .map_err(|err| anyhow!("{}: duckdb not available: {}", chain, err))?;
+        }
+        Ok(())
+    }
+
+    async fn query(&self, chain: &str, sql: &str) -> anyhow::Result<PostgresRows> {
+        let sql = sql.to_string();
+        let max_rows = self.limits.max_rows;
+        async {
+            self.limits.check(&sql)?;
+            self.with_reader(chain, move |conn| query_rows(conn, &sql, max_rows))
+                .await
+        }
+        .await
+        .map_err(|err| {
+            anyhow!(
+                "DuckDB data engine run sql for chain({}) error:{}",
+                chain,
+                err
+            )
+        })
+    }
+
+    async fn query_page(
+        &self,
+        chain: &str,
+        sql: &str,
+        options: &QueryOptions,
+    ) -> anyhow::Result<PostgresRowsPage> {
+        if !options.params.is_empty() {
+            return Err(anyhow!(
+                "{}: {} data engine not support query parameters",
+                chain,
+                self.name()
+            ));
+        }
+
+        async {
+            let keyword = self.limits.check(sql)?;
+            let query = PageQuery::new(
+                &keyword,
+                sql,
+                options,
+                self.limits.max_rows,
+                &PAGED_STATEMENTS,
+            );
+            self.with_reader(chain, move |conn| {
+                let rows = query_rows(conn, &query.sql, query.fetch_rows())?;
+                let total = match query.count.as_ref() {
+                    None => None,
+                    Some(count) => Some(conn.query_row(count, [], |row| row.get::<_, i64>(0))?),
+                };
+                Ok(query.page(rows, total.map(|total| total as usize)))
+            })
+            .await
+        }
+        .await
+        .map_err(|err| {
+            anyhow!(
+                "DuckDB data engine run sql for chain({}) error:{}",
+                chain,
+                err
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyperdot_core::types::DuckDBDataEngineForChain;
+
+    use super::*;
+    use crate::storeage::engine::testing::new_block;
+
+    #[tokio::test]
+    async fn test_duckdb_write_and_query() {
+        let engine = DuckDBEngine::new(DuckDBDataEngine {
+            support_chains: vec![DuckDBDataEngineForChain {
+                name: "polkadot".to_string(),
+                path: ":memory:".to_string(),
+                enabled: true,
+            }],
+            query_timeout_ms: Some(500),
+            query_max_rows: Some(2),
+            query_statements: None,
+        })
+        .await
+        .unwrap();
+        assert!(
+            engine
+                .supports_chain("polkadot", &ChainKind::Polkadot)
+                .await
+        );
+        assert!(!engine.supports_chain("kusama", &ChainKind::Polkadot).await);
+        engine.health().await.unwrap();
+
+        let blocks = ChainBlocks::Polkadot((1..=3).map(|number| new_block(number, true)).collect());
+        engine.write_block("polkadot", &blocks).await.unwrap();
+        // Written again is idempotent.
+        engine.write_block("polkadot", &blocks).await.unwrap();
+
+        let rows = engine
+            .query(
+                "polkadot",
+                r#"SELECT b."number", b.hash_bytes, count(e.id) AS events FROM blocks b
+                JOIN events e ON e.block_number = b."number"
+                GROUP BY b."number", b.hash_bytes ORDER BY b."number""#,
+            )
+            .await
+            .unwrap();
+        // The rows over the max are dropped.
+        assert_eq!(rows.len, 2);
+        assert_eq!(rows.columns, vec!["number", "hash_bytes", "events"]);
+        assert_eq!(rows.rows[1]["number"], serde_json::json!(2));
+        assert_eq!(
+            rows.rows[1]["hash_bytes"],
+            serde_json::json!(vec![2_u8; 32])
+        );
+        assert_eq!(rows.rows[1]["events"], serde_json::json!(1));
+
+        let tables = engine.schema("polkadot").await.unwrap();
+        assert_eq!(tables.len(), 4);
+        assert!(tables["blocks"]
+            .iter()
+            .any(|column| column.column_name == "spec_version"));
+        assert!(engine.query("kusama", "SELECT 1").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_duckdb_query_sandbox() {
+        let dir = std::env::temp_dir().join(format!("hyperdot-duckdb-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let engine = DuckDBEngine::new(DuckDBDataEngine {
+            support_chains: vec![DuckDBDataEngineForChain {
+                name: "polkadot".to_string(),
+                path: dir.join("polkadot.db").to_string_lossy().to_string(),
+                enabled: true,
+            }],
+            query_timeout_ms: Some(200),
+            query_max_rows: None,
+            query_statements: Some(vec!["SELECT".to_string(), "DELETE".to_string()]),
+        })
+        .await
+        .unwrap();
+
+        // Not allowed statements.
+        assert!(engine.query("polkadot", "DROP TABLE blocks").await.is_err());
+        assert!(engine
+            .query("polkadot", "COPY blocks TO '/tmp/blocks.csv'")
+            .await
+            .is_err());
+        // Allowed but read-only, without external access.
+        assert!(engine
+            .query("polkadot", "DELETE FROM blocks")
+            .await
+            .is_err());
+        assert!(engine
+            .query("polkadot", "SELECT * FROM read_csv_auto('/etc/passwd')")
+            .await
+            .is_err());
+        assert!(engine
+            .query("polkadot", "SELECT 1; DROP TABLE blocks")
+            .await
+            .is_err());
+        let err = engine
+            .query(
+                "polkadot",
+                "SELECT count(*) FROM range(1000000000000) a, range(1000) b",
+            )
+            .await
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("canceled"), "{}", err);
+
+        let rows = engine
+            .query("polkadot", "SELECT count(*) AS n FROM blocks")
+            .await
+            .unwrap();
+        assert_eq!(rows.rows[0]["n"], serde_json::json!(0));
+
+        let page = engine
+            .query_page(
+                "polkadot",
+                "SELECT range AS n FROM range(10);",
+                &QueryOptions {
+                    limit: Some(3),
+                    offset: 2,
+                    with_total: true,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(page.rows.len, 3);
+        assert_eq!(page.rows.rows[0]["n"], serde_json::json!(2));
+        assert_eq!(page.total, Some(10));
+        assert!(page.has_more);
+        let page = engine
+            .query_page("polkadot", "SELECT * FROM range(10)", &QueryOptions {
+                limit: Some(3),
+                offset: 8,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(page.rows.len, 2);
+        assert_eq!(page.total, None);
+        assert!(!page.has_more);
+        assert!(engine
+            .query_page("polkadot", "SELECT 1", &QueryOptions {
+                params: vec![serde_json::json!(1)],
+                ..Default::default()
+            })
+            .await
+            .is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
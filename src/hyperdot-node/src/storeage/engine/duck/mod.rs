@@ -0,0 +1,4 @@
+mod duck;
+mod writer;
+
+pub use duck::DuckDBEngine;
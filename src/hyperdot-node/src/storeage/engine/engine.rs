@@ -1,12 +1,194 @@
-use std::any::Any;
+use std::collections::HashMap;
+
+use hyperdot_core::types::ChainKind;
+use hyperdot_core::types::DataEngineKind;
+use hyperdot_core::types::PostgresRows;
+use hyperdot_core::types::PostgresRowsPage;
+use hyperdot_core::types::PostgresTableInfo;
+use hyperdot_core::types::QueryOptions;
+
+use crate::types::block::polkadot_chain;
+use crate::types::rpc::WriteBlock;
+
+/// The blocks of one chain written to the data engines, typed by the
+/// kind of chain.
+#[derive(Debug, Clone)]
+pub enum ChainBlocks {
+    Polkadot(Vec<polkadot_chain::Block>),
+}
+
+impl ChainBlocks {
+    pub fn kind(&self) -> ChainKind {
+        match self {
+            Self::Polkadot(_) => ChainKind::Polkadot,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Polkadot(blocks) => blocks.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn block_numbers(&self) -> Vec<u64> {
+        match self {
+            Self::Polkadot(blocks) => blocks
+                .iter()
+                .map(|block| block.header.block_number)
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<WriteBlock> for ChainBlocks {
+    type Error = anyhow::Error;
+
+    fn try_from(req: WriteBlock) -> Result<Self, Self::Error> {
+        match req.chain_kind {
+            ChainKind::Polkadot => req
+                .polkadot_blocks
+                .map(Self::Polkadot)
+                .ok_or(anyhow::anyhow!("{}: polkadot blocks not found", req.chain)),
+            ChainKind::Ethereum => Err(anyhow::anyhow!(
+                "{}: ethereum blocks not supported",
+                req.chain
+            )),
+        }
+    }
+}
 
 #[async_trait::async_trait]
 pub trait DataEngine: Send + Sync {
     fn name(&self) -> String;
 
-    async fn write_block(
+    fn kind(&self) -> DataEngineKind;
+
+    /// Checks if the blocks of chain are stored by the engine.
+    async fn supports_chain(&self, chain: &str, kind: &ChainKind) -> bool;
+
+    /// Write the batch of blocks for chain.
+    async fn write_block(&self, chain: &str, blocks: &ChainBlocks) -> anyhow::Result<()>;
+
+    /// Get the columns of each table for chain.
+    async fn schema(&self, chain: &str) -> anyhow::Result<HashMap<String, Vec<PostgresTableInfo>>>;
+
+    /// Checks the connections of the engine are available.
+    async fn health(&self) -> anyhow::Result<()>;
+
+    /// Run query sql for chain.
+    async fn query(&self, chain: &str, sql: &str) -> anyhow::Result<PostgresRows>;
+
+    /// Run query sql with the bind parameters for a page of rows. The
+    /// default is a fallback without bind parameters, it pages the rows of
+    /// `query`, which are capped by the engine, so the total is at most the
+    /// cap. The engines able to page and count in the query override it.
+    async fn query_page(
         &self,
-        chain: String,
-        blocks: Vec<Box<dyn Any + Send + Sync>>,
-    ) -> anyhow::Result<()>;
+        chain: &str,
+        sql: &str,
+        options: &QueryOptions,
+    ) -> anyhow::Result<PostgresRowsPage> {
+        if !options.params.is_empty() {
+            return Err(anyhow::anyhow!(
+                "{}: {} data engine not support query parameters",
+                chain,
+                self.name()
+            ));
+        }
+        Ok(paginate(self.query(chain, sql).await?, options))
+    }
+}
+
+/// Cut the page of options from all the rows of a query.
+pub(crate) fn paginate(mut rows: PostgresRows, options: &QueryOptions) -> PostgresRowsPage {
+    let total = rows.rows.len();
+    let offset = options.offset.min(total);
+    let limit = options.limit.unwrap_or(total);
+    let has_more = total - offset > limit;
+    rows.rows = rows.rows.into_iter().skip(offset).take(limit).collect();
+    rows.len = rows.rows.len();
+    PostgresRowsPage {
+        rows,
+        total: options.with_total.then_some(total),
+        has_more,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chain_blocks_from_write_block() {
+        let mut block = polkadot_chain::Block::default();
+        block.header.block_number = 3;
+        let blocks = ChainBlocks::try_from(WriteBlock {
+            chain: "polkadot".to_string(),
+            chain_kind: ChainKind::Polkadot,
+            polkadot_blocks: Some(vec![block]),
+        })
+        .unwrap();
+        assert!(matches!(blocks.kind(), ChainKind::Polkadot));
+        assert_eq!(blocks.block_numbers(), vec![3]);
+
+        assert!(ChainBlocks::try_from(WriteBlock {
+            chain: "polkadot".to_string(),
+            chain_kind: ChainKind::Polkadot,
+            polkadot_blocks: None,
+        })
+        .is_err());
+        assert!(ChainBlocks::try_from(WriteBlock {
+            chain: "ethereum".to_string(),
+            chain_kind: ChainKind::Ethereum,
+            polkadot_blocks: None,
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_paginate() {
+        let rows = PostgresRows {
+            columns: vec!["n".to_string()],
+            len: 5,
+            column_types: vec![],
+            rows: (0..5)
+                .map(|n| {
+                    let mut row = serde_json::Map::new();
+                    row.insert("n".to_string(), n.into());
+                    row
+                })
+                .collect(),
+        };
+
+        let page = paginate(rows.clone(), &QueryOptions {
+            limit: Some(2),
+            offset: 1,
+            with_total: true,
+            ..Default::default()
+        });
+        assert_eq!(page.rows.len, 2);
+        assert_eq!(page.rows.rows[0]["n"], 1);
+        assert_eq!(page.total, Some(5));
+        assert!(page.has_more);
+
+        let page = paginate(rows.clone(), &QueryOptions {
+            limit: Some(2),
+            offset: 3,
+            ..Default::default()
+        });
+        assert_eq!(page.rows.len, 2);
+        assert_eq!(page.total, None);
+        assert!(!page.has_more);
+
+        let page = paginate(rows, &QueryOptions {
+            offset: 10,
+            ..Default::default()
+        });
+        assert_eq!(page.rows.len, 0);
+        assert!(!page.has_more);
+    }
 }
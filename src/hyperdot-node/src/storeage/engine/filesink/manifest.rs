@@ -0,0 +1,74 @@
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::anyhow;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::types::rpc::BlockRange;
+
+/// The manifest file in the directory of chain, the underscore prefix
+/// makes it skipped by the readers of partitions.
+pub(crate) const MANIFEST_FILE: &str = "_manifest.json";
+
+/// A committed file, readable once it's in the manifest.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestFile {
+    pub table: String,
+    /// The partition directory, e.g. `date=2023-06-01`.
+    pub partition: String,
+    /// The path of file relative to the directory of chain.
+    pub path: String,
+    pub rows: usize,
+    pub min_block_number: u64,
+    pub max_block_number: u64,
+    /// The unix seconds of the file committed.
+    pub committed_at: u64,
+}
+
+/// The committed files of a chain. Downstream jobs pick up the new files
+/// and partitions from the manifest instead of listing the directories,
+/// so the files being written are never read.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    /// The sequence of the next file.
+    pub next_part: u64,
+    pub files: Vec<ManifestFile>,
+    /// The merged ranges of blocks in the committed files, a block written
+    /// again is skipped.
+    #[serde(default)]
+    pub blocks: Vec<BlockRange>,
+}
+
+impl Manifest {
+    /// Load the manifest of the chain directory, empty if not exists.
+    pub fn load(dir: &Path) -> anyhow::Result<Self> {
+        let path = dir.join(MANIFEST_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let data = std::fs::read(&path)?;
+        serde_json::from_slice(&data)
+            .map_err(|err| anyhow!("invalid manifest {}: {}", path.display(), err))
+    }
+
+    /// Checks if the block is in the committed files.
+    pub fn contains(&self, block_number: u64) -> bool {
+        self.blocks
+            .iter()
+            .any(|range| range.start <= block_number && block_number <= range.end)
+    }
+
+    /// Save the manifest to a temporary file and rename it, so the
+    /// readers see either the previous or the new manifest.
+    pub fn save(&self, dir: &Path) -> anyhow::Result<()> {
+        let path = dir.join(MANIFEST_FILE);
+        let tmp_path = dir.join(format!("{}.tmp", MANIFEST_FILE));
+        let mut file = std::fs::File::create(&tmp_path)?;
+        file.write_all(&serde_json::to_vec_pretty(self)?)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, &path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,5 @@
+mod manifest;
+mod sink;
+mod tables;
+
+pub use sink::FileSinkEngine;
@@ -0,0 +1,651 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::Weak;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+use anyhow::anyhow;
+use hyperdot_core::types::ChainKind;
+use hyperdot_core::types::DataEngineKind;
+use hyperdot_core::types::ParquetDataEngine;
+use hyperdot_core::types::PostgresRows;
+use hyperdot_core::types::PostgresTableInfo;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+
+use super::super::engine::ChainBlocks;
+use super::super::engine::DataEngine;
+use super::manifest::Manifest;
+use super::manifest::ManifestFile;
+use super::tables::record_batches;
+use super::tables::table_schema;
+use super::tables::TABLES;
+use crate::types::block::polkadot_chain;
+use crate::types::rpc::BlockRange;
+
+const DEFAULT_MAX_FILE_ROWS: usize = 1_000_000;
+const DEFAULT_MAX_FILE_SECS: u64 = 3600;
+/// The max interval to check the files opened for too long.
+const ROLL_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+const IN_PROGRESS_SUFFIX: &str = ".inprogress";
+/// The log of blocks in the files being written, in json lines. The
+/// underscore prefix makes it skipped by the readers of partitions.
+const PENDING_LOG_FILE: &str = "_pending.jsonl";
+
+/// The partition of the block timestamp in milliseconds.
+fn date_partition(block_timestamp: u64) -> String {
+    let date = chrono::DateTime::from_timestamp_millis(block_timestamp as i64).unwrap_or_default();
+    format!("date={}", date.format("%Y-%m-%d"))
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Read the blocks of the pending log. The last line is skipped if it's
+/// torn, the block was never acknowledged as its write didn't complete.
+fn read_pending_log(path: &Path) -> anyhow::Result<Vec<polkadot_chain::Block>> {
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+
+    let lines = BufReader::new(File::open(path)?)
+        .lines()
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut blocks = vec![];
+    for (i, line) in lines.iter().enumerate() {
+        match serde_json::from_str(line) {
+            Ok(block) => blocks.push(block),
+            Err(_) if i + 1 == lines.len() => {
+                tracing::warn!("🗑️ {}: skipped torn last block", path.display())
+            }
+            Err(err) => return Err(anyhow!("invalid block of {}: {}", path.display(), err)),
+        }
+    }
+    Ok(blocks)
+}
+
+/// Remove the files not committed before the node stopped.
+fn remove_in_progress(dir: &Path) -> anyhow::Result<usize> {
+    let mut removed = 0;
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            removed += remove_in_progress(&path)?;
+        } else if path.to_string_lossy().ends_with(IN_PROGRESS_SUFFIX) {
+            std::fs::remove_file(&path)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// The file being written, hidden by the dot prefix and in progress
+/// suffix until it's committed.
+struct OpenFile {
+    path: String,
+    tmp_path: PathBuf,
+    writer: ArrowWriter<File>,
+    rows: usize,
+    min_block_number: u64,
+    max_block_number: u64,
+    opened_at: Instant,
+}
+
+/// The files of a chain, in `{table}/date={date}/part-{n}.parquet` of the
+/// chain directory. The blocks are appended to the pending log and synced
+/// before written to the files, so a written block survives a crash, and
+/// the files are committed together, after which the log is truncated.
+pub(crate) struct ChainSink {
+    dir: PathBuf,
+    max_file_rows: usize,
+    max_file_age: Duration,
+    manifest: Manifest,
+    // (table, partition) map to the file being written
+    files: HashMap<(&'static str, String), OpenFile>,
+    pending_log: File,
+    // the blocks in the files being written
+    pending: Vec<BlockRange>,
+}
+
+impl ChainSink {
+    pub(crate) fn open(
+        dir: PathBuf,
+        max_file_rows: usize,
+        max_file_age: Duration,
+    ) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        let manifest = Manifest::load(&dir)?;
+        let removed = remove_in_progress(&dir)?;
+        let pending_path = dir.join(PENDING_LOG_FILE);
+        let blocks = read_pending_log(&pending_path)?;
+        let pending_log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&pending_path)?;
+
+        let mut sink = Self {
+            dir,
+            max_file_rows,
+            max_file_age,
+            manifest,
+            files: HashMap::new(),
+            pending_log,
+            pending: vec![],
+        };
+        // The blocks of the removed files are written again from the log,
+        // the ones committed before the log truncated are skipped.
+        let blocks = blocks
+            .into_iter()
+            .filter(|block| !sink.manifest.contains(block.header.block_number))
+            .collect::<Vec<_>>();
+        if removed > 0 || !blocks.is_empty() {
+            tracing::warn!(
+                "🗑️ {}: removed {} uncommitted files, rewrite {} pending blocks",
+                sink.dir.display(),
+                removed,
+                blocks.len()
+            );
+        }
+        sink.append(&blocks)?;
+        sink.roll_full()?;
+        Ok(sink)
+    }
+
+    /// Checks if the block is committed or being written.
+    fn contains(&self, block_number: u64) -> bool {
+        self.manifest.contains(block_number)
+            || self
+                .pending
+                .iter()
+                .any(|range| range.start <= block_number && block_number <= range.end)
+    }
+
+    fn open_file(&mut self, table: &'static str, partition: &str) -> anyhow::Result<OpenFile> {
+        let part = self.manifest.next_part;
+        self.manifest.next_part += 1;
+
+        let dir = self.dir.join(table).join(partition);
+        std::fs::create_dir_all(&dir)?;
+        let name = format!("part-{:06}.parquet", part);
+        let tmp_path = dir.join(format!(".{}{}", name, IN_PROGRESS_SUFFIX));
+        let props = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+        let writer =
+            ArrowWriter::try_new(File::create(&tmp_path)?, table_schema(table), Some(props))?;
+        Ok(OpenFile {
+            path: format!("{}/{}/{}", table, partition, name),
+            tmp_path,
+            writer,
+            rows: 0,
+            min_block_number: u64::MAX,
+            max_block_number: 0,
+            opened_at: Instant::now(),
+        })
+    }
+
+    /// Close the file and add it to the manifest, the manifest is saved
+    /// once all the files are committed.
+    fn commit(&mut self, key: &(&'static str, String)) -> anyhow::Result<()> {
+        let file = match self.files.remove(key) {
+            None => return Ok(()),
+            Some(file) => file,
+        };
+
+        file.writer.into_inner()?.sync_all()?;
+        std::fs::rename(&file.tmp_path, self.dir.join(&file.path))?;
+        tracing::info!(
+            "📦 {}: committed {} with {} rows",
+            self.dir.display(),
+            file.path,
+            file.rows
+        );
+        self.manifest.files.push(ManifestFile {
+            table: key.0.to_string(),
+            partition: key.1.clone(),
+            path: file.path,
+            rows: file.rows,
+            min_block_number: file.min_block_number,
+            max_block_number: file.max_block_number,
+            committed_at: unix_secs(),
+        });
+        Ok(())
+    }
+
+    /// Append the rows of finalized blocks to the files of their tables
+    /// and date partitions. The files are append only, so the unfinalized
+    /// blocks are skipped until they are finalized, and the blocks written
+    /// before are skipped. The blocks are synced to the pending log before
+    /// returned.
+    pub(crate) fn write(&mut self, blocks: &[polkadot_chain::Block]) -> anyhow::Result<()> {
+        let blocks = blocks
+            .iter()
+            .filter(|block| block.header.is_finished && !self.contains(block.header.block_number))
+            .cloned()
+            .collect::<Vec<_>>();
+        if blocks.is_empty() {
+            return Ok(());
+        }
+
+        let mut lines = vec![];
+        for block in blocks.iter() {
+            serde_json::to_writer(&mut lines, block)?;
+            lines.push(b'\n');
+        }
+        self.pending_log.write_all(&lines)?;
+        self.pending_log.sync_data()?;
+
+        self.append(&blocks)?;
+        self.roll_full()
+    }
+
+    /// Append the rows of blocks to the files being written.
+    fn append(&mut self, blocks: &[polkadot_chain::Block]) -> anyhow::Result<()> {
+        let mut partitions: BTreeMap<String, Vec<polkadot_chain::Block>> = BTreeMap::new();
+        for block in blocks.iter() {
+            partitions
+                .entry(date_partition(block.header.block_timestamp))
+                .or_default()
+                .push(block.clone());
+        }
+
+        for (partition, blocks) in partitions.iter() {
+            let min_block_number = blocks
+                .iter()
+                .map(|b| b.header.block_number)
+                .min()
+                .unwrap_or_default();
+            let max_block_number = blocks
+                .iter()
+                .map(|b| b.header.block_number)
+                .max()
+                .unwrap_or_default();
+            for (table, batch) in record_batches(blocks)? {
+                if batch.num_rows() == 0 {
+                    continue;
+                }
+
+                let key = (table, partition.clone());
+                if !self.files.contains_key(&key) {
+                    let file = self.open_file(table, partition)?;
+                    self.files.insert(key.clone(), file);
+                }
+                let file = self.files.get_mut(&key).unwrap();
+                file.writer.write(&batch)?;
+                file.rows += batch.num_rows();
+                file.min_block_number = file.min_block_number.min(min_block_number);
+                file.max_block_number = file.max_block_number.max(max_block_number);
+            }
+        }
+
+        let mut pending = std::mem::take(&mut self.pending);
+        pending.extend(blocks.iter().map(|block| BlockRange {
+            start: block.header.block_number,
+            end: block.header.block_number,
+        }));
+        self.pending = BlockRange::merge(pending);
+        Ok(())
+    }
+
+    /// Commit all the files if any of them has the max rows.
+    fn roll_full(&mut self) -> anyhow::Result<()> {
+        match self
+            .files
+            .values()
+            .any(|file| file.rows >= self.max_file_rows)
+        {
+            true => self.roll_all(),
+            false => Ok(()),
+        }
+    }
+
+    /// Commit all the files if any of them is opened for longer than the
+    /// max file age.
+    pub(crate) fn roll_expired(&mut self) -> anyhow::Result<()> {
+        match self
+            .files
+            .values()
+            .any(|file| file.opened_at.elapsed() >= self.max_file_age)
+        {
+            true => self.roll_all(),
+            false => Ok(()),
+        }
+    }
+
+    /// Commit all the files being written with their blocks to the
+    /// manifest, then truncate the pending log. If the node stopped before
+    /// the log truncated, the committed blocks in the log are skipped.
+    pub(crate) fn roll_all(&mut self) -> anyhow::Result<()> {
+        if self.files.is_empty() && self.pending.is_empty() {
+            return Ok(());
+        }
+
+        let mut keys = self.files.keys().cloned().collect::<Vec<_>>();
+        keys.sort();
+        for key in keys.iter() {
+            self.commit(key)?;
+        }
+        let mut blocks = std::mem::take(&mut self.manifest.blocks);
+        blocks.append(&mut self.pending);
+        self.manifest.blocks = BlockRange::merge(blocks);
+        self.manifest.save(&self.dir)?;
+
+        self.pending_log.set_len(0)?;
+        self.pending_log.sync_all()?;
+        Ok(())
+    }
+}
+
+impl Drop for ChainSink {
+    fn drop(&mut self) {
+        if let Err(err) = self.roll_all() {
+            tracing::error!("{}: commit files error: {}", self.dir.display(), err);
+        }
+    }
+}
+
+/// The file sink engine writes the blocks as parquet files partitioned by
+/// date for the data lake, a directory per chain. The rows are readable
+/// once the files are rolled by rows or time and committed to the
+/// manifest, the blocks of uncommitted files are written again from the
+/// pending log if the node crashed.
+pub struct FileSinkEngine {
+    // support chain name map to the sink of chain
+    sinks: HashMap<String, Arc<Mutex<ChainSink>>>,
+}
+
+impl FileSinkEngine {
+    pub async fn new(engine: ParquetDataEngine) -> anyhow::Result<Self> {
+        let max_file_rows = engine.max_file_rows.unwrap_or(DEFAULT_MAX_FILE_ROWS).max(1);
+        let max_file_age =
+            Duration::from_secs(engine.max_file_secs.unwrap_or(DEFAULT_MAX_FILE_SECS));
+
+        let mut sinks = HashMap::new();
+        for support_chain in engine.support_chains.iter() {
+            if !support_chain.enabled {
+                tracing::info!(
+                    "💁 {}: skipped not enabled for parquet data engine",
+                    support_chain.name
+                );
+                continue;
+            }
+
+            let dir = Path::new(&engine.root).join(&support_chain.name);
+            let sink =
+                ChainSink::open(dir.clone(), max_file_rows, max_file_age).map_err(|err| {
+                    anyhow!(
+                        "{}: open parquet directory({}) error: {}",
+                        support_chain.name,
+                        dir.display(),
+                        err
+                    )
+                })?;
+            tracing::info!(
+                "🗂️ {}: parquet data engine writing to {}",
+                support_chain.name,
+                dir.display()
+            );
+            sinks.insert(support_chain.name.clone(), Arc::new(Mutex::new(sink)));
+        }
+
+        let weak_sinks = sinks
+            .iter()
+            .map(|(chain, sink)| (chain.clone(), Arc::downgrade(sink)))
+            .collect();
+        let interval = ROLL_CHECK_INTERVAL
+            .min(max_file_age)
+            .max(Duration::from_secs(1));
+        tokio::spawn(Self::roll_expired_files(weak_sinks, interval));
+
+        Ok(Self { sinks })
+    }
+
+    /// Commit the expired files periodically, until the engine dropped.
+    async fn roll_expired_files(sinks: Vec<(String, Weak<Mutex<ChainSink>>)>, interval: Duration) {
+        let start = tokio::time::Instant::now() + interval;
+        let mut ticker = tokio::time::interval_at(start, interval);
+        loop {
+            ticker.tick().await;
+            let mut alive = false;
+            for (chain, sink) in sinks.iter() {
+                let sink = match sink.upgrade() {
+                    None => continue,
+                    Some(sink) => sink,
+                };
+
+                alive = true;
+                let result =
+                    tokio::task::spawn_blocking(move || sink.lock().unwrap().roll_expired()).await;
+                if let Err(err) = result.map_err(anyhow::Error::from).and_then(|r| r) {
+                    tracing::error!("{}: roll parquet files error: {}", chain, err);
+                }
+            }
+
+            if !alive {
+                break;
+            }
+        }
+    }
+
+    /// Run the function with the sink of chain in a blocking thread.
+    async fn with_sink<T, F>(&self, chain: &str, f: F) -> anyhow::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut ChainSink) -> anyhow::Result<T> + Send + 'static,
+    {
+        let sink = self
+            .sinks
+            .get(chain)
+            .cloned()
+            .ok_or(anyhow!("Parquet not support chain({})", chain))?;
+        tokio::task::spawn_blocking(move || {
+            let mut sink = sink.lock().unwrap();
+            f(&mut sink)
+        })
+        .await?
+    }
+}
+
+#[async_trait::async_trait]
+impl DataEngine for FileSinkEngine {
+    fn name(&self) -> String {
+        "Parquet".to_string()
+    }
+
+    fn kind(&self) -> DataEngineKind {
+        DataEngineKind::Parquet
+    }
+
+    async fn supports_chain(&self, chain: &str, kind: &ChainKind) -> bool {
+        matches!(kind, ChainKind::Polkadot) && self.sinks.contains_key(chain)
+    }
+
+    async fn write_block(&self, chain: &str, blocks: &ChainBlocks) -> anyhow::Result<()> {
+        let blocks = blocks.clone();
+        self.with_sink(chain, move |sink| match &blocks {
+            ChainBlocks::Polkadot(blocks) => sink.write(blocks),
+        })
+        .await
+        .map_err(|err| anyhow!("{}: write blocks error: {}", chain, err))
+    }
+
+    async fn schema(&self, chain: &str) -> anyhow::Result<HashMap<String, Vec<PostgresTableInfo>>> {
+        if !self.sinks.contains_key(chain) {
+            return Err(anyhow!("Parquet not support chain({})", chain));
+        }
+
+        Ok(TABLES
+            .iter()
+            .map(|table| {
+                let columns = table_schema(table)
+                    .fields()
+                    .iter()
+                    .map(|field| PostgresTableInfo {
+                        column_name: field.name().clone(),
+                        data_type: field.data_type().to_string(),
+                    })
+                    .collect();
+                (table.to_string(), columns)
+            })
+            .collect())
+    }
+
+    async fn health(&self) -> anyhow::Result<()> {
+        for chain in self.sinks.keys() {
+            self.with_sink(chain, |sink| {
+                std::fs::metadata(&sink.dir)?;
+                Ok(())
+            })
+            .await
+            .map_err(|err| anyhow!("{}: parquet directory not available: {}", chain, err))?;
+        }
+        Ok(())
+    }
+
+    async fn query(&self, chain: &str, _sql: &str) -> anyhow::Result<PostgresRows> {
+        Err(anyhow!(
+            "{}: parquet data engine not support query, read the files of manifest",
+            chain
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyperdot_core::types::ParquetDataEngineForChain;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    use super::*;
+    use crate::storeage::engine::testing::new_block_at;
+
+    const DAY_MILLIS: u64 = 24 * 3600 * 1000;
+
+    fn read_rows(path: PathBuf) -> usize {
+        ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap()
+            .map(|batch| batch.unwrap().num_rows())
+            .sum()
+    }
+
+    #[tokio::test]
+    async fn test_parquet_roll_by_rows() {
+        let root = std::env::temp_dir().join(format!("hyperdot-parquet-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let engine = FileSinkEngine::new(ParquetDataEngine {
+            root: root.to_string_lossy().to_string(),
+            max_file_rows: Some(3),
+            max_file_secs: None,
+            support_chains: vec![ParquetDataEngineForChain {
+                name: "polkadot".to_string(),
+                enabled: true,
+            }],
+        })
+        .await
+        .unwrap();
+        let dir = root.join("polkadot");
+        engine.health().await.unwrap();
+        assert!(engine.query("polkadot", "SELECT 1").await.is_err());
+        assert_eq!(engine.schema("polkadot").await.unwrap().len(), 4);
+
+        let blocks = ChainBlocks::Polkadot(vec![
+            new_block_at(1, DAY_MILLIS, true),
+            new_block_at(2, DAY_MILLIS + 6000, true),
+            new_block_at(3, DAY_MILLIS + 12000, false),
+        ]);
+        engine.write_block("polkadot", &blocks).await.unwrap();
+        assert!(Manifest::load(&dir).unwrap().files.is_empty());
+
+        let blocks = ChainBlocks::Polkadot(vec![
+            new_block_at(3, DAY_MILLIS + 12000, true),
+            new_block_at(4, 2 * DAY_MILLIS, true),
+        ]);
+        engine.write_block("polkadot", &blocks).await.unwrap();
+        // The files are rolled together once any of them has the max rows.
+        let manifest = Manifest::load(&dir).unwrap();
+        assert_eq!(manifest.files.len(), 4);
+        let file = &manifest.files[0];
+        assert_eq!(file.table, "blocks");
+        assert_eq!(file.partition, "date=1970-01-02");
+        assert_eq!(file.path, "blocks/date=1970-01-02/part-000000.parquet");
+        assert_eq!(
+            (file.rows, file.min_block_number, file.max_block_number),
+            (3, 1, 3)
+        );
+        assert_eq!(read_rows(dir.join(&file.path)), 3);
+        assert_eq!(manifest.files[1].partition, "date=1970-01-03");
+        assert_eq!(read_rows(dir.join(&manifest.files[1].path)), 1);
+        assert_eq!(manifest.files[2].table, "events");
+        assert!(manifest
+            .files
+            .iter()
+            .all(|file| dir.join(&file.path).exists()));
+        assert_eq!(manifest.blocks, vec![BlockRange { start: 1, end: 4 }]);
+
+        // The blocks written again are skipped.
+        engine.write_block("polkadot", &blocks).await.unwrap();
+        drop(engine);
+        assert_eq!(Manifest::load(&dir).unwrap().files.len(), 4);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_parquet_roll_by_time() {
+        let dir =
+            std::env::temp_dir().join(format!("hyperdot-parquet-time-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut sink = ChainSink::open(dir.clone(), 100, Duration::ZERO).unwrap();
+        sink.write(&[new_block_at(1, 0, true)]).unwrap();
+        assert_eq!(sink.files.len(), 2);
+        sink.roll_expired().unwrap();
+        assert!(sink.files.is_empty());
+        assert_eq!(sink.manifest.files.len(), 2);
+        drop(sink);
+
+        // The uncommitted files are removed when opened again.
+        std::fs::write(
+            dir.join("blocks/date=1970-01-01/.part-000009.parquet.inprogress"),
+            b"",
+        )
+        .unwrap();
+        let mut sink = ChainSink::open(dir.clone(), 100, Duration::ZERO).unwrap();
+        assert_eq!(sink.manifest.next_part, 2);
+        assert!(!dir
+            .join("blocks/date=1970-01-01/.part-000009.parquet.inprogress")
+            .exists());
+
+        // The written blocks of the uncommitted files survive a crash.
+        sink.write(&[new_block_at(1, 0, true), new_block_at(2, 0, true)])
+            .unwrap();
+        assert_eq!(sink.pending, vec![BlockRange { start: 2, end: 2 }]);
+        std::mem::forget(sink);
+        let mut sink = ChainSink::open(dir.clone(), 100, Duration::ZERO).unwrap();
+        assert_eq!(sink.files.len(), 2);
+        sink.roll_all().unwrap();
+        assert_eq!(sink.manifest.files.len(), 4);
+        assert_eq!(read_rows(dir.join(&sink.manifest.files[1].path)), 1);
+        assert_eq!(sink.manifest.blocks, vec![BlockRange { start: 1, end: 2 }]);
+        assert_eq!(
+            std::fs::metadata(dir.join(PENDING_LOG_FILE)).unwrap().len(),
+            0
+        );
+        drop(sink);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,190 @@
+use std::sync::Arc;
+
+use arrow_array::ArrayRef;
+use arrow_array::BooleanArray;
+use arrow_array::RecordBatch;
+use arrow_array::StringArray;
+use arrow_array::UInt16Array;
+use arrow_array::UInt32Array;
+use arrow_array::UInt64Array;
+use arrow_schema::DataType;
+use arrow_schema::Field;
+use arrow_schema::Schema;
+use arrow_schema::SchemaRef;
+
+use crate::types::block::polkadot_chain;
+
+/// The tables of the substrate files, the bytes are hex strings and the
+/// json values are text, the same as the other columnar engines.
+pub(crate) const TABLES: [&str; 4] = ["blocks", "extrinsics", "events", "block_logs"];
+
+fn field(name: &str, data_type: DataType) -> Field {
+    Field::new(name, data_type, false)
+}
+
+fn nullable(name: &str, data_type: DataType) -> Field {
+    Field::new(name, data_type, true)
+}
+
+pub(crate) fn table_schema(table: &str) -> SchemaRef {
+    let fields = match table {
+        "blocks" => vec![
+            field("number", DataType::UInt64),
+            field("timestamp", DataType::UInt64),
+            field("hash", DataType::Utf8),
+            field("parent_hash", DataType::Utf8),
+            field("extrinsics_root", DataType::Utf8),
+            field("state_root", DataType::Utf8),
+            field("is_finalized", DataType::Boolean),
+            nullable("validator", DataType::Utf8),
+            field("spec_version", DataType::UInt32),
+        ],
+        "extrinsics" => vec![
+            field("id", DataType::Utf8),
+            field("block_number", DataType::UInt64),
+            field("block_timestamp", DataType::UInt64),
+            field("extrinsic_hash", DataType::Utf8),
+            field("is_signed", DataType::Boolean),
+            field("mod_name", DataType::Utf8),
+            field("call_name", DataType::Utf8),
+            field("result", DataType::Boolean),
+            nullable("call_params", DataType::Utf8),
+        ],
+        "events" => vec![
+            field("id", DataType::Utf8),
+            field("block_number", DataType::UInt64),
+            field("block_timestamp", DataType::UInt64),
+            field("extrinsic_index", DataType::UInt32),
+            field("extrinsic_id", DataType::Utf8),
+            field("mod_name", DataType::Utf8),
+            field("event_name", DataType::Utf8),
+            field("event_index", DataType::UInt32),
+            field("phase", DataType::UInt16),
+            field("extrinsic_hash", DataType::Utf8),
+            nullable("values", DataType::Utf8),
+        ],
+        "block_logs" => vec![
+            field("id", DataType::Utf8),
+            field("block_number", DataType::UInt64),
+            field("type", DataType::Utf8),
+            nullable("data", DataType::Utf8),
+            nullable("engine", DataType::Utf8),
+        ],
+        _ => unreachable!("unknown table {}", table),
+    };
+    Arc::new(Schema::new(fields))
+}
+
+fn hex_string(bytes: &[u8]) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
+
+fn u64s<T>(rows: &[T], f: impl Fn(&T) -> u64) -> ArrayRef {
+    Arc::new(UInt64Array::from_iter_values(rows.iter().map(f)))
+}
+
+fn u32s<T>(rows: &[T], f: impl Fn(&T) -> u32) -> ArrayRef {
+    Arc::new(UInt32Array::from_iter_values(rows.iter().map(f)))
+}
+
+fn bools<T>(rows: &[T], f: impl Fn(&T) -> bool) -> ArrayRef {
+    Arc::new(
+        rows.iter()
+            .map(|row| Some(f(row)))
+            .collect::<BooleanArray>(),
+    )
+}
+
+fn strings<T>(rows: &[T], f: impl Fn(&T) -> String) -> ArrayRef {
+    Arc::new(StringArray::from_iter_values(rows.iter().map(f)))
+}
+
+fn optional_strings<T>(rows: &[T], f: impl Fn(&T) -> Option<String>) -> ArrayRef {
+    Arc::new(rows.iter().map(f).collect::<StringArray>())
+}
+
+fn blocks_batch(blocks: &[polkadot_chain::Block]) -> anyhow::Result<RecordBatch> {
+    let headers = blocks.iter().map(|b| &b.header).collect::<Vec<_>>();
+    let columns = vec![
+        u64s(&headers, |h| h.block_number),
+        u64s(&headers, |h| h.block_timestamp),
+        strings(&headers, |h| hex_string(&h.block_hash)),
+        strings(&headers, |h| hex_string(&h.parent_hash)),
+        strings(&headers, |h| hex_string(&h.extrinsics_root)),
+        strings(&headers, |h| hex_string(&h.state_root)),
+        bools(&headers, |h| h.is_finished),
+        optional_strings(&headers, |h| h.validator.as_ref().map(|v| hex_string(v))),
+        u32s(&headers, |h| h.spec_version),
+    ];
+    Ok(RecordBatch::try_new(table_schema("blocks"), columns)?)
+}
+
+fn extrinsics_batch(blocks: &[polkadot_chain::Block]) -> anyhow::Result<RecordBatch> {
+    let exts = blocks
+        .iter()
+        .flat_map(|b| b.body.extrinsics.iter().flatten())
+        .collect::<Vec<_>>();
+    let columns = vec![
+        strings(&exts, |e| e.id.clone()),
+        u64s(&exts, |e| e.block_number),
+        u64s(&exts, |e| e.block_timestamp),
+        strings(&exts, |e| hex_string(&e.extrinsic_hash)),
+        bools(&exts, |e| e.signature.is_some()),
+        strings(&exts, |e| e.mod_name.clone()),
+        strings(&exts, |e| e.call_name.clone()),
+        bools(&exts, |e| e.result),
+        optional_strings(&exts, |e| e.call_params.as_ref().map(|v| v.to_string())),
+    ];
+    Ok(RecordBatch::try_new(table_schema("extrinsics"), columns)?)
+}
+
+fn events_batch(blocks: &[polkadot_chain::Block]) -> anyhow::Result<RecordBatch> {
+    let events = blocks
+        .iter()
+        .flat_map(|b| b.body.events.iter().flatten())
+        .collect::<Vec<_>>();
+    let columns = vec![
+        strings(&events, |e| e.id.clone()),
+        u64s(&events, |e| e.block_number),
+        u64s(&events, |e| e.block_timestamp),
+        u32s(&events, |e| e.extrinsic_index),
+        strings(&events, |e| e.extrinsic_id.clone()),
+        strings(&events, |e| e.mod_name.clone()),
+        strings(&events, |e| e.event_name.clone()),
+        u32s(&events, |e| e.event_index),
+        Arc::new(UInt16Array::from_iter_values(
+            events.iter().map(|e| e.phase),
+        )),
+        strings(&events, |e| hex_string(&e.extrinsic_hash)),
+        optional_strings(&events, |e| e.values.as_ref().map(|v| v.to_string())),
+    ];
+    Ok(RecordBatch::try_new(table_schema("events"), columns)?)
+}
+
+fn logs_batch(blocks: &[polkadot_chain::Block]) -> anyhow::Result<RecordBatch> {
+    let logs = blocks
+        .iter()
+        .flat_map(|b| b.logs.iter().flatten())
+        .collect::<Vec<_>>();
+    let columns = vec![
+        strings(&logs, |l| l.id.clone()),
+        u64s(&logs, |l| l.block_number),
+        strings(&logs, |l| l.r#type.clone()),
+        optional_strings(&logs, |l| l.data.as_ref().map(|v| hex_string(v))),
+        optional_strings(&logs, |l| l.engine.clone()),
+    ];
+    Ok(RecordBatch::try_new(table_schema("block_logs"), columns)?)
+}
+
+/// The record batch of each table for the blocks, in the order of
+/// `TABLES`.
+pub(crate) fn record_batches(
+    blocks: &[polkadot_chain::Block],
+) -> anyhow::Result<Vec<(&'static str, RecordBatch)>> {
+    Ok(vec![
+        ("blocks", blocks_batch(blocks)?),
+        ("extrinsics", extrinsics_batch(blocks)?),
+        ("events", events_batch(blocks)?),
+        ("block_logs", logs_batch(blocks)?),
+    ])
+}
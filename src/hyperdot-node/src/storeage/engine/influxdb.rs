@@ -0,0 +1,442 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use hyper::client::HttpConnector;
+use hyper::Body;
+use hyper::Client;
+use hyper::Method;
+use hyper::Request;
+use hyperdot_core::types::ChainKind;
+use hyperdot_core::types::DataEngineKind;
+use hyperdot_core::types::InfluxDBDataEngine;
+use hyperdot_core::types::PostgresRows;
+use hyperdot_core::types::PostgresTableInfo;
+
+use super::engine::ChainBlocks;
+use super::engine::DataEngine;
+use crate::types::block::polkadot_chain;
+
+/// The measurements of points and their fields, as the tables schema.
+const MEASUREMENTS: [(&str, &[(&str, &str)]); 2] = [
+    ("blocks", &[
+        ("chain", "tag"),
+        ("spec_version", "tag"),
+        ("number", "unsigned"),
+        ("extrinsics", "unsigned"),
+        ("signed_extrinsics", "unsigned"),
+        ("failed_extrinsics", "unsigned"),
+        ("events", "unsigned"),
+        ("transfers", "unsigned"),
+        ("transfer_volume", "float"),
+        ("validator", "string"),
+        ("finalized", "boolean"),
+    ]),
+    ("events", &[
+        ("chain", "tag"),
+        ("module", "tag"),
+        ("event", "tag"),
+        ("count", "unsigned"),
+    ]),
+];
+
+/// Escape the commas, equal signs and spaces of tag value.
+fn escape_tag(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+}
+
+/// Quote the string field value.
+fn quote_field(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// The amount of the transfer event, which is a number or a string of
+/// the big number.
+fn transfer_amount(values: Option<&serde_json::Value>) -> f64 {
+    match values.and_then(|values| values.get("amount")) {
+        Some(serde_json::Value::Number(amount)) => amount.as_f64().unwrap_or_default(),
+        Some(serde_json::Value::String(amount)) => amount.parse().unwrap_or_default(),
+        _ => 0.0,
+    }
+}
+
+/// Convert the block to points in line protocol, keyed by the block
+/// timestamp in milliseconds. The points of a block have the same tags
+/// and timestamp if written again, so they are overwritten by a replay.
+fn block_lines(chain: &str, block: &polkadot_chain::Block) -> Vec<String> {
+    let header = &block.header;
+    let chain = escape_tag(chain);
+    let extrinsics = block.body.extrinsics.as_deref().unwrap_or_default();
+    let events = block.body.events.as_deref().unwrap_or_default();
+    let transfers = events
+        .iter()
+        .filter(|e| e.mod_name == "Balances" && e.event_name == "Transfer")
+        .collect::<Vec<_>>();
+
+    let mut fields = vec![
+        format!("number={}u", header.block_number),
+        format!("extrinsics={}u", extrinsics.len()),
+        format!(
+            "signed_extrinsics={}u",
+            extrinsics.iter().filter(|e| e.signature.is_some()).count()
+        ),
+        format!(
+            "failed_extrinsics={}u",
+            extrinsics.iter().filter(|e| !e.result).count()
+        ),
+        format!("events={}u", events.len()),
+        format!("transfers={}u", transfers.len()),
+        format!(
+            "transfer_volume={:?}",
+            transfers
+                .iter()
+                .map(|e| transfer_amount(e.values.as_ref()))
+                .sum::<f64>()
+        ),
+        format!("finalized={}", header.is_finished),
+    ];
+    if let Some(validator) = header.validator.as_ref() {
+        fields.push(format!(
+            "validator={}",
+            quote_field(&format!("0x{}", hex::encode(validator)))
+        ));
+    }
+
+    let mut lines = vec![format!(
+        "blocks,chain={},spec_version={} {} {}",
+        chain,
+        header.spec_version,
+        fields.join(","),
+        header.block_timestamp
+    )];
+
+    let mut counts: BTreeMap<(&str, &str), usize> = BTreeMap::new();
+    for event in events.iter() {
+        *counts
+            .entry((&event.mod_name, &event.event_name))
+            .or_default() += 1;
+    }
+    for ((module, event), count) in counts {
+        lines.push(format!(
+            "events,chain={},module={},event={} count={}u {}",
+            chain,
+            escape_tag(module),
+            escape_tag(event),
+            count,
+            header.block_timestamp
+        ));
+    }
+    lines
+}
+
+/// The influxdb engine writes the per-block metrics of chain as points
+/// in line protocol, a bucket per chain. The points of a fork block have
+/// other timestamp and tags than the finalized block at its height, so
+/// they are never overwritten, only the finalized blocks are written.
+pub struct InfluxDBEngine {
+    url: String,
+    org: String,
+    token: Option<String>,
+    client: Client<HttpConnector>,
+    // support chain name map to bucket
+    buckets: HashMap<String, String>,
+}
+
+impl InfluxDBEngine {
+    pub async fn new(engine: InfluxDBDataEngine) -> anyhow::Result<Self> {
+        let mut buckets = HashMap::new();
+        for support_chain in engine.support_chains.iter() {
+            if !support_chain.enabled {
+                tracing::info!(
+                    "💁 {}: skipped not enabled for influxdb data engine",
+                    support_chain.name
+                );
+                continue;
+            }
+
+            tracing::info!(
+                "📈 {}: influxdb data engine using bucket {}",
+                support_chain.name,
+                support_chain.bucket,
+            );
+            buckets.insert(support_chain.name.clone(), support_chain.bucket.clone());
+        }
+
+        Ok(Self {
+            url: engine.url.trim_end_matches('/').to_string(),
+            org: engine.org,
+            token: engine.token,
+            client: Client::new(),
+            buckets,
+        })
+    }
+
+    async fn send(&self, method: Method, url: &str, body: String) -> anyhow::Result<()> {
+        let mut request = Request::builder().method(method).uri(url);
+        if let Some(token) = self.token.as_ref() {
+            request = request.header("Authorization", format!("Token {}", token));
+        }
+
+        let response = self.client.request(request.body(Body::from(body))?).await?;
+        let status = response.status();
+        if !status.is_success() {
+            let bytes = hyper::body::to_bytes(response.into_body()).await?;
+            return Err(anyhow!(
+                "influxdb response {}: {}",
+                status,
+                String::from_utf8_lossy(&bytes).trim()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Write the lines to the bucket with milliseconds precision.
+    async fn write_lines(&self, bucket: &str, lines: Vec<String>) -> anyhow::Result<()> {
+        let mut url = url::Url::parse(&format!("{}/api/v2/write", self.url))?;
+        url.query_pairs_mut()
+            .append_pair("org", &self.org)
+            .append_pair("bucket", bucket)
+            .append_pair("precision", "ms");
+        self.send(Method::POST, url.as_str(), lines.join("\n"))
+            .await
+    }
+}
+
+#[async_trait::async_trait]
+impl DataEngine for InfluxDBEngine {
+    fn name(&self) -> String {
+        "InfluxDB".to_string()
+    }
+
+    fn kind(&self) -> DataEngineKind {
+        DataEngineKind::InfluxDB
+    }
+
+    async fn supports_chain(&self, chain: &str, kind: &ChainKind) -> bool {
+        matches!(kind, ChainKind::Polkadot) && self.buckets.contains_key(chain)
+    }
+
+    /// Write the points of the finalized blocks of the batch in one
+    /// request, the unfinalized blocks are skipped until finalized.
+    async fn write_block(&self, chain: &str, blocks: &ChainBlocks) -> anyhow::Result<()> {
+        let bucket = self
+            .buckets
+            .get(chain)
+            .ok_or(anyhow!("InfluxDB not support chain({})", chain))?;
+        let lines = match blocks {
+            ChainBlocks::Polkadot(blocks) => blocks
+                .iter()
+                .filter(|block| block.header.is_finished)
+                .flat_map(|block| block_lines(chain, block))
+                .collect::<Vec<_>>(),
+        };
+        if lines.is_empty() {
+            return Ok(());
+        }
+
+        self.write_lines(bucket, lines)
+            .await
+            .map_err(|err| anyhow!("{}: write points error: {}", chain, err))
+    }
+
+    async fn schema(&self, chain: &str) -> anyhow::Result<HashMap<String, Vec<PostgresTableInfo>>> {
+        if !self.buckets.contains_key(chain) {
+            return Err(anyhow!("InfluxDB not support chain({})", chain));
+        }
+
+        Ok(MEASUREMENTS
+            .iter()
+            .map(|(measurement, fields)| {
+                let columns = fields
+                    .iter()
+                    .map(|(name, data_type)| PostgresTableInfo {
+                        column_name: name.to_string(),
+                        data_type: data_type.to_string(),
+                    })
+                    .collect();
+                (measurement.to_string(), columns)
+            })
+            .collect())
+    }
+
+    async fn health(&self) -> anyhow::Result<()> {
+        self.send(Method::GET, &format!("{}/health", self.url), String::new())
+            .await
+            .map_err(|err| anyhow!("influxdb not available: {}", err))
+    }
+
+    async fn query(&self, chain: &str, _sql: &str) -> anyhow::Result<PostgresRows> {
+        Err(anyhow!(
+            "{}: influxdb data engine not support sql query",
+            chain
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    use axum::extract::Query;
+    use axum::extract::State;
+    use axum::http::HeaderMap;
+    use axum::http::StatusCode;
+    use axum::routing::get;
+    use axum::routing::post;
+    use axum::Router;
+    use hyperdot_core::types::InfluxDBDataEngineForChain;
+
+    use super::*;
+
+    type Writes = Arc<Mutex<Vec<(HashMap<String, String>, Option<String>, String)>>>;
+
+    async fn mock_write(
+        State(writes): State<Writes>,
+        Query(params): Query<HashMap<String, String>>,
+        headers: HeaderMap,
+        body: String,
+    ) -> StatusCode {
+        let token = headers
+            .get("Authorization")
+            .map(|v| v.to_str().unwrap().to_string());
+        writes.lock().unwrap().push((params, token, body));
+        StatusCode::NO_CONTENT
+    }
+
+    fn new_event(number: u64, index: u32, event_name: &str, amount: u64) -> polkadot_chain::Event {
+        polkadot_chain::Event {
+            id: format!("{}-{}", number, index),
+            block_number: number,
+            block_timestamp: 1686000000000,
+            extrinsic_index: 1,
+            extrinsic_id: format!("{}-1", number),
+            mod_name: "Balances".to_string(),
+            event_name: event_name.to_string(),
+            event_index: index,
+            phase: 0,
+            extrinsic_hash: vec![],
+            values: Some(serde_json::json!({ "amount": amount })),
+        }
+    }
+
+    #[test]
+    fn test_block_lines() {
+        let mut block = polkadot_chain::Block::default();
+        block.header.block_number = 10;
+        block.header.block_timestamp = 1686000000000;
+        block.header.spec_version = 9430;
+        block.header.is_finished = true;
+        block.header.validator = Some(vec![1, 2]);
+        block.body.events = Some(vec![
+            new_event(10, 0, "Withdraw", 7),
+            new_event(10, 1, "Transfer", 100),
+            new_event(10, 2, "Transfer", 50),
+        ]);
+
+        let lines = block_lines("polkadot main", &block);
+        assert_eq!(lines, vec![
+            "blocks,chain=polkadot\\ main,spec_version=9430 number=10u,extrinsics=0u,signed_extrinsics=0u,failed_extrinsics=0u,events=3u,transfers=2u,transfer_volume=150.0,finalized=true,validator=\"0x0102\" 1686000000000",
+            "events,chain=polkadot\\ main,module=Balances,event=Transfer count=2u 1686000000000",
+            "events,chain=polkadot\\ main,module=Balances,event=Withdraw count=1u 1686000000000",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_influxdb_write_block() {
+        let writes = Writes::default();
+        let app = Router::new()
+            .route("/api/v2/write", post(mock_write))
+            .route("/health", get(|| async { StatusCode::OK }))
+            .with_state(writes.clone());
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+
+        let engine = InfluxDBEngine::new(InfluxDBDataEngine {
+            url,
+            org: "hyperdot".to_string(),
+            token: Some("secret".to_string()),
+            support_chains: vec![InfluxDBDataEngineForChain {
+                name: "polkadot".to_string(),
+                bucket: "polkadot-metrics".to_string(),
+                enabled: true,
+            }],
+        })
+        .await
+        .unwrap();
+        engine.health().await.unwrap();
+        assert!(engine.query("polkadot", "SELECT 1").await.is_err());
+        assert_eq!(engine.schema("polkadot").await.unwrap().len(), 2);
+
+        let blocks = (1..=3)
+            .map(|number| {
+                let mut block = polkadot_chain::Block::default();
+                block.header.block_number = number;
+                block.header.block_timestamp = number * 6000;
+                block.header.is_finished = true;
+                block
+            })
+            .collect();
+        engine
+            .write_block("polkadot", &ChainBlocks::Polkadot(blocks))
+            .await
+            .unwrap();
+        assert!(engine
+            .write_block("kusama", &ChainBlocks::Polkadot(vec![]))
+            .await
+            .is_err());
+
+        {
+            let mut writes = writes.lock().unwrap();
+            assert_eq!(writes.len(), 1);
+            let (params, token, body) = &writes[0];
+            assert_eq!(params["org"], "hyperdot");
+            assert_eq!(params["bucket"], "polkadot-metrics");
+            assert_eq!(params["precision"], "ms");
+            assert_eq!(token.as_deref(), Some("Token secret"));
+            assert_eq!(body.lines().count(), 3);
+            assert!(body.lines().last().unwrap().ends_with(" 18000"));
+            writes.clear();
+        }
+
+        // The fork block at the height is skipped, only the points of the
+        // finalized block are written.
+        let mut fork = polkadot_chain::Block::default();
+        fork.header.block_number = 4;
+        fork.header.block_timestamp = 24001;
+        fork.header.spec_version = 1;
+        fork.body.events = Some(vec![new_event(4, 0, "Withdraw", 7)]);
+        let mut finalized = polkadot_chain::Block::default();
+        finalized.header.block_number = 4;
+        finalized.header.block_timestamp = 24000;
+        finalized.header.spec_version = 2;
+        finalized.header.is_finished = true;
+        finalized.body.events = Some(vec![new_event(4, 0, "Transfer", 100)]);
+        engine
+            .write_block("polkadot", &ChainBlocks::Polkadot(vec![fork]))
+            .await
+            .unwrap();
+        engine
+            .write_block("polkadot", &ChainBlocks::Polkadot(vec![finalized]))
+            .await
+            .unwrap();
+
+        let writes = writes.lock().unwrap();
+        assert_eq!(writes.len(), 1);
+        let lines = writes[0].2.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines
+            .iter()
+            .all(|line| line.ends_with(" 24000") && !line.contains("Withdraw")));
+        assert!(lines[0].starts_with("blocks,chain=polkadot,spec_version=2 "));
+    }
+}
@@ -1,12 +1,22 @@
+pub mod clickhouse;
 mod controller;
 mod influxdb;
 
+#[cfg(feature = "duckdb")]
+pub mod duck;
 pub mod engine;
+pub mod filesink;
 pub mod pg;
+mod sandbox;
 // pub mod postgres;
 pub mod spark;
+pub mod sqlite;
+#[cfg(test)]
+mod testing;
 // mod url;
 mod utils;
 
 pub use controller::Controller;
+pub use controller::WriteBlockRejected;
 pub use pg::PgEngine;
+pub use pg::PgMigrator;
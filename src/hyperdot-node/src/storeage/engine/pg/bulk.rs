@@ -0,0 +1,410 @@
+use std::collections::BTreeMap;
+
+use futures::pin_mut;
+use tokio_postgres::binary_copy::BinaryCopyInWriter;
+use tokio_postgres::error::SqlState;
+use tokio_postgres::types::ToSql;
+use tokio_postgres::types::Type;
+use tokio_postgres::Transaction;
+
+use super::writer::SubstrateWriter;
+use crate::types::block::polkadot_chain;
+
+/// The staging tables live in the writer session and are emptied when the
+/// transaction ends, so every batch starts with empty tables.
+const CREATE_STAGING_TABLES_STMT: &str = r#"
+CREATE TEMP TABLE IF NOT EXISTS staging_blocks (LIKE blocks) ON COMMIT DELETE ROWS;
+CREATE TEMP TABLE IF NOT EXISTS staging_block_logs (LIKE block_logs) ON COMMIT DELETE ROWS;
+CREATE TEMP TABLE IF NOT EXISTS staging_extrinsics (LIKE extrinsics) ON COMMIT DELETE ROWS;
+CREATE TEMP TABLE IF NOT EXISTS staging_events (LIKE events) ON COMMIT DELETE ROWS;
+"#;
+
+/// Delete the unfinalized blocks replaced by the batch and their logs,
+/// extrinsics, events and completion markers: the ones at the numbers `$1`
+/// of the finalized blocks, and the ones from the lowest number `$2` of the
+/// unfinalized blocks.
+const ROLLBACK_UNFINALIZED_BATCH_STMTS: [&str; 5] = [
+    r#"DELETE FROM block_logs WHERE block_number IN (
+    SELECT "number" FROM blocks WHERE NOT is_finalized AND ("number" = ANY($1) OR "number" >= $2)
+)"#,
+    r#"DELETE FROM extrinsics WHERE block_number IN (
+    SELECT "number" FROM blocks WHERE NOT is_finalized AND ("number" = ANY($1) OR "number" >= $2)
+)"#,
+    r#"DELETE FROM events WHERE block_number IN (
+    SELECT "number" FROM blocks WHERE NOT is_finalized AND ("number" = ANY($1) OR "number" >= $2)
+)"#,
+    r#"DELETE FROM block_completions WHERE block_number IN (
+    SELECT "number" FROM blocks WHERE NOT is_finalized AND ("number" = ANY($1) OR "number" >= $2)
+)"#,
+    r#"DELETE FROM blocks WHERE NOT is_finalized AND ("number" = ANY($1) OR "number" >= $2)"#,
+];
+
+const BLOCKS_COPY_STMT: &str = r#"COPY staging_blocks (
+    "number", "timestamp", "hash", parent_hash, extrinsics_root, state_root, is_finalized,
+    validator, spec_version, hash_bytes, parent_hash_bytes, extrinsics_root_bytes,
+    state_root_bytes, validator_bytes
+) FROM STDIN BINARY"#;
+
+const BLOCKS_COPY_TYPES: [Type; 14] = [
+    Type::INT8,
+    Type::INT8,
+    Type::TEXT,
+    Type::TEXT,
+    Type::TEXT,
+    Type::TEXT,
+    Type::BOOL,
+    Type::TEXT,
+    Type::INT4,
+    Type::BYTEA,
+    Type::BYTEA,
+    Type::BYTEA,
+    Type::BYTEA,
+    Type::BYTEA,
+];
+
+const LOGS_COPY_STMT: &str = r#"COPY staging_block_logs (
+    id, block_number, "type", "data", engine
+) FROM STDIN BINARY"#;
+
+const LOGS_COPY_TYPES: [Type; 5] = [Type::TEXT, Type::INT8, Type::TEXT, Type::TEXT, Type::TEXT];
+
+const EXTRINSICS_COPY_STMT: &str = r#"COPY staging_extrinsics (
+    id, block_number, extrinsic_hash, is_signed, mod_name, call_name, result, call_params,
+    extrinsic_hash_bytes
+) FROM STDIN BINARY"#;
+
+const EXTRINSICS_COPY_TYPES: [Type; 9] = [
+    Type::TEXT,
+    Type::INT8,
+    Type::TEXT,
+    Type::BOOL,
+    Type::TEXT,
+    Type::TEXT,
+    Type::BOOL,
+    Type::JSON,
+    Type::BYTEA,
+];
+
+const EVENTS_COPY_STMT: &str = r#"COPY staging_events (
+    id, block_number, extrinsic_id, mod_name, event_name, phase, values
+) FROM STDIN BINARY"#;
+
+const EVENTS_COPY_TYPES: [Type; 7] = [
+    Type::TEXT,
+    Type::INT8,
+    Type::TEXT,
+    Type::TEXT,
+    Type::TEXT,
+    Type::INT2,
+    Type::JSON,
+];
+
+/// Merge the staging tables into the tables, with the same conflict
+/// handling as the row upserts.
+const MERGE_STAGING_STMT: &str = r#"
+INSERT INTO blocks (
+    "number", "timestamp", "hash", parent_hash, extrinsics_root, state_root, is_finalized,
+    validator, spec_version, hash_bytes, parent_hash_bytes, extrinsics_root_bytes,
+    state_root_bytes, validator_bytes
+) SELECT
+    "number", "timestamp", "hash", parent_hash, extrinsics_root, state_root, is_finalized,
+    validator, spec_version, hash_bytes, parent_hash_bytes, extrinsics_root_bytes,
+    state_root_bytes, validator_bytes
+FROM staging_blocks
+ON CONFLICT ("number") DO UPDATE
+    SET
+        "timestamp" = excluded."timestamp",
+        "hash" = excluded."hash",
+        parent_hash = excluded.parent_hash,
+        extrinsics_root = excluded.extrinsics_root,
+        state_root = excluded.state_root,
+        is_finalized = excluded.is_finalized,
+        validator = excluded.validator,
+        spec_version = excluded.spec_version,
+        hash_bytes = excluded.hash_bytes,
+        parent_hash_bytes = excluded.parent_hash_bytes,
+        extrinsics_root_bytes = excluded.extrinsics_root_bytes,
+        state_root_bytes = excluded.state_root_bytes,
+        validator_bytes = excluded.validator_bytes
+    WHERE excluded.is_finalized OR NOT blocks.is_finalized;
+
+INSERT INTO block_logs (id, block_number, "type", "data", engine)
+SELECT id, block_number, "type", "data", engine FROM staging_block_logs
+ON CONFLICT (id) DO UPDATE
+    SET block_number = EXCLUDED.block_number,
+        "type" = EXCLUDED.type,
+        "data" = EXCLUDED.data,
+        engine = EXCLUDED.engine;
+
+INSERT INTO extrinsics (
+    id, block_number, extrinsic_hash, is_signed, mod_name, call_name, result, call_params,
+    extrinsic_hash_bytes
+) SELECT
+    id, block_number, extrinsic_hash, is_signed, mod_name, call_name, result, call_params,
+    extrinsic_hash_bytes
+FROM staging_extrinsics
+ON CONFLICT (id) DO UPDATE
+SET
+    block_number = EXCLUDED.block_number,
+    extrinsic_hash = EXCLUDED.extrinsic_hash,
+    is_signed = EXCLUDED.is_signed,
+    mod_name = EXCLUDED.mod_name,
+    call_name = EXCLUDED.call_name,
+    result = EXCLUDED.result,
+    call_params = EXCLUDED.call_params,
+    extrinsic_hash_bytes = EXCLUDED.extrinsic_hash_bytes;
+
+INSERT INTO events (id, block_number, extrinsic_id, mod_name, event_name, phase, values)
+SELECT id, block_number, extrinsic_id, mod_name, event_name, phase, values FROM staging_events
+ON CONFLICT (id) DO UPDATE
+    SET block_number = EXCLUDED.block_number,
+        extrinsic_id = EXCLUDED.extrinsic_id,
+        mod_name = EXCLUDED.mod_name,
+        event_name = EXCLUDED.event_name,
+        phase = EXCLUDED.phase,
+        values = EXCLUDED.values;
+"#;
+
+/// The blocks left after writing the batch one by one: a finalized block
+/// replaces the one at its number, an unfinalized block rolls back the
+/// unfinalized ones from its number and never replaces a finalized one.
+pub(crate) fn effective_blocks(blocks: &[polkadot_chain::Block]) -> Vec<&polkadot_chain::Block> {
+    let mut effective: BTreeMap<u64, &polkadot_chain::Block> = BTreeMap::new();
+    for block in blocks.iter() {
+        let number = block.header.block_number;
+        if !block.header.is_finished {
+            effective.retain(|n, b| *n < number || b.header.is_finished);
+            if effective.contains_key(&number) {
+                continue;
+            }
+        }
+        effective.insert(number, block);
+    }
+    effective.into_values().collect()
+}
+
+/// The parameters of the batch rollback statements: the numbers of the
+/// finalized blocks and the lowest number of the unfinalized blocks.
+pub(crate) fn rollback_params(blocks: &[polkadot_chain::Block]) -> (Vec<i64>, i64) {
+    let finalized = blocks
+        .iter()
+        .filter(|b| b.header.is_finished)
+        .map(|b| b.header.block_number as i64)
+        .collect();
+    let unfinalized_from = blocks
+        .iter()
+        .filter(|b| !b.header.is_finished)
+        .map(|b| b.header.block_number as i64)
+        .min()
+        .unwrap_or(i64::MAX);
+    (finalized, unfinalized_from)
+}
+
+/// The conflicts the merge can't resolve in one statement, such as a row
+/// updated twice by the staging rows.
+pub(crate) fn is_conflict(err: &tokio_postgres::Error) -> bool {
+    matches!(
+        err.code(),
+        Some(&SqlState::CARDINALITY_VIOLATION) | Some(&SqlState::UNIQUE_VIOLATION)
+    )
+}
+
+fn hex_string(bytes: &[u8]) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
+
+impl SubstrateWriter {
+    /// Write the batch with `COPY ... FROM STDIN BINARY` into the staging
+    /// tables and merge them into the tables, which takes the same round
+    /// trips whatever the number of blocks and rows.
+    pub(crate) async fn copy_blocks(
+        tx: &Transaction<'_>,
+        blocks: &[polkadot_chain::Block],
+    ) -> Result<(), tokio_postgres::Error> {
+        tx.batch_execute(CREATE_STAGING_TABLES_STMT).await?;
+
+        let (finalized, unfinalized_from) = rollback_params(blocks);
+        for stmt in ROLLBACK_UNFINALIZED_BATCH_STMTS.iter() {
+            tx.execute(*stmt, &[&finalized, &unfinalized_from]).await?;
+        }
+
+        let blocks = effective_blocks(blocks);
+        Self::copy_headers(tx, &blocks).await?;
+        Self::copy_logs(tx, &blocks).await?;
+        Self::copy_extrinsics(tx, &blocks).await?;
+        Self::copy_events(tx, &blocks).await?;
+
+        tx.batch_execute(MERGE_STAGING_STMT).await?;
+
+        let numbers = blocks
+            .iter()
+            .map(|b| b.header.block_number as i64)
+            .collect::<Vec<_>>();
+        Self::write_completions(tx, &numbers).await
+    }
+
+    async fn copy_headers(
+        tx: &Transaction<'_>,
+        blocks: &[&polkadot_chain::Block],
+    ) -> Result<(), tokio_postgres::Error> {
+        let sink = tx.copy_in(BLOCKS_COPY_STMT).await?;
+        let writer = BinaryCopyInWriter::new(sink, &BLOCKS_COPY_TYPES);
+        pin_mut!(writer);
+        for block in blocks.iter() {
+            let header = &block.header;
+            let validator = header.validator.as_ref().map(|v| hex_string(v));
+            let values: [&(dyn ToSql + Sync); 14] = [
+                &(header.block_number as i64),
+                &(header.block_timestamp as i64),
+                &hex_string(&header.block_hash),
+                &hex_string(&header.parent_hash),
+                &hex_string(&header.extrinsics_root),
+                &hex_string(&header.state_root),
+                &header.is_finished,
+                &validator,
+                &(header.spec_version as i32),
+                &header.block_hash,
+                &header.parent_hash,
+                &header.extrinsics_root,
+                &header.state_root,
+                &header.validator,
+            ];
+            writer.as_mut().write(&values).await?;
+        }
+        writer.finish().await?;
+        Ok(())
+    }
+
+    async fn copy_logs(
+        tx: &Transaction<'_>,
+        blocks: &[&polkadot_chain::Block],
+    ) -> Result<(), tokio_postgres::Error> {
+        let sink = tx.copy_in(LOGS_COPY_STMT).await?;
+        let writer = BinaryCopyInWriter::new(sink, &LOGS_COPY_TYPES);
+        pin_mut!(writer);
+        for log in blocks.iter().flat_map(|b| b.logs.iter().flatten()) {
+            let data = log.data.as_ref().map(|v| hex_string(v));
+            let values: [&(dyn ToSql + Sync); 5] = [
+                &log.id,
+                &(log.block_number as i64),
+                &log.r#type,
+                &data,
+                &log.engine,
+            ];
+            writer.as_mut().write(&values).await?;
+        }
+        writer.finish().await?;
+        Ok(())
+    }
+
+    async fn copy_extrinsics(
+        tx: &Transaction<'_>,
+        blocks: &[&polkadot_chain::Block],
+    ) -> Result<(), tokio_postgres::Error> {
+        let sink = tx.copy_in(EXTRINSICS_COPY_STMT).await?;
+        let writer = BinaryCopyInWriter::new(sink, &EXTRINSICS_COPY_TYPES);
+        pin_mut!(writer);
+        for ext in blocks
+            .iter()
+            .flat_map(|b| b.body.extrinsics.iter().flatten())
+        {
+            let values: [&(dyn ToSql + Sync); 9] = [
+                &ext.id,
+                &(ext.block_number as i64),
+                &hex_string(&ext.extrinsic_hash),
+                &ext.signature.is_some(),
+                &ext.mod_name,
+                &ext.call_name,
+                &ext.result,
+                &ext.call_params,
+                &ext.extrinsic_hash,
+            ];
+            writer.as_mut().write(&values).await?;
+        }
+        writer.finish().await?;
+        Ok(())
+    }
+
+    async fn copy_events(
+        tx: &Transaction<'_>,
+        blocks: &[&polkadot_chain::Block],
+    ) -> Result<(), tokio_postgres::Error> {
+        let sink = tx.copy_in(EVENTS_COPY_STMT).await?;
+        let writer = BinaryCopyInWriter::new(sink, &EVENTS_COPY_TYPES);
+        pin_mut!(writer);
+        for event in blocks.iter().flat_map(|b| b.body.events.iter().flatten()) {
+            let values: [&(dyn ToSql + Sync); 7] = [
+                &event.id,
+                &(event.block_number as i64),
+                &event.extrinsic_id,
+                &event.mod_name,
+                &event.event_name,
+                &(event.phase as i16),
+                &event.values,
+            ];
+            writer.as_mut().write(&values).await?;
+        }
+        writer.finish().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_block(number: u64, hash: u8, is_finished: bool) -> polkadot_chain::Block {
+        let mut block = polkadot_chain::Block::default();
+        block.header.block_number = number;
+        block.header.block_hash = vec![hash; 32];
+        block.header.is_finished = is_finished;
+        block
+    }
+
+    fn numbers_and_hashes(blocks: &[&polkadot_chain::Block]) -> Vec<(u64, u8)> {
+        blocks
+            .iter()
+            .map(|b| (b.header.block_number, b.header.block_hash[0]))
+            .collect()
+    }
+
+    #[test]
+    fn test_effective_blocks() {
+        // A finalized block replaces the unfinalized one at its number.
+        let blocks = vec![
+            new_block(10, 1, false),
+            new_block(11, 1, false),
+            new_block(10, 2, true),
+        ];
+        assert_eq!(numbers_and_hashes(&effective_blocks(&blocks)), vec![
+            (10, 2),
+            (11, 1)
+        ]);
+
+        // An unfinalized block rolls back the unfinalized forks after it,
+        // but never replaces a finalized block.
+        let blocks = vec![
+            new_block(10, 1, true),
+            new_block(11, 1, false),
+            new_block(12, 1, false),
+            new_block(11, 2, false),
+            new_block(10, 3, false),
+        ];
+        assert_eq!(numbers_and_hashes(&effective_blocks(&blocks)), vec![(
+            10, 1
+        )]);
+    }
+
+    #[test]
+    fn test_rollback_params() {
+        let blocks = vec![
+            new_block(10, 1, true),
+            new_block(12, 1, false),
+            new_block(11, 1, false),
+        ];
+        assert_eq!(rollback_params(&blocks), (vec![10], 11));
+
+        let blocks = vec![new_block(10, 1, true)];
+        assert_eq!(rollback_params(&blocks), (vec![10], i64::MAX));
+    }
+}
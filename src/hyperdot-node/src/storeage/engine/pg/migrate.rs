@@ -0,0 +1,302 @@
+use anyhow::anyhow;
+use hyperdot_core::types::PostgresDataEngineConnection;
+use tokio_postgres::error::SqlState;
+use tokio_postgres::Client;
+
+use crate::storeage::tls;
+
+/// The database connected to create the missing chain databases.
+const MAINTENANCE_DBNAME: &str = "postgres";
+
+/// The key of the advisory lock held while migrating, so the nodes
+/// sharing a database don't apply the same migration concurrently.
+const MIGRATE_LOCK_KEY: i64 = 0x6879_7065_7264_6f74;
+
+const SCHEMA_VERSION_TABLE_STMT: &str = r#"
+CREATE TABLE IF NOT EXISTS schema_version (
+    version INT NOT NULL,
+    name TEXT NOT NULL,
+    applied_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (version)
+)
+"#;
+
+const SCHEMA_VERSION_EXISTS_STMT: &str = "SELECT to_regclass('schema_version') IS NOT NULL";
+
+const SCHEMA_VERSIONS_STMT: &str = "SELECT version FROM schema_version ORDER BY version";
+
+const SCHEMA_VERSION_INSERT_STMT: &str =
+    "INSERT INTO schema_version (version, name) VALUES ($1, $2)";
+
+const DATABASE_EXISTS_STMT: &str = "SELECT 1 FROM pg_database WHERE datname = $1";
+
+pub(crate) struct Migration {
+    pub(crate) version: i32,
+    pub(crate) name: &'static str,
+    pub(crate) sql: &'static str,
+}
+
+/// The embedded migrations in version order. The migrations are forward
+/// only: an applied migration is never changed, a schema change is a new
+/// migration appended here.
+pub(crate) const MIGRATIONS: [Migration; 3] = [
+    Migration {
+        version: 1,
+        name: "substrate",
+        sql: include_str!("migrations/V0001__substrate.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "block_number_indexes",
+        sql: include_str!("migrations/V0002__block_number_indexes.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "block_completions",
+        sql: include_str!("migrations/V0003__block_completions.sql"),
+    },
+];
+
+/// Create the database of a chain if it's missing and bring its tables
+/// to the latest schema version.
+pub struct PgMigrator {
+    connection: PostgresDataEngineConnection,
+    dbname: String,
+}
+
+impl PgMigrator {
+    pub fn new(connection: &PostgresDataEngineConnection, dbname: &str) -> Self {
+        Self {
+            connection: connection.clone(),
+            dbname: dbname.to_string(),
+        }
+    }
+
+    /// The latest schema version of the embedded migrations.
+    pub fn latest_version() -> i32 {
+        MIGRATIONS.iter().map(|m| m.version).max().unwrap_or(0)
+    }
+
+    pub(crate) fn connection_config(&self, dbname: &str) -> anyhow::Result<tokio_postgres::Config> {
+        self.role_config(
+            dbname,
+            &self.connection.username,
+            Some(&self.connection.password),
+        )
+    }
+
+    /// The config connecting as the role, the password is not sent if
+    /// it's none, e.g. the role authenticated by certificate.
+    pub(crate) fn role_config(
+        &self,
+        dbname: &str,
+        username: &str,
+        password: Option<&str>,
+    ) -> anyhow::Result<tokio_postgres::Config> {
+        let (ssl_mode, _) = tls::pg_ssl_mode(&self.connection)?;
+        let mut connection_config = tokio_postgres::Config::default();
+        connection_config.user(username);
+        if let Some(password) = password {
+            connection_config.password(password);
+        }
+        connection_config.host(&self.connection.host);
+        connection_config.port(self.connection.port);
+        connection_config.dbname(dbname);
+        connection_config.ssl_mode(ssl_mode);
+        Ok(connection_config)
+    }
+
+    async fn connect(&self, dbname: &str) -> anyhow::Result<Client> {
+        let (client, connection) = self
+            .connection_config(dbname)?
+            .connect(tls::pg_tls_connector(&self.connection)?)
+            .await?;
+        let connection_name = self.connection.name.clone();
+        tokio::spawn(async move {
+            if let Err(err) = connection.await {
+                tracing::error!(
+                    "🐛 {}: postgres migrate connection has broken: {}",
+                    connection_name,
+                    err
+                );
+            }
+        });
+        Ok(client)
+    }
+
+    /// Create the database, then apply the pending migrations, return the
+    /// applied versions.
+    pub async fn run(&self) -> anyhow::Result<Vec<i32>> {
+        self.create_database().await?;
+        let mut client = self.connect(&self.dbname).await.map_err(|err| {
+            anyhow!(
+                "{}: connect postgres dbname({}) error: {}",
+                self.connection.name,
+                self.dbname,
+                err
+            )
+        })?;
+
+        client
+            .query_one("SELECT pg_advisory_lock($1)", &[&MIGRATE_LOCK_KEY])
+            .await?;
+        let res = self.migrate(&mut client).await;
+        client
+            .query_one("SELECT pg_advisory_unlock($1)", &[&MIGRATE_LOCK_KEY])
+            .await?;
+        res
+    }
+
+    /// The migrations not applied to the database yet.
+    pub async fn pending(&self) -> anyhow::Result<Vec<(i32, &'static str)>> {
+        let client = self.connect(&self.dbname).await?;
+        let versioned: bool = client
+            .query_one(SCHEMA_VERSION_EXISTS_STMT, &[])
+            .await?
+            .get(0);
+        let applied = match versioned {
+            true => Self::applied_versions(&client).await?,
+            false => vec![],
+        };
+        Ok(pending_migrations(&applied)?
+            .map(|m| (m.version, m.name))
+            .collect())
+    }
+
+    async fn create_database(&self) -> anyhow::Result<()> {
+        let client = self.connect(MAINTENANCE_DBNAME).await.map_err(|err| {
+            anyhow!(
+                "{}: connect postgres dbname({}) error: {}",
+                self.connection.name,
+                MAINTENANCE_DBNAME,
+                err
+            )
+        })?;
+        if client
+            .query_opt(DATABASE_EXISTS_STMT, &[&self.dbname])
+            .await?
+            .is_some()
+        {
+            return Ok(());
+        }
+
+        let stmt = format!("CREATE DATABASE {}", quote_ident(&self.dbname));
+        match client.batch_execute(&stmt).await {
+            Ok(_) => {
+                tracing::info!(
+                    "🏗️ {}: postgres database({}) created",
+                    self.connection.name,
+                    self.dbname
+                );
+                Ok(())
+            }
+            // Created by another node in the meantime.
+            Err(err) if err.code() == Some(&SqlState::DUPLICATE_DATABASE) => Ok(()),
+            Err(err) => Err(anyhow!(
+                "{}: create postgres database({}) error: {}",
+                self.connection.name,
+                self.dbname,
+                err
+            )),
+        }
+    }
+
+    async fn applied_versions(client: &Client) -> anyhow::Result<Vec<i32>> {
+        Ok(client
+            .query(SCHEMA_VERSIONS_STMT, &[])
+            .await?
+            .iter()
+            .map(|row| row.get::<_, i32>(0))
+            .collect())
+    }
+
+    async fn migrate(&self, client: &mut Client) -> anyhow::Result<Vec<i32>> {
+        client.batch_execute(SCHEMA_VERSION_TABLE_STMT).await?;
+        let applied = Self::applied_versions(client).await?;
+
+        let mut versions = vec![];
+        for migration in pending_migrations(&applied)? {
+            let tx = client.transaction().await?;
+            tx.batch_execute(migration.sql).await.map_err(|err| {
+                anyhow!(
+                    "{}: apply migration V{:04}__{} to dbname({}) error: {}",
+                    self.connection.name,
+                    migration.version,
+                    migration.name,
+                    self.dbname,
+                    err
+                )
+            })?;
+            tx.execute(SCHEMA_VERSION_INSERT_STMT, &[
+                &migration.version,
+                &migration.name,
+            ])
+            .await?;
+            tx.commit().await?;
+
+            tracing::info!(
+                "🏗️ {}: postgres dbname({}) migrated to V{:04}__{}",
+                self.connection.name,
+                self.dbname,
+                migration.version,
+                migration.name
+            );
+            versions.push(migration.version);
+        }
+        Ok(versions)
+    }
+}
+
+/// The migrations newer than the applied versions. The schema of a newer
+/// node is refused, since the migrations never go backward.
+fn pending_migrations(applied: &[i32]) -> anyhow::Result<impl Iterator<Item = &'static Migration>> {
+    let current = applied.iter().max().copied().unwrap_or(0);
+    if current > PgMigrator::latest_version() {
+        return Err(anyhow!(
+            "schema version {} is newer than the latest known version {}",
+            current,
+            PgMigrator::latest_version()
+        ));
+    }
+    Ok(MIGRATIONS.iter().filter(move |m| m.version > current))
+}
+
+fn quote_ident(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrations_are_ordered_and_forward_only() {
+        for (i, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, i as i32 + 1);
+            assert!(!migration.sql.to_uppercase().contains("DROP "));
+        }
+        assert_eq!(PgMigrator::latest_version(), MIGRATIONS.len() as i32);
+    }
+
+    #[test]
+    fn test_pending_migrations() {
+        let all = pending_migrations(&[]).unwrap().count();
+        assert_eq!(all, MIGRATIONS.len());
+
+        let pending = pending_migrations(&[1])
+            .unwrap()
+            .map(|m| m.version)
+            .collect::<Vec<_>>();
+        assert_eq!(pending, vec![2, 3]);
+
+        let latest = PgMigrator::latest_version();
+        assert_eq!(pending_migrations(&[latest]).unwrap().count(), 0);
+        assert!(pending_migrations(&[latest + 1]).is_err());
+    }
+
+    #[test]
+    fn test_quote_ident() {
+        assert_eq!(quote_ident("polkadot"), "\"polkadot\"");
+        assert_eq!(quote_ident("a\"b"), "\"a\"\"b\"");
+    }
+}
@@ -0,0 +1,54 @@
+CREATE TABLE IF NOT EXISTS block_logs (
+    id TEXT NOT NULL,
+    block_number bigint NOT NULL,
+    "type" TEXT,
+    "data" TEXT,
+    engine TEXT
+);
+CREATE UNIQUE INDEX IF NOT EXISTS unique_index_block_logs ON block_logs (id);
+
+CREATE TABLE IF NOT EXISTS extrinsics (
+    id TEXT NOT NULL,
+    block_number BIGINT NOT NULL,
+    extrinsic_hash TEXT NOT NULL,
+    is_signed BOOLEAN NOT NULL,
+    mod_name TEXT,
+    call_name TEXT,
+    result BOOLEAN,
+    call_params JSON,
+    extrinsic_hash_bytes BYTEA NOT NULL
+);
+CREATE UNIQUE INDEX IF NOT EXISTS unique_index_extrinsics ON extrinsics (id);
+
+CREATE TABLE IF NOT EXISTS events (
+    id TEXT NOT NULL,
+    block_number BIGINT NOT NULL,
+    extrinsic_id TEXT,
+    mod_name TEXT,
+    event_name TEXT,
+    phase SMALLINT NOT NULL,
+    values JSON
+);
+CREATE UNIQUE INDEX IF NOT EXISTS unique_index_events ON events (id);
+
+CREATE TABLE IF NOT EXISTS blocks (
+    "number" bigint NOT NULL,
+    "timestamp" BIGINT,
+    "hash" TEXT NOT NULL,
+    parent_hash TEXT NOT NULL,
+    extrinsics_root TEXT NOT NULL,
+    state_root TEXT NOT NULL,
+    is_finalized BOOLEAN NOT NULL,
+    validator TEXT,
+    spec_version INT NOT NULL,
+
+    hash_bytes BYTEA NOT NULL,
+    parent_hash_bytes BYTEA NOT NULL,
+    extrinsics_root_bytes BYTEA NOT NULL,
+    state_root_bytes BYTEA NOT NULL,
+    validator_bytes BYTEA,
+    PRIMARY KEY (number)
+);
+
+CREATE INDEX IF NOT EXISTS idx_blocks_hash ON blocks ("hash");
+CREATE INDEX IF NOT EXISTS idx_blocks_parent_hash ON blocks (parent_hash);
@@ -0,0 +1,5 @@
+-- The unfinalized rollback deletes the logs, extrinsics and events by
+-- block number.
+CREATE INDEX IF NOT EXISTS idx_block_logs_block_number ON block_logs (block_number);
+CREATE INDEX IF NOT EXISTS idx_extrinsics_block_number ON extrinsics (block_number);
+CREATE INDEX IF NOT EXISTS idx_events_block_number ON events (block_number);
@@ -0,0 +1,34 @@
+-- The completion marker of each block, written in the same transaction
+-- as the rows of the block. A block without a marker is partially
+-- written, it's reported as a gap so it's written again.
+CREATE TABLE IF NOT EXISTS block_completions (
+    block_number BIGINT NOT NULL,
+    block_hash TEXT NOT NULL,
+    extrinsics BIGINT NOT NULL,
+    events BIGINT NOT NULL,
+    logs BIGINT NOT NULL,
+    completed_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (block_number)
+);
+
+-- The blocks written before are taken as complete, otherwise the whole
+-- stored history is reported as gaps and extracted again on upgrade. The
+-- rows of each table are counted in one pass, not per block.
+INSERT INTO block_completions (block_number, block_hash, extrinsics, events, logs)
+SELECT
+    b."number",
+    b."hash",
+    COALESCE(x.n, 0),
+    COALESCE(e.n, 0),
+    COALESCE(l.n, 0)
+FROM blocks b
+LEFT JOIN (
+    SELECT block_number, count(*) AS n FROM extrinsics GROUP BY block_number
+) x ON x.block_number = b."number"
+LEFT JOIN (
+    SELECT block_number, count(*) AS n FROM events GROUP BY block_number
+) e ON e.block_number = b."number"
+LEFT JOIN (
+    SELECT block_number, count(*) AS n FROM block_logs GROUP BY block_number
+) l ON l.block_number = b."number"
+ON CONFLICT (block_number) DO NOTHING;
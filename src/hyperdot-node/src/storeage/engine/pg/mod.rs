@@ -1,5 +1,9 @@
+mod bulk;
+mod migrate;
 mod pg;
+mod sandbox;
 mod writer;
 
+pub use migrate::PgMigrator;
 pub use pg::ConnectionState;
 pub use pg::PgEngine;
@@ -1,32 +1,128 @@
-use std::any::Any;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::anyhow;
 use anyhow::Context;
+use deadpool_postgres::Manager;
+use deadpool_postgres::ManagerConfig;
+use deadpool_postgres::Object;
+use deadpool_postgres::Pool;
+use deadpool_postgres::RecyclingMethod;
+use deadpool_postgres::Runtime;
+use hyperdot_core::types::ChainKind;
+use hyperdot_core::types::DataEngineKind;
 use hyperdot_core::types::PostgresDataEngine;
 use hyperdot_core::types::PostgresDataEngineConnection;
 use hyperdot_core::types::PostgresDataEngineForChain;
+use hyperdot_core::types::PostgresRows;
+use hyperdot_core::types::PostgresRowsPage;
+use hyperdot_core::types::PostgresTableInfo;
+use hyperdot_core::types::QueryOptions;
 use tokio::sync::RwLock;
-use tokio::task::JoinHandle;
-use tokio_postgres::Client;
-use tokio_postgres::NoTls;
 
+use super::super::engine::ChainBlocks;
 use super::super::engine::DataEngine;
+use super::migrate::PgMigrator;
+use super::sandbox::QuerySandbox;
 use super::writer::SubstrateWriter;
-use crate::types::block::polkadot_chain;
+use crate::storeage::tls;
+use crate::types::rpc::BlockRange;
+
+/// The lowest block stored, the blocks stored are the ones with a
+/// completion marker as in the gaps.
+const BLOCK_MIN_NUMBER_STMT: &'static str = "SELECT MIN(block_number) FROM block_completions";
+
+/// The sentinels `$1 - 1` and `$2 + 1` make the leading and
+/// trailing gaps of the range detected. The blocks without a completion
+/// marker are partially written, so they're reported as gaps.
+const BLOCK_GAPS_STMT: &'static str = r#"
+WITH stored AS (
+    SELECT block_number AS "number" FROM block_completions
+    WHERE block_number BETWEEN $1::BIGINT AND $2::BIGINT
+    UNION ALL SELECT $1::BIGINT - 1
+    UNION ALL SELECT $2::BIGINT + 1
+)
+SELECT "number" + 1 AS gap_start, next_number - 1 AS gap_end FROM (
+    SELECT "number", LEAD("number") OVER (ORDER BY "number") AS next_number FROM stored
+) t
+WHERE next_number - "number" > 1
+ORDER BY gap_start
+"#;
+
+const SCHEMA_TABLES_STMT: &str =
+    "SELECT table_name FROM information_schema.tables WHERE table_schema = 'public'";
+
+const SCHEMA_COLUMNS_STMT: &str =
+    "SELECT column_name, data_type FROM information_schema.columns WHERE table_name = $1";
+
+/// The default max connections of the query pool of a chain.
+const DEFAULT_POOL_SIZE: usize = 8;
+
+/// The default seconds to wait for a pooled connection.
+const DEFAULT_POOL_TIMEOUT_SECS: u64 = 30;
 
 pub struct ConnectionState {
-    pub client: Client,
     pub used_connection: PostgresDataEngineConnection,
     pub support_chain: PostgresDataEngineForChain,
     pub connection_config: tokio_postgres::Config,
-    pub connection_handle: JoinHandle<anyhow::Result<()>>,
+    /// The pool of the queries of the chain.
+    pub pool: Pool,
+    /// The pool of the ad-hoc queries of the query api, connected as the
+    /// `query_username` role. None if it's not configured, the ad-hoc
+    /// queries are refused rather than run as the owner.
+    pub reader: Option<Pool>,
+    /// The pool of the block writes. It holds a single connection, so the
+    /// transactions of the chain are written one by one and never wait
+    /// for the queries.
+    pub writer: Pool,
+}
+
+impl ConnectionState {
+    /// Get a connection for the queries. The connections are verified
+    /// before reused, the broken ones are dropped and reconnected.
+    pub async fn client(&self) -> anyhow::Result<Object> {
+        self.pool.get().await.map_err(|err| {
+            anyhow!(
+                "{}: get postgres connection error: {}",
+                self.support_chain.name,
+                err
+            )
+        })
+    }
+
+    /// Get a connection for the ad-hoc queries.
+    pub async fn reader(&self) -> anyhow::Result<Object> {
+        let reader = self.reader.as_ref().ok_or(anyhow!(
+            "{}: postgres ad-hoc queries refused, connection {} has no query_username",
+            self.support_chain.name,
+            self.used_connection.name
+        ))?;
+        reader.get().await.map_err(|err| {
+            anyhow!(
+                "{}: get postgres query connection error: {}",
+                self.support_chain.name,
+                err
+            )
+        })
+    }
+
+    /// Get the connection for the block writes exclusively.
+    pub async fn writer(&self) -> anyhow::Result<Object> {
+        self.writer.get().await.map_err(|err| {
+            anyhow!(
+                "{}: get postgres writer connection error: {}",
+                self.support_chain.name,
+                err
+            )
+        })
+    }
 }
 
 pub struct PgEngine {
     // support chain name of connection map to state
     connections: RwLock<HashMap<String, Arc<ConnectionState>>>,
+    sandbox: QuerySandbox,
 }
 
 impl PgEngine {
@@ -56,64 +152,144 @@ impl PgEngine {
                 }
                 Some(c) => c,
             };
-            let used_connection_name = used_connection.name.clone();
-
-            let mut connection_config = tokio_postgres::Config::default();
-            connection_config.user(&used_connection.username);
-            connection_config.password(&used_connection.password);
-            connection_config.host(&used_connection.host);
-            connection_config.port(used_connection.port);
-            connection_config.dbname(&support_chain.dbname);
-            let (client, connection) = match connection_config.connect(NoTls).await {
+
+            let migrator = PgMigrator::new(used_connection, &support_chain.dbname);
+            if let Err(err) = migrator.run().await {
+                tracing::error!(
+                    "💔 {}: migrate postgres dbname({}) error: {}",
+                    support_chain.name,
+                    support_chain.dbname,
+                    err
+                );
+                continue;
+            }
+
+            let connection_config = match migrator.connection_config(&support_chain.dbname) {
+                Err(err) => {
+                    tracing::error!(
+                        "💔 {}: connection name = {} config error: {}",
+                        support_chain.name,
+                        used_connection.name,
+                        err
+                    );
+                    continue;
+                }
+                Ok(connection_config) => connection_config,
+            };
+            let pool_size = used_connection.pool_size.unwrap_or(DEFAULT_POOL_SIZE);
+            let pool = match Self::build_pool(&connection_config, used_connection, pool_size) {
                 Err(err) => {
                     tracing::error!(
-                        "💔 {}: connection name = {} connect postgres error: {}",
+                        "💔 {}: connection name = {} build postgres pool error: {}",
                         support_chain.name,
                         used_connection.name,
                         err
                     );
                     continue;
                 }
-                Ok(res) => res,
+                Ok(pool) => pool,
             };
-
-            // TODO: check database tables, if not exists maybe consider init it.
-            let connection_handle = tokio::spawn(async move {
-                // TODO: consider re-connection
-                if let Err(err) = connection.await {
+            let reader = match used_connection.query_username.as_ref() {
+                None => {
+                    tracing::warn!(
+                        "⚠️ {}: connection name = {} has no query_username, the ad-hoc queries are refused",
+                        support_chain.name,
+                        used_connection.name
+                    );
+                    None
+                }
+                Some(query_username) => match migrator
+                    .role_config(
+                        &support_chain.dbname,
+                        query_username,
+                        used_connection.query_password.as_deref(),
+                    )
+                    .and_then(|reader_config| {
+                        Self::build_pool(&reader_config, used_connection, pool_size)
+                    }) {
+                    Err(err) => {
+                        tracing::error!(
+                            "💔 {}: connection name = {} build postgres query pool error: {}",
+                            support_chain.name,
+                            used_connection.name,
+                            err
+                        );
+                        continue;
+                    }
+                    Ok(pool) => Some(pool),
+                },
+            };
+            let writer = match Self::build_pool(&connection_config, used_connection, 1) {
+                Err(err) => {
                     tracing::error!(
-                        "🐛 {}: postgres connection has broken: {}",
-                        used_connection_name,
+                        "💔 {}: connection name = {} build postgres writer pool error: {}",
+                        support_chain.name,
+                        used_connection.name,
                         err
                     );
-                    return Err(anyhow!("{}", err));
+                    continue;
                 }
-                return Ok(());
-            });
+                Ok(pool) => pool,
+            };
+
+            let conn_state = ConnectionState {
+                support_chain: support_chain.clone(),
+                used_connection: used_connection.clone(),
+                connection_config,
+                pool,
+                reader,
+                writer,
+            };
+
+            // The pools connect lazily, check the database is reachable.
+            if let Err(err) = conn_state.writer().await {
+                tracing::error!(
+                    "💔 {}: connection name = {} connect postgres error: {}",
+                    support_chain.name,
+                    used_connection.name,
+                    err
+                );
+                continue;
+            }
 
             tracing::info!(
-                "🙅 {}: postgres data engine connected at dbname({})",
+                "🙅 {}: postgres data engine connected at dbname({}) with pool size {}",
                 support_chain.name,
                 support_chain.dbname,
+                pool_size,
             );
 
-            connections.insert(
-                support_chain.name.to_string(),
-                Arc::new(ConnectionState {
-                    support_chain: support_chain.clone(),
-                    used_connection: used_connection.clone(),
-                    connection_config,
-                    client,
-                    connection_handle,
-                }),
-            );
+            connections.insert(support_chain.name.to_string(), Arc::new(conn_state));
         }
 
         Ok(Self {
             connections: RwLock::new(connections),
+            sandbox: QuerySandbox::new(&engine),
         })
     }
 
+    fn build_pool(
+        connection_config: &tokio_postgres::Config,
+        connection: &PostgresDataEngineConnection,
+        max_size: usize,
+    ) -> anyhow::Result<Pool> {
+        let tls = tls::pg_tls_connector(connection)?;
+        let manager = Manager::from_config(connection_config.clone(), tls, ManagerConfig {
+            recycling_method: RecyclingMethod::Verified,
+        });
+        let timeout = Duration::from_secs(
+            connection
+                .pool_timeout_secs
+                .unwrap_or(DEFAULT_POOL_TIMEOUT_SECS),
+        );
+        Ok(Pool::builder(manager)
+            .max_size(max_size)
+            .runtime(Runtime::Tokio1)
+            .wait_timeout(Some(timeout))
+            .create_timeout(Some(timeout))
+            .build()?)
+    }
+
     pub async fn get_conn_state_for_chain(
         &self,
         chain: &str,
@@ -126,33 +302,48 @@ impl PgEngine {
             Some(conn) => Ok(conn.clone()),
         }
     }
-    pub async fn write_block_internal(
-        &self,
-        chain: &str,
-        block: Box<dyn Any + Send + Sync>,
-    ) -> anyhow::Result<()> {
-        let conn_state = self.get_conn_state_for_chain(chain).await?;
-        let block = block.downcast::<polkadot_chain::Block>().unwrap();
-        SubstrateWriter::write_block(conn_state.clone(), *block).await
-    }
 
-    /// Run query sql for chain.
-    pub async fn query(
+    /// Find the block numbers in `[start, end]` missing from the blocks table
+    /// for chain. If start is none, start from the lowest stored block number.
+    pub async fn block_gaps(
         &self,
         chain: &str,
-        sql: &str,
-    ) -> anyhow::Result<hyperdot_core::types::PostgresRows> {
+        start: Option<u64>,
+        end: u64,
+    ) -> anyhow::Result<Vec<BlockRange>> {
         let conn_state = self.get_conn_state_for_chain(chain).await?;
-        let rows = conn_state.client.query(sql, &[]).await.map_err(|err| {
-            anyhow::anyhow!(
-                "Postgres data engine run sql({}) for chain({}) error:{}",
-                sql,
-                chain,
-                err
-            )
-        })?;
+        let client = conn_state.client().await?;
+        let start = match start {
+            Some(start) => start as i64,
+            None => {
+                let row = client
+                    .query_one(BLOCK_MIN_NUMBER_STMT, &[])
+                    .await
+                    .map_err(|err| anyhow!("{}: query min block number error: {}", chain, err))?;
+                match row.get::<_, Option<i64>>(0) {
+                    // nothing stored, no gaps could be detected.
+                    None => return Ok(vec![]),
+                    Some(start) => start,
+                }
+            }
+        };
 
-        hyperdot_core::types::PostgresRows::try_from(rows)
+        let end = end as i64;
+        if end < start {
+            return Ok(vec![]);
+        }
+
+        let rows = client
+            .query(BLOCK_GAPS_STMT, &[&start, &end])
+            .await
+            .map_err(|err| anyhow!("{}: query block gaps error: {}", chain, err))?;
+        Ok(rows
+            .into_iter()
+            .map(|row| BlockRange {
+                start: row.get::<_, i64>(0) as u64,
+                end: row.get::<_, i64>(1) as u64,
+            })
+            .collect())
     }
 
     // pub async fn write_block_for_polkadot_chain(
@@ -215,21 +406,99 @@ impl DataEngine for PgEngine {
         "Postgres".to_string()
     }
 
-    async fn write_block(
-        &self,
-        chain: String,
-        blocks: Vec<Box<dyn Any + Send + Sync>>,
-    ) -> anyhow::Result<()> {
-        for block in blocks {
-            match self.write_block_internal(&chain, block).await {
-                Err(err) => {
-                    tracing::error!("write block error: {}", err);
-                    continue;
-                }
-                Ok(_) => {}
-            };
+    fn kind(&self) -> DataEngineKind {
+        DataEngineKind::Postgres
+    }
+
+    async fn supports_chain(&self, chain: &str, kind: &ChainKind) -> bool {
+        matches!(kind, ChainKind::Polkadot) && self.connections.read().await.contains_key(chain)
+    }
+
+    /// Write the batch of blocks for chain in one transaction.
+    async fn write_block(&self, chain: &str, blocks: &ChainBlocks) -> anyhow::Result<()> {
+        let conn_state = self.get_conn_state_for_chain(chain).await?;
+        match blocks {
+            ChainBlocks::Polkadot(blocks) => SubstrateWriter::write_blocks(&conn_state, blocks)
+                .await
+                .map_err(|err| anyhow!("{}: write blocks error: {}", chain, err)),
         }
+    }
+
+    async fn schema(&self, chain: &str) -> anyhow::Result<HashMap<String, Vec<PostgresTableInfo>>> {
+        let conn_state = self.get_conn_state_for_chain(chain).await?;
+        let client = conn_state.client().await?;
+        let rows = client
+            .query(SCHEMA_TABLES_STMT, &[])
+            .await
+            .map_err(|err| anyhow!("{}: query tables error: {}", chain, err))?;
 
+        let mut tables = HashMap::new();
+        for row in rows.into_iter() {
+            let table_name: String = row.get(0);
+            let column_rows = client
+                .query(SCHEMA_COLUMNS_STMT, &[&table_name])
+                .await
+                .map_err(|err| {
+                    anyhow!(
+                        "{}: query columns of table({}) error: {}",
+                        chain,
+                        table_name,
+                        err
+                    )
+                })?;
+
+            let columns = column_rows
+                .into_iter()
+                .map(|column_row| PostgresTableInfo {
+                    column_name: column_row.get(0),
+                    data_type: column_row.get(1),
+                })
+                .collect();
+            tables.insert(table_name, columns);
+        }
+        Ok(tables)
+    }
+
+    async fn health(&self) -> anyhow::Result<()> {
+        let connections = self.connections.read().await.clone();
+        for (chain, conn_state) in connections.iter() {
+            conn_state
+                .client()
+                .await?
+                .simple_query("SELECT 1")
+                .await
+                .map_err(|err| anyhow!("{}: postgres not available: {}", chain, err))?;
+        }
         Ok(())
     }
+
+    async fn query(&self, chain: &str, sql: &str) -> anyhow::Result<PostgresRows> {
+        Ok(self
+            .query_page(chain, sql, &QueryOptions::default())
+            .await?
+            .rows)
+    }
+
+    /// Run the ad-hoc sql in a read-only transaction with the limits of the
+    /// engine.
+    async fn query_page(
+        &self,
+        chain: &str,
+        sql: &str,
+        options: &QueryOptions,
+    ) -> anyhow::Result<PostgresRowsPage> {
+        let conn_state = self.get_conn_state_for_chain(chain).await?;
+        let mut client = conn_state.reader().await?;
+        self.sandbox
+            .run(&mut client, &conn_state.used_connection, sql, options)
+            .await
+            .map_err(|err| {
+                anyhow::anyhow!(
+                    "Postgres data engine run sql({}) for chain({}) error:{}",
+                    sql,
+                    chain,
+                    err
+                )
+            })
+    }
 }
@@ -0,0 +1,300 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use hyperdot_core::types::PostgresDataEngine;
+use hyperdot_core::types::PostgresDataEngineConnection;
+use hyperdot_core::types::PostgresRows;
+use hyperdot_core::types::PostgresRowsPage;
+use hyperdot_core::types::QueryOptions;
+use rust_decimal::Decimal;
+use serde_json::Value;
+use tokio_postgres::types::ToSql;
+use tokio_postgres::types::Type;
+use tokio_postgres::Client;
+
+use super::super::sandbox::PageQuery;
+use super::super::sandbox::QueryLimits;
+use crate::storeage::tls;
+
+const DEFAULT_QUERY_STATEMENTS: [&str; 6] =
+    ["SELECT", "WITH", "VALUES", "TABLE", "EXPLAIN", "SHOW"];
+
+/// The statements paged and counted as a subquery.
+const PAGED_STATEMENTS: [&str; 4] = ["SELECT", "WITH", "VALUES", "TABLE"];
+
+/// The extra time given to the server to cancel a query by its
+/// statement_timeout, before the query is canceled by the client.
+const CANCEL_GRACE: Duration = Duration::from_secs(2);
+
+/// The limits of the ad-hoc queries of the query api. The queries run in
+/// a read-only transaction with a statement_timeout, the allowlist only
+/// rejects the unexpected statements early with a clear reason.
+pub(crate) struct QuerySandbox {
+    limits: QueryLimits,
+}
+
+impl QuerySandbox {
+    pub(crate) fn new(engine: &PostgresDataEngine) -> Self {
+        let mut limits = QueryLimits::new(
+            engine.query_timeout_ms,
+            engine.query_max_rows,
+            engine.query_statements.as_ref(),
+            &DEFAULT_QUERY_STATEMENTS,
+        );
+        // Leave room for the extra row fetched to know if there are more
+        // rows.
+        limits.max_rows = limits.max_rows.min(i32::MAX as usize - 1);
+        Self { limits }
+    }
+
+    /// Check the leading keyword of the sql is allowed, return the keyword.
+    pub(crate) fn check(&self, sql: &str) -> anyhow::Result<String> {
+        self.limits.check(sql)
+    }
+
+    /// Run the sql with the bind parameters in a read-only transaction for
+    /// a page of rows, a page has at most `max_rows` rows. The query is
+    /// canceled if the server doesn't stop it by the statement_timeout in
+    /// time.
+    pub(crate) async fn run(
+        &self,
+        client: &mut Client,
+        connection: &PostgresDataEngineConnection,
+        sql: &str,
+        options: &QueryOptions,
+    ) -> anyhow::Result<PostgresRowsPage> {
+        let keyword = self.check(sql)?;
+        let query = PageQuery::new(
+            &keyword,
+            sql,
+            options,
+            self.limits.max_rows,
+            &PAGED_STATEMENTS,
+        );
+        let statements = if query.count.is_some() { 2 } else { 1 };
+        let cancel_token = client.cancel_token();
+        match tokio::time::timeout(
+            self.limits.timeout * statements + CANCEL_GRACE,
+            self.fetch(client, &query, &options.params),
+        )
+        .await
+        {
+            Ok(res) => res,
+            Err(_) => {
+                if let Err(err) = cancel_token
+                    .cancel_query(tls::pg_tls_connector(connection)?)
+                    .await
+                {
+                    tracing::error!(
+                        "🐛 {}: cancel postgres query error: {}",
+                        connection.name,
+                        err
+                    );
+                }
+                Err(anyhow!(
+                    "query canceled after {}ms",
+                    self.limits.timeout.as_millis()
+                ))
+            }
+        }
+    }
+
+    async fn fetch(
+        &self,
+        client: &mut Client,
+        query: &PageQuery,
+        params: &[Value],
+    ) -> anyhow::Result<PostgresRowsPage> {
+        let tx = client
+            .build_transaction()
+            .read_only(true)
+            .start()
+            .await
+            .map_err(db_error)?;
+        tx.batch_execute(&format!(
+            "SET LOCAL statement_timeout = {}",
+            self.limits.timeout.as_millis()
+        ))
+        .await
+        .map_err(db_error)?;
+
+        let stmt = tx.prepare(&query.sql).await.map_err(db_error)?;
+        let params = bind_params(stmt.params(), params)?;
+        let params = params
+            .iter()
+            .map(|param| param.as_ref() as &(dyn ToSql + Sync))
+            .collect::<Vec<_>>();
+
+        let max_rows = query.fetch_rows().min(i32::MAX as usize);
+        let portal = tx.bind(&stmt, &params).await.map_err(db_error)?;
+        let mut rows = tx
+            .query_portal(&portal, max_rows as i32)
+            .await
+            .map_err(db_error)?;
+        rows.drain(..query.skip.min(rows.len()));
+        let has_more = rows.len() > query.limit;
+        rows.truncate(query.limit);
+
+        let total = match query.count.as_ref() {
+            None => None,
+            Some(count) => {
+                let row = tx
+                    .query_one(count.as_str(), &params)
+                    .await
+                    .map_err(db_error)?;
+                Some(row.get::<_, i64>(0) as usize)
+            }
+        };
+        tx.rollback().await.map_err(db_error)?;
+
+        Ok(PostgresRowsPage {
+            rows: PostgresRows::try_from(rows)?,
+            total,
+            has_more,
+        })
+    }
+}
+
+/// The reason of the server, e.g. the statement timeout or the permission
+/// denied.
+fn db_error(err: tokio_postgres::Error) -> anyhow::Error {
+    match err.as_db_error() {
+        Some(db_err) => anyhow!("{}", db_err),
+        None => anyhow!(err),
+    }
+}
+
+/// Convert the json values to the parameter types of the statement.
+fn bind_params(
+    types: &[Type],
+    values: &[Value],
+) -> anyhow::Result<Vec<Box<dyn ToSql + Sync + Send>>> {
+    if types.len() != values.len() {
+        return Err(anyhow!(
+            "query expects {} parameters but got {}",
+            types.len(),
+            values.len()
+        ));
+    }
+    types
+        .iter()
+        .zip(values)
+        .enumerate()
+        .map(|(i, (ty, value))| {
+            to_sql(ty, value).ok_or(anyhow!(
+                "parameter ${} expects {} but got {}",
+                i + 1,
+                ty,
+                value
+            ))
+        })
+        .collect()
+}
+
+/// The json null is bound as the sql null of any type.
+fn to_sql(ty: &Type, value: &Value) -> Option<Box<dyn ToSql + Sync + Send>> {
+    fn boxed<T: ToSql + Sync + Send + 'static>(
+        value: &Value,
+        convert: impl Fn(&Value) -> Option<T>,
+    ) -> Option<Box<dyn ToSql + Sync + Send>> {
+        match value {
+            Value::Null => Some(Box::new(None::<T>)),
+            value => convert(value).map(|v| Box::new(v) as Box<dyn ToSql + Sync + Send>),
+        }
+    }
+
+    match *ty {
+        Type::BOOL => boxed(value, Value::as_bool),
+        Type::INT2 => boxed(value, |v| v.as_i64().and_then(|n| i16::try_from(n).ok())),
+        Type::INT4 => boxed(value, |v| v.as_i64().and_then(|n| i32::try_from(n).ok())),
+        Type::INT8 => boxed(value, Value::as_i64),
+        Type::FLOAT4 => boxed(value, |v| v.as_f64().map(|n| n as f32)),
+        Type::FLOAT8 => boxed(value, Value::as_f64),
+        // The numerics could be out of the range of json numbers, so the
+        // strings are accepted too.
+        Type::NUMERIC => boxed(value, |v| match v {
+            Value::String(s) => Decimal::from_str(s).ok(),
+            Value::Number(n) => Decimal::from_str(&n.to_string()).ok(),
+            _ => None,
+        }),
+        Type::TEXT | Type::VARCHAR | Type::BPCHAR | Type::NAME => {
+            boxed(value, |v| v.as_str().map(str::to_string))
+        }
+        Type::JSON | Type::JSONB => boxed(value, |v| Some(v.clone())),
+        // The hex string with or without `0x`.
+        Type::BYTEA => boxed(value, |v| {
+            v.as_str()
+                .and_then(|s| hex::decode(s.trim_start_matches("0x")).ok())
+        }),
+        Type::INT4_ARRAY => boxed(value, |v| {
+            v.as_array()?
+                .iter()
+                .map(|v| v.as_i64().and_then(|n| i32::try_from(n).ok()))
+                .collect::<Option<Vec<_>>>()
+        }),
+        Type::INT8_ARRAY => boxed(value, |v| {
+            v.as_array()?
+                .iter()
+                .map(Value::as_i64)
+                .collect::<Option<Vec<_>>>()
+        }),
+        Type::TEXT_ARRAY => boxed(value, |v| {
+            v.as_array()?
+                .iter()
+                .map(|v| v.as_str().map(str::to_string))
+                .collect::<Option<Vec<_>>>()
+        }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_sandbox(statements: Option<Vec<&str>>) -> QuerySandbox {
+        QuerySandbox::new(&PostgresDataEngine {
+            connections: vec![],
+            support_chains: vec![],
+            query_timeout_ms: None,
+            query_max_rows: None,
+            query_statements: statements.map(|s| s.iter().map(|s| s.to_string()).collect()),
+        })
+    }
+
+    #[test]
+    fn test_check_statements() {
+        let sandbox = new_sandbox(None);
+        assert!(sandbox.check("SELECT * FROM blocks").is_ok());
+        assert!(sandbox.check("explain select 1").is_ok());
+        assert!(sandbox.check("DROP TABLE blocks").is_err());
+        assert!(sandbox.check("/* select */ DELETE FROM blocks").is_err());
+        assert!(sandbox.check("set statement_timeout = 0").is_err());
+
+        let sandbox = new_sandbox(Some(vec!["select"]));
+        assert!(sandbox.check("select 1").is_ok());
+        assert!(sandbox.check("show server_version").is_err());
+    }
+
+    #[test]
+    fn test_bind_params() {
+        let types = [Type::INT8, Type::TEXT, Type::NUMERIC, Type::INT8_ARRAY];
+        let values = serde_json::json!([7, "polkadot", "1.5", [1, 2]]);
+        assert_eq!(
+            bind_params(&types, values.as_array().unwrap())
+                .unwrap()
+                .len(),
+            4
+        );
+
+        let values = serde_json::json!([null, null, 2, null]);
+        assert!(bind_params(&types, values.as_array().unwrap()).is_ok());
+
+        let values = serde_json::json!(["7", "polkadot", 1, [1]]);
+        assert!(bind_params(&types, values.as_array().unwrap()).is_err());
+        assert!(bind_params(&types, &[]).is_err());
+        assert!(bind_params(&[Type::INT4], &[Value::from(i64::MAX)]).is_err());
+        assert!(bind_params(&[Type::TIMESTAMP], &[Value::from("2023-01-01")]).is_err());
+    }
+}
@@ -4,7 +4,9 @@ use anyhow::anyhow;
 use futures::future::try_join_all;
 use subxt::config::Header;
 use tokio_postgres::types::ToSql;
+use tokio_postgres::Transaction;
 
+use super::bulk::is_conflict;
 use super::pg::ConnectionState;
 use crate::types::block::polkadot_chain;
 
@@ -41,7 +43,46 @@ ON CONFLICT ("number") DO UPDATE
         parent_hash_bytes = excluded.parent_hash_bytes,
         extrinsics_root_bytes = excluded.extrinsics_root_bytes,
         state_root_bytes = excluded.state_root_bytes,
-        validator_bytes = excluded.validator_bytes;
+        validator_bytes = excluded.validator_bytes
+    WHERE excluded.is_finalized OR NOT blocks.is_finalized;
+"#;
+
+/// Delete the unfinalized blocks in `[$1, $2]` and their logs, extrinsics,
+/// events and completion markers, so the orphaned rows of abandoned forks
+/// are rolled back.
+const ROLLBACK_UNFINALIZED_STMTS: [&'static str; 5] = [
+    r#"DELETE FROM block_logs WHERE block_number IN (
+    SELECT "number" FROM blocks WHERE "number" BETWEEN $1 AND $2 AND NOT is_finalized
+)"#,
+    r#"DELETE FROM extrinsics WHERE block_number IN (
+    SELECT "number" FROM blocks WHERE "number" BETWEEN $1 AND $2 AND NOT is_finalized
+)"#,
+    r#"DELETE FROM events WHERE block_number IN (
+    SELECT "number" FROM blocks WHERE "number" BETWEEN $1 AND $2 AND NOT is_finalized
+)"#,
+    r#"DELETE FROM block_completions WHERE block_number IN (
+    SELECT "number" FROM blocks WHERE "number" BETWEEN $1 AND $2 AND NOT is_finalized
+)"#,
+    r#"DELETE FROM blocks WHERE "number" BETWEEN $1 AND $2 AND NOT is_finalized"#,
+];
+
+/// Mark the blocks `$1` completely written, with the rows stored for them.
+/// It's written in the same transaction as the rows, after all of them.
+const BLOCK_COMPLETIONS_UPSERT_STMT: &str = r#"
+INSERT INTO block_completions (block_number, block_hash, extrinsics, events, logs)
+SELECT
+    b."number",
+    b."hash",
+    (SELECT count(*) FROM extrinsics e WHERE e.block_number = b."number"),
+    (SELECT count(*) FROM events e WHERE e.block_number = b."number"),
+    (SELECT count(*) FROM block_logs l WHERE l.block_number = b."number")
+FROM blocks b WHERE b."number" = ANY($1)
+ON CONFLICT (block_number) DO UPDATE
+    SET block_hash = EXCLUDED.block_hash,
+        extrinsics = EXCLUDED.extrinsics,
+        events = EXCLUDED.events,
+        logs = EXCLUDED.logs,
+        completed_at = now()
 "#;
 
 const LOG_UPSERT_STMT: &'static str = r#"
@@ -104,8 +145,36 @@ ON CONFLICT (id) DO UPDATE
 "#;
 
 impl SubstrateWriter {
+    /// Roll back the unfinalized rows replaced by the block. A finalized
+    /// block replaces the unfinalized one at the same number, and an
+    /// unfinalized block replaces the unfinalized ones from its number,
+    /// which were written by an abandoned fork.
+    pub(crate) async fn rollback_unfinalized(
+        tx: &Transaction<'_>,
+        block: &polkadot_chain::Block,
+    ) -> anyhow::Result<()> {
+        let start = block.header.block_number as i64;
+        let end = if block.header.is_finished {
+            start
+        } else {
+            i64::MAX
+        };
+
+        for stmt in ROLLBACK_UNFINALIZED_STMTS.iter() {
+            tx.execute(*stmt, &[&start, &end]).await.map_err(|err| {
+                anyhow!(
+                    "rollback unfinalized blocks from #{} error: {}",
+                    block.header.block_number,
+                    err
+                )
+            })?;
+        }
+
+        Ok(())
+    }
+
     pub(crate) async fn write_header(
-        pg_conn_state: &Arc<ConnectionState>,
+        tx: &Transaction<'_>,
         block: &polkadot_chain::Block,
     ) -> anyhow::Result<()> {
         let validator = block.header.validator.as_ref().map_or(None, |validator| {
@@ -127,8 +196,7 @@ impl SubstrateWriter {
             &block.header.state_root,
             &block.header.validator,
         ];
-        let row = pg_conn_state
-            .client
+        let row = tx
             .execute(BLOCK_UPSERT_STMT, &values)
             .await
             .map_err(|err| anyhow!("insert block #{} error: {}", block.header.block_number, err))?;
@@ -143,7 +211,7 @@ impl SubstrateWriter {
     }
 
     pub(crate) async fn write_log(
-        pg_conn_state: &Arc<ConnectionState>,
+        tx: &Transaction<'_>,
         block: &polkadot_chain::Block,
     ) -> anyhow::Result<()> {
         let logs = match block.logs.as_ref() {
@@ -154,7 +222,7 @@ impl SubstrateWriter {
         // make prepare stmts
         let mut prepare_futs = vec![];
         for i in 0..logs.len() {
-            prepare_futs.push(pg_conn_state.client.prepare(LOG_UPSERT_STMT))
+            prepare_futs.push(tx.prepare(LOG_UPSERT_STMT))
         }
 
         let stmts = try_join_all(prepare_futs).await.map_err(|err| {
@@ -181,17 +249,13 @@ impl SubstrateWriter {
                 &data,
                 &log.engine,
             ];
-            let row = pg_conn_state
-                .client
-                .execute(stmt, &values)
-                .await
-                .map_err(|err| {
-                    anyhow!(
-                        "execute insert logs of block #{} error: {}",
-                        block.header.block_number,
-                        err
-                    )
-                })?;
+            let row = tx.execute(stmt, &values).await.map_err(|err| {
+                anyhow!(
+                    "execute insert logs of block #{} error: {}",
+                    block.header.block_number,
+                    err
+                )
+            })?;
 
             if row == 0 {
                 tracing::debug!("block #{}: log #{} updated", log.block_number, log.id)
@@ -204,7 +268,7 @@ impl SubstrateWriter {
     }
 
     pub(crate) async fn write_extrinsics(
-        pg_conn_state: &Arc<ConnectionState>,
+        tx: &Transaction<'_>,
         block: &polkadot_chain::Block,
     ) -> anyhow::Result<()> {
         let exts = match block.body.extrinsics.as_ref() {
@@ -215,7 +279,7 @@ impl SubstrateWriter {
         // make prepare stmts
         let mut prepare_futs = vec![];
         for i in 0..exts.len() {
-            prepare_futs.push(pg_conn_state.client.prepare(EXTRINSICS_UPSERT_STMT))
+            prepare_futs.push(tx.prepare(EXTRINSICS_UPSERT_STMT))
         }
 
         let stmts = try_join_all(prepare_futs).await.map_err(|err| {
@@ -243,17 +307,13 @@ impl SubstrateWriter {
                 &ext.call_params,
                 &ext.extrinsic_hash,
             ];
-            let row = pg_conn_state
-                .client
-                .execute(stmt, &values)
-                .await
-                .map_err(|err| {
-                    anyhow!(
-                        "execute insert extrinsics of block #{} error: {}",
-                        block.header.block_number,
-                        err
-                    )
-                })?;
+            let row = tx.execute(stmt, &values).await.map_err(|err| {
+                anyhow!(
+                    "execute insert extrinsics of block #{} error: {}",
+                    block.header.block_number,
+                    err
+                )
+            })?;
 
             if row == 0 {
                 tracing::debug!("block #{}: extrinsic #{} updated", ext.block_number, ext.id)
@@ -270,7 +330,7 @@ impl SubstrateWriter {
     }
 
     pub(crate) async fn write_events(
-        pg_conn_state: &Arc<ConnectionState>,
+        tx: &Transaction<'_>,
         block: &polkadot_chain::Block,
     ) -> anyhow::Result<()> {
         let events = match block.body.events.as_ref() {
@@ -281,7 +341,7 @@ impl SubstrateWriter {
         // make prepare stmts
         let mut prepare_futs = vec![];
         for i in 0..events.len() {
-            prepare_futs.push(pg_conn_state.client.prepare(EVENT_UPSERT_STMT))
+            prepare_futs.push(tx.prepare(EVENT_UPSERT_STMT))
         }
 
         let stmts = try_join_all(prepare_futs).await.map_err(|err| {
@@ -305,17 +365,13 @@ impl SubstrateWriter {
                 &(event.phase as i16),
                 &event.values,
             ];
-            let row = pg_conn_state
-                .client
-                .execute(stmt, &values)
-                .await
-                .map_err(|err| {
-                    anyhow!(
-                        "execute insert events of block #{} error: {}",
-                        block.header.block_number,
-                        err
-                    )
-                })?;
+            let row = tx.execute(stmt, &values).await.map_err(|err| {
+                anyhow!(
+                    "execute insert events of block #{} error: {}",
+                    block.header.block_number,
+                    err
+                )
+            })?;
 
             if row == 0 {
                 tracing::debug!("block #{}: event #{} updated", event.block_number, event.id)
@@ -331,14 +387,91 @@ impl SubstrateWriter {
     }
 
     pub(crate) async fn write_block(
-        pg_conn_state: Arc<ConnectionState>,
-        block: polkadot_chain::Block,
+        tx: &Transaction<'_>,
+        block: &polkadot_chain::Block,
     ) -> anyhow::Result<()> {
-        Self::write_header(&pg_conn_state, &block).await?;
-        Self::write_log(&pg_conn_state, &block).await?;
-        Self::write_extrinsics(&pg_conn_state, &block).await?;
-        Self::write_events(&pg_conn_state, &block).await?;
+        Self::rollback_unfinalized(tx, block).await?;
+        Self::write_header(tx, block).await?;
+        Self::write_log(tx, block).await?;
+        Self::write_extrinsics(tx, block).await?;
+        Self::write_events(tx, block).await?;
+        Self::write_completions(tx, &[block.header.block_number as i64])
+            .await
+            .map_err(|err| {
+                anyhow!(
+                    "mark block #{} completed error: {}",
+                    block.header.block_number,
+                    err
+                )
+            })
+    }
 
+    /// Record the completion markers of the blocks, readers take the
+    /// blocks without a marker as not written.
+    pub(crate) async fn write_completions(
+        tx: &Transaction<'_>,
+        numbers: &[i64],
+    ) -> Result<(), tokio_postgres::Error> {
+        tx.execute(BLOCK_COMPLETIONS_UPSERT_STMT, &[&numbers])
+            .await?;
         Ok(())
     }
+
+    /// Write the batch of blocks in one transaction, none of them is
+    /// written if any failed. The blocks are copied in bulk, and upserted
+    /// row by row when the bulk merge conflicts.
+    pub(crate) async fn write_blocks(
+        pg_conn_state: &Arc<ConnectionState>,
+        blocks: &[polkadot_chain::Block],
+    ) -> anyhow::Result<()> {
+        let mut writer = pg_conn_state.writer().await?;
+        let writer: &mut tokio_postgres::Client = &mut writer;
+        let mut tx = writer
+            .transaction()
+            .await
+            .map_err(|err| anyhow!("begin transaction error: {}", err))?;
+
+        // The bulk copy runs in a savepoint, so its writes are undone
+        // before falling back to the row upserts on conflicts.
+        let savepoint = tx
+            .savepoint("bulk_copy")
+            .await
+            .map_err(|err| anyhow!("begin savepoint error: {}", err))?;
+        match Self::copy_blocks(&savepoint, blocks).await {
+            Ok(_) => savepoint
+                .commit()
+                .await
+                .map_err(|err| anyhow!("release savepoint error: {}", err))?,
+            Err(err) if is_conflict(&err) => {
+                tracing::warn!(
+                    "🔁 copy {} blocks conflicts, fallback to upserts: {}",
+                    blocks.len(),
+                    err
+                );
+                savepoint
+                    .rollback()
+                    .await
+                    .map_err(|err| anyhow!("rollback savepoint error: {}", err))?;
+                for block in blocks.iter() {
+                    Self::write_block(&tx, block).await?;
+                }
+            }
+            Err(err) => {
+                return Err(anyhow!(
+                    "copy blocks #{}..#{} error: {}",
+                    blocks.first().map_or(0, |b| b.header.block_number),
+                    blocks.last().map_or(0, |b| b.header.block_number),
+                    err
+                ))
+            }
+        }
+
+        tx.commit().await.map_err(|err| {
+            anyhow!(
+                "commit transaction of {} blocks error: {}",
+                blocks.len(),
+                err
+            )
+        })
+    }
 }
@@ -0,0 +1,252 @@
+//! The limits shared by the ad-hoc queries of the data engines.
+
+use std::time::Duration;
+
+use anyhow::anyhow;
+use hyperdot_core::types::PostgresRows;
+use hyperdot_core::types::PostgresRowsPage;
+use hyperdot_core::types::QueryOptions;
+
+/// The default milliseconds an ad-hoc query could run.
+const DEFAULT_QUERY_TIMEOUT_MS: u64 = 30_000;
+
+/// The default max rows an ad-hoc query returns.
+const DEFAULT_QUERY_MAX_ROWS: usize = 10_000;
+
+/// The limits of the ad-hoc queries of an engine, the engine runs them
+/// read-only, the allowlist of the leading keywords only rejects the
+/// unexpected statements early with a clear reason.
+pub(crate) struct QueryLimits {
+    pub(crate) timeout: Duration,
+    pub(crate) max_rows: usize,
+    statements: Vec<String>,
+}
+
+impl QueryLimits {
+    pub(crate) fn new(
+        timeout_ms: Option<u64>,
+        max_rows: Option<usize>,
+        statements: Option<&Vec<String>>,
+        default_statements: &[&str],
+    ) -> Self {
+        let statements = match statements {
+            Some(statements) => statements.iter().map(|s| s.to_uppercase()).collect(),
+            None => default_statements.iter().map(|s| s.to_string()).collect(),
+        };
+        Self {
+            timeout: Duration::from_millis(timeout_ms.unwrap_or(DEFAULT_QUERY_TIMEOUT_MS)),
+            max_rows: max_rows.unwrap_or(DEFAULT_QUERY_MAX_ROWS),
+            statements,
+        }
+    }
+
+    /// Check the leading keyword of the sql is allowed, return the keyword.
+    pub(crate) fn check(&self, sql: &str) -> anyhow::Result<String> {
+        let keyword = leading_keyword(sql).ok_or(anyhow!("query has no statement"))?;
+        match self.statements.contains(&keyword) {
+            true => Ok(keyword),
+            false => Err(anyhow!(
+                "{} statement not allowed, expect one of {}",
+                keyword,
+                self.statements.join(", ")
+            )),
+        }
+    }
+}
+
+/// The statements of a page of an ad-hoc query. The pages of the paged
+/// statements, e.g. SELECT, are cut and counted by the engine, the rows of
+/// the others are skipped after fetched and never counted.
+pub(crate) struct PageQuery {
+    pub(crate) sql: String,
+    pub(crate) count: Option<String>,
+    pub(crate) skip: usize,
+    pub(crate) limit: usize,
+}
+
+impl PageQuery {
+    pub(crate) fn new(
+        keyword: &str,
+        sql: &str,
+        options: &QueryOptions,
+        max_rows: usize,
+        paged_statements: &[&str],
+    ) -> Self {
+        let limit = options.limit.unwrap_or(max_rows).min(max_rows);
+        if !paged_statements.contains(&keyword) {
+            return Self {
+                sql: sql.to_string(),
+                count: None,
+                skip: options.offset,
+                limit,
+            };
+        }
+
+        // The query is closed on a new line, so a trailing line comment
+        // doesn't comment out the paging.
+        let sql = sql.trim_end().trim_end_matches(';');
+        Self {
+            sql: format!(
+                "SELECT * FROM (\n{}\n) AS page LIMIT {} OFFSET {}",
+                sql,
+                limit + 1,
+                options.offset
+            ),
+            count: options
+                .with_total
+                .then(|| format!("SELECT count(*) FROM (\n{}\n) AS total", sql)),
+            skip: 0,
+            limit,
+        }
+    }
+
+    /// The max rows fetched by `sql`, one more row is fetched to know if
+    /// there are more rows.
+    pub(crate) fn fetch_rows(&self) -> usize {
+        self.skip.saturating_add(self.limit).saturating_add(1)
+    }
+
+    /// Cut the page from the rows fetched by `sql`.
+    pub(crate) fn page(&self, mut rows: PostgresRows, total: Option<usize>) -> PostgresRowsPage {
+        rows.rows.drain(..self.skip.min(rows.rows.len()));
+        let has_more = rows.rows.len() > self.limit;
+        rows.rows.truncate(self.limit);
+        rows.len = rows.rows.len();
+        PostgresRowsPage {
+            rows,
+            total,
+            has_more,
+        }
+    }
+}
+
+/// The first keyword of the sql in uppercase, the leading comments and
+/// parentheses are skipped.
+fn leading_keyword(sql: &str) -> Option<String> {
+    let mut rest = sql;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '(');
+        if let Some(comment) = rest.strip_prefix("--") {
+            rest = comment.split_once('\n').map_or("", |(_, rest)| rest);
+        } else if rest.starts_with("/*") {
+            rest = skip_block_comment(rest)?;
+        } else {
+            break;
+        }
+    }
+
+    let keyword = rest
+        .chars()
+        .take_while(|c| c.is_ascii_alphabetic())
+        .collect::<String>();
+    match keyword.is_empty() {
+        true => None,
+        false => Some(keyword.to_uppercase()),
+    }
+}
+
+/// Skip the block comment at the beginning of the sql, the block comments
+/// of postgres nest.
+fn skip_block_comment(sql: &str) -> Option<&str> {
+    let mut depth = 0;
+    let mut i = 0;
+    let bytes = sql.as_bytes();
+    while i + 1 < bytes.len() {
+        match &bytes[i..i + 2] {
+            b"/*" => {
+                depth += 1;
+                i += 2;
+            }
+            b"*/" => {
+                depth -= 1;
+                i += 2;
+                if depth == 0 {
+                    return Some(&sql[i..]);
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_leading_keyword() {
+        assert_eq!(leading_keyword("select 1").as_deref(), Some("SELECT"));
+        assert_eq!(
+            leading_keyword("  -- drop\n (Select 1)").as_deref(),
+            Some("SELECT")
+        );
+        assert_eq!(
+            leading_keyword("/* a /* nested */ */ with t as (select 1) table t").as_deref(),
+            Some("WITH")
+        );
+        assert_eq!(leading_keyword("/* unclosed select 1"), None);
+        assert_eq!(leading_keyword("-- select 1"), None);
+        assert_eq!(leading_keyword(""), None);
+    }
+
+    #[test]
+    fn test_page_query() {
+        let options = QueryOptions {
+            limit: Some(50),
+            offset: 20,
+            with_total: true,
+            ..Default::default()
+        };
+        let query = PageQuery::new(
+            "SELECT",
+            "select * from blocks -- all\n;\n",
+            &options,
+            10,
+            &["SELECT"],
+        );
+        assert_eq!(
+            query.sql,
+            "SELECT * FROM (\nselect * from blocks -- all\n\n) AS page LIMIT 11 OFFSET 20"
+        );
+        assert_eq!(query.limit, 10);
+        assert_eq!(query.skip, 0);
+        assert_eq!(query.fetch_rows(), 11);
+        assert!(query.count.is_some());
+
+        let query = PageQuery::new("EXPLAIN", "explain select 1", &options, 100, &["SELECT"]);
+        assert_eq!(query.sql, "explain select 1");
+        assert_eq!(query.limit, 50);
+        assert_eq!(query.skip, 20);
+        assert_eq!(query.fetch_rows(), 71);
+        assert!(query.count.is_none());
+
+        let rows = PostgresRows {
+            columns: vec!["n".to_string()],
+            len: 3,
+            column_types: vec![],
+            rows: (0..3)
+                .map(|n| {
+                    let mut row = serde_json::Map::new();
+                    row.insert("n".to_string(), n.into());
+                    row
+                })
+                .collect(),
+        };
+        let query = PageQuery::new(
+            "EXPLAIN",
+            "explain select 1",
+            &QueryOptions {
+                limit: Some(1),
+                offset: 1,
+                ..Default::default()
+            },
+            100,
+            &["SELECT"],
+        );
+        let page = query.page(rows, None);
+        assert_eq!(page.rows.len, 1);
+        assert_eq!(page.rows.rows[0]["n"], 1);
+        assert!(page.has_more);
+    }
+}
@@ -0,0 +1,4 @@
+mod sqlite;
+mod writer;
+
+pub use sqlite::SQLiteEngine;
@@ -0,0 +1,684 @@
+use std::collections::HashMap;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use anyhow::anyhow;
+use hyperdot_core::types::ChainKind;
+use hyperdot_core::types::DataEngineKind;
+use hyperdot_core::types::PostgresColumnDataType;
+use hyperdot_core::types::PostgresRows;
+use hyperdot_core::types::PostgresRowsPage;
+use hyperdot_core::types::PostgresTableInfo;
+use hyperdot_core::types::QueryOptions;
+use hyperdot_core::types::SQLiteDataEngine;
+use rusqlite::types::ValueRef;
+use rusqlite::Connection;
+use rusqlite::OpenFlags;
+
+use super::super::engine::ChainBlocks;
+use super::super::engine::DataEngine;
+use super::super::sandbox::PageQuery;
+use super::super::sandbox::QueryLimits;
+use super::writer::SubstrateWriter;
+use super::writer::SUBSTRATE_SCHEMA;
+use crate::types::rpc::BlockRange;
+
+const PRAGMAS: &str = r#"
+PRAGMA journal_mode = WAL;
+PRAGMA synchronous = NORMAL;
+"#;
+
+const DEFAULT_QUERY_STATEMENTS: [&str; 4] = ["SELECT", "WITH", "VALUES", "EXPLAIN"];
+
+/// The statements paged and counted as a subquery.
+const PAGED_STATEMENTS: [&str; 3] = ["SELECT", "WITH", "VALUES"];
+
+/// The sequence of the in-memory databases, so each engine has its own.
+static MEMORY_DATABASES: AtomicUsize = AtomicUsize::new(0);
+
+const BLOCK_MIN_NUMBER_STMT: &str = r#"SELECT MIN("number") FROM blocks"#;
+
+/// The same as the postgres engine, the sentinels `?1 - 1` and `?2 + 1`
+/// make the leading and trailing gaps of the range detected.
+const BLOCK_GAPS_STMT: &str = r#"
+WITH stored AS (
+    SELECT "number" FROM blocks WHERE "number" BETWEEN ?1 AND ?2
+    UNION ALL SELECT ?1 - 1
+    UNION ALL SELECT ?2 + 1
+)
+SELECT "number" + 1 AS gap_start, next_number - 1 AS gap_end FROM (
+    SELECT "number", LEAD("number") OVER (ORDER BY "number") AS next_number FROM stored
+) t
+WHERE next_number - "number" > 1
+ORDER BY gap_start
+"#;
+
+const SCHEMA_COLUMNS_STMT: &str = r#"
+SELECT m.name, p.name, p.type FROM sqlite_master m, pragma_table_info(m.name) p
+WHERE m.type = 'table'
+ORDER BY m.name, p.cid
+"#;
+
+/// The embedded sqlite engine, a database per chain. It needs no server,
+/// so the storage node runs from files alone, e.g. in tests. The
+/// connection is synchronous, so it's used in blocking threads.
+///
+/// The ad-hoc queries run on their own read-only connections, they never
+/// wait for the writes and are interrupted on timeout.
+pub struct SQLiteEngine {
+    connections: HashMap<String, Arc<Mutex<Connection>>>,
+    /// The database uris of chains, opened read-only for the queries.
+    uris: HashMap<String, String>,
+    limits: QueryLimits,
+}
+
+impl SQLiteEngine {
+    pub async fn new(engine: SQLiteDataEngine) -> anyhow::Result<Self> {
+        let mut connections = HashMap::new();
+        let mut uris = HashMap::new();
+        for support_chain in engine.support_chains.iter() {
+            if !support_chain.enabled {
+                tracing::info!(
+                    "💁 {}: skipped not enabled for sqlite data engine",
+                    support_chain.name
+                );
+                continue;
+            }
+
+            // The in-memory database is shared by the connections of the
+            // engine by name.
+            let uri = match support_chain.path.as_str() {
+                ":memory:" => format!(
+                    "file:hyperdot-{}-{}?mode=memory&cache=shared",
+                    support_chain.name,
+                    MEMORY_DATABASES.fetch_add(1, Ordering::Relaxed)
+                ),
+                path => format!("file:{}", path),
+            };
+            let conn_uri = uri.clone();
+            let conn = tokio::task::spawn_blocking(move || -> anyhow::Result<Connection> {
+                let conn = Connection::open_with_flags(
+                    &conn_uri,
+                    OpenFlags::default() | OpenFlags::SQLITE_OPEN_URI,
+                )?;
+                conn.execute_batch(PRAGMAS)?;
+                conn.execute_batch(SUBSTRATE_SCHEMA)?;
+                Ok(conn)
+            })
+            .await?
+            .map_err(|err| {
+                anyhow!(
+                    "{}: open sqlite({}) error: {}",
+                    support_chain.name,
+                    support_chain.path,
+                    err
+                )
+            })?;
+
+            tracing::info!(
+                "🪶 {}: sqlite data engine opened at {}",
+                support_chain.name,
+                support_chain.path,
+            );
+            connections.insert(support_chain.name.clone(), Arc::new(Mutex::new(conn)));
+            uris.insert(support_chain.name.clone(), uri);
+        }
+
+        Ok(Self {
+            connections,
+            uris,
+            limits: QueryLimits::new(
+                engine.query_timeout_ms,
+                engine.query_max_rows,
+                engine.query_statements.as_ref(),
+                &DEFAULT_QUERY_STATEMENTS,
+            ),
+        })
+    }
+
+    /// Run the function with the connection of chain in a blocking thread.
+    async fn with_conn<T, F>(&self, chain: &str, f: F) -> anyhow::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> anyhow::Result<T> + Send + 'static,
+    {
+        let conn = self
+            .connections
+            .get(chain)
+            .cloned()
+            .ok_or(anyhow!("SQLite not support chain({})", chain))?;
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn.lock().unwrap();
+            f(&mut conn)
+        })
+        .await?
+    }
+
+    /// Run the function with a new read-only connection of chain in a
+    /// blocking thread, it's interrupted on timeout.
+    async fn with_reader<T, F>(&self, chain: &str, f: F) -> anyhow::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> anyhow::Result<T> + Send + 'static,
+    {
+        let uri = self
+            .uris
+            .get(chain)
+            .ok_or(anyhow!("SQLite not support chain({})", chain))?;
+        let conn = Connection::open_with_flags(
+            uri,
+            OpenFlags::SQLITE_OPEN_READ_ONLY
+                | OpenFlags::SQLITE_OPEN_URI
+                | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+        conn.pragma_update(None, "query_only", true)?;
+        let interrupt = conn.get_interrupt_handle();
+        let mut task = tokio::task::spawn_blocking(move || f(&conn));
+        match tokio::time::timeout(self.limits.timeout, &mut task).await {
+            Ok(result) => result?,
+            Err(_) => {
+                interrupt.interrupt();
+                let _ = task.await;
+                Err(anyhow!(
+                    "query canceled after {}ms",
+                    self.limits.timeout.as_millis()
+                ))
+            }
+        }
+    }
+
+    /// Find the block numbers in `[start, end]` missing from the blocks table
+    /// for chain. If start is none, start from the lowest stored block number.
+    pub async fn block_gaps(
+        &self,
+        chain: &str,
+        start: Option<u64>,
+        end: u64,
+    ) -> anyhow::Result<Vec<BlockRange>> {
+        self.with_conn(chain, move |conn| {
+            let start = match start {
+                Some(start) => start as i64,
+                None => {
+                    match conn.query_row(BLOCK_MIN_NUMBER_STMT, [], |row| {
+                        row.get::<_, Option<i64>>(0)
+                    })? {
+                        // nothing stored, no gaps could be detected.
+                        None => return Ok(vec![]),
+                        Some(start) => start,
+                    }
+                }
+            };
+
+            let end = end as i64;
+            if end < start {
+                return Ok(vec![]);
+            }
+
+            let mut stmt = conn.prepare(BLOCK_GAPS_STMT)?;
+            let gaps = stmt
+                .query_map([start, end], |row| {
+                    Ok(BlockRange {
+                        start: row.get::<_, i64>(0)? as u64,
+                        end: row.get::<_, i64>(1)? as u64,
+                    })
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(gaps)
+        })
+        .await
+        .map_err(|err| anyhow!("{}: query block gaps error: {}", chain, err))
+    }
+}
+
+/// Map the declared type of column to the postgres column type, by the
+/// affinity rules of sqlite.
+fn column_type(decl_type: &str) -> PostgresColumnDataType {
+    let decl_type = decl_type.to_uppercase();
+    if decl_type == "BOOLEAN" {
+        PostgresColumnDataType::BOOL
+    } else if decl_type == "SMALLINT" {
+        PostgresColumnDataType::SMALLINT
+    } else if decl_type.contains("INT") {
+        PostgresColumnDataType::BIGINT
+    } else if decl_type.contains("CHAR") || decl_type.contains("CLOB") || decl_type.contains("TEXT")
+    {
+        PostgresColumnDataType::TEXT
+    } else if decl_type.contains("BLOB") {
+        PostgresColumnDataType::BYTEA
+    } else if decl_type.contains("REAL") || decl_type.contains("FLOA") || decl_type.contains("DOUB")
+    {
+        PostgresColumnDataType::Float8
+    } else if decl_type.contains("NUMERIC") || decl_type.contains("DECIMAL") {
+        PostgresColumnDataType::NUMERIC
+    } else {
+        PostgresColumnDataType::Invalid
+    }
+}
+
+/// The column type of the value, for the columns of expressions which
+/// have no declared type.
+fn value_type(value: ValueRef<'_>) -> PostgresColumnDataType {
+    match value {
+        ValueRef::Null => PostgresColumnDataType::Invalid,
+        ValueRef::Integer(_) => PostgresColumnDataType::BIGINT,
+        ValueRef::Real(_) => PostgresColumnDataType::Float8,
+        ValueRef::Text(_) => PostgresColumnDataType::TEXT,
+        ValueRef::Blob(_) => PostgresColumnDataType::BYTEA,
+    }
+}
+
+/// Convert the value to json in the same way as postgres rows.
+fn json_value(value: ValueRef<'_>, column_type: &PostgresColumnDataType) -> serde_json::Value {
+    use serde_json::Value;
+    match (value, column_type) {
+        (ValueRef::Null, _) => Value::Null,
+        (ValueRef::Integer(v), PostgresColumnDataType::BOOL) => Value::Bool(v != 0),
+        (ValueRef::Integer(v), _) => Value::from(v),
+        (ValueRef::Real(v), _) => Value::from(v),
+        (ValueRef::Text(v), _) => Value::String(String::from_utf8_lossy(v).to_string()),
+        (ValueRef::Blob(v), _) => Value::Array(v.iter().map(|b| Value::from(*b)).collect()),
+    }
+}
+
+/// Query at most `max_rows` rows, the rest are dropped.
+fn query_rows(conn: &Connection, sql: &str, max_rows: usize) -> anyhow::Result<PostgresRows> {
+    let mut stmt = conn.prepare(sql)?;
+    let columns = stmt
+        .column_names()
+        .into_iter()
+        .map(|name| name.to_string())
+        .collect::<Vec<_>>();
+    let mut column_types = stmt
+        .columns()
+        .iter()
+        .map(|column| column.decl_type().map(column_type))
+        .collect::<Vec<_>>();
+
+    let mut rows = vec![];
+    let mut query = stmt.query([])?;
+    while rows.len() < max_rows {
+        let row = match query.next()? {
+            None => break,
+            Some(row) => row,
+        };
+        let mut values = serde_json::Map::new();
+        for (i, column) in columns.iter().enumerate() {
+            let value = row.get_ref(i)?;
+            if column_types[i].is_none() && !matches!(value, ValueRef::Null) {
+                column_types[i] = Some(value_type(value));
+            }
+            let column_type = column_types[i]
+                .as_ref()
+                .unwrap_or(&PostgresColumnDataType::Invalid);
+            values.insert(column.clone(), json_value(value, column_type));
+        }
+        rows.push(values);
+    }
+
+    Ok(PostgresRows {
+        columns,
+        len: rows.len(),
+        column_types: column_types
+            .into_iter()
+            .map(|column_type| column_type.unwrap_or_default())
+            .collect(),
+        rows,
+    })
+}
+
+#[async_trait::async_trait]
+impl DataEngine for SQLiteEngine {
+    fn name(&self) -> String {
+        "SQLite".to_string()
+    }
+
+    fn kind(&self) -> DataEngineKind {
+        DataEngineKind::SQLite
+    }
+
+    async fn supports_chain(&self, chain: &str, kind: &ChainKind) -> bool {
+        matches!(kind, ChainKind::Polkadot) && self.connections.contains_key(chain)
+    }
+
+    /// Write the batch of blocks for chain in one transaction.
+    async fn write_block(&self, chain: &str, blocks: &ChainBlocks) -> anyhow::Result<()> {
+        let blocks = blocks.clone();
+        self.with_conn(chain, move |conn| match &blocks {
+            ChainBlocks::Polkadot(blocks) => SubstrateWriter::write_blocks(conn, blocks),
+        })
+        .await
+        .map_err(|err| anyhow!("{}: write blocks error: {}", chain, err))
+    }
+
+    async fn schema(&self, chain: &str) -> anyhow::Result<HashMap<String, Vec<PostgresTableInfo>>> {
+        self.with_conn(chain, |conn| {
+            let mut stmt = conn.prepare(SCHEMA_COLUMNS_STMT)?;
+            let mut rows = stmt.query([])?;
+            let mut tables: HashMap<String, Vec<PostgresTableInfo>> = HashMap::new();
+            while let Some(row) = rows.next()? {
+                tables
+                    .entry(row.get(0)?)
+                    .or_default()
+                    .push(PostgresTableInfo {
+                        column_name: row.get(1)?,
+                        data_type: row.get(2)?,
+                    });
+            }
+            Ok(tables)
+        })
+        .await
+    }
+
+    async fn health(&self) -> anyhow::Result<()> {
+        for chain in self.connections.keys() {
+            self.with_conn(chain, |conn| {
+                conn.query_row("SELECT 1", [], |_| Ok(()))?;
+                Ok(())
+            })
+            .await
+            .map_err(|err| anyhow!("{}: sqlite not available: {}", chain, err))?;
+        }
+        Ok(())
+    }
+
+    async fn query(&self, chain: &str, sql: &str) -> anyhow::Result<PostgresRows> {
+        let sql = sql.to_string();
+        let max_rows = self.limits.max_rows;
+        async {
+            self.limits.check(&sql)?;
+            self.with_reader(chain, move |conn| query_rows(conn, &sql, max_rows))
+                .await
+        }
+        .await
+        .map_err(|err| {
+            anyhow!(
+                "SQLite data engine run sql for chain({}) error:{}",
+                chain,
+                err
+            )
+        })
+    }
+
+    async fn query_page(
+        &self,
+        chain: &str,
+        sql: &str,
+        options: &QueryOptions,
+    ) -> anyhow::Result<PostgresRowsPage> {
+        if !options.params.is_empty() {
+            return Err(anyhow!(
+                "{}: {} data engine not support query parameters",
+                chain,
+                self.name()
+            ));
+        }
+
+        async {
+            let keyword = self.limits.check(sql)?;
+            let query = PageQuery::new(
+                &keyword,
+                sql,
+                options,
+                self.limits.max_rows,
+                &PAGED_STATEMENTS,
+            );
+            self.with_reader(chain, move |conn| {
+                let rows = query_rows(conn, &query.sql, query.fetch_rows())?;
+                let total = match query.count.as_ref() {
+                    None => None,
+                    Some(count) => Some(conn.query_row(count, [], |row| row.get::<_, i64>(0))?),
+                };
+                Ok(query.page(rows, total.map(|total| total as usize)))
+            })
+            .await
+        }
+        .await
+        .map_err(|err| {
+            anyhow!(
+                "SQLite data engine run sql for chain({}) error:{}",
+                chain,
+                err
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyperdot_core::types::SQLiteDataEngineForChain;
+
+    use super::*;
+    use crate::storeage::engine::testing::new_block;
+
+    #[tokio::test]
+    async fn test_sqlite_write_and_query() {
+        let engine = SQLiteEngine::new(SQLiteDataEngine {
+            support_chains: vec![SQLiteDataEngineForChain {
+                name: "polkadot".to_string(),
+                path: ":memory:".to_string(),
+                enabled: true,
+            }],
+            query_timeout_ms: None,
+            query_max_rows: None,
+            query_statements: None,
+        })
+        .await
+        .unwrap();
+        assert!(
+            engine
+                .supports_chain("polkadot", &ChainKind::Polkadot)
+                .await
+        );
+        assert!(!engine.supports_chain("kusama", &ChainKind::Polkadot).await);
+        engine.health().await.unwrap();
+        assert!(engine
+            .block_gaps("polkadot", None, 10)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let blocks = ChainBlocks::Polkadot(vec![
+            new_block(1, true),
+            new_block(2, true),
+            new_block(4, true),
+            new_block(6, false),
+        ]);
+        engine.write_block("polkadot", &blocks).await.unwrap();
+        // Written again is idempotent.
+        engine.write_block("polkadot", &blocks).await.unwrap();
+
+        let rows = engine
+            .query(
+                "polkadot",
+                r#"SELECT b."number", b.is_finalized, b.hash_bytes, count(e.id) AS events FROM blocks b
+                JOIN events e ON e.block_number = b."number"
+                GROUP BY b."number" ORDER BY b."number""#,
+            )
+            .await
+            .unwrap();
+        assert_eq!(rows.len, 4);
+        assert_eq!(rows.columns, vec![
+            "number",
+            "is_finalized",
+            "hash_bytes",
+            "events"
+        ]);
+        assert!(matches!(rows.column_types[..], [
+            PostgresColumnDataType::BIGINT,
+            PostgresColumnDataType::BOOL,
+            PostgresColumnDataType::BYTEA,
+            PostgresColumnDataType::BIGINT
+        ]));
+        assert_eq!(rows.rows[1]["number"], serde_json::json!(2));
+        assert_eq!(rows.rows[1]["is_finalized"], serde_json::json!(true));
+        assert_eq!(
+            rows.rows[1]["hash_bytes"],
+            serde_json::json!(vec![2_u8; 32])
+        );
+        assert_eq!(rows.rows[3]["is_finalized"], serde_json::json!(false));
+
+        let gaps = engine.block_gaps("polkadot", None, 8).await.unwrap();
+        assert_eq!(
+            gaps.iter().map(|r| (r.start, r.end)).collect::<Vec<_>>(),
+            vec![(3, 3), (5, 5), (7, 8)]
+        );
+
+        // The unfinalized block of a fork rolls back the unfinalized blocks
+        // after it.
+        engine
+            .write_block(
+                "polkadot",
+                &ChainBlocks::Polkadot(vec![new_block(5, false)]),
+            )
+            .await
+            .unwrap();
+        let rows = engine
+            .query("polkadot", "SELECT count(*) AS events FROM events")
+            .await
+            .unwrap();
+        assert_eq!(rows.rows[0]["events"], serde_json::json!(4));
+
+        // The stale unfinalized block never replaces the finalized one.
+        let mut stale = new_block(2, false);
+        stale.header.block_hash = vec![0xff; 32];
+        engine
+            .write_block("polkadot", &ChainBlocks::Polkadot(vec![stale]))
+            .await
+            .unwrap();
+        let rows = engine
+            .query(
+                "polkadot",
+                r#"SELECT is_finalized, hash_bytes FROM blocks WHERE "number" = 2"#,
+            )
+            .await
+            .unwrap();
+        assert_eq!(rows.rows[0]["is_finalized"], serde_json::json!(true));
+        assert_eq!(
+            rows.rows[0]["hash_bytes"],
+            serde_json::json!(vec![2_u8; 32])
+        );
+        // Nor rolls back the unfinalized blocks after it.
+        let rows = engine
+            .query("polkadot", "SELECT count(*) AS events FROM events")
+            .await
+            .unwrap();
+        assert_eq!(rows.rows[0]["events"], serde_json::json!(4));
+
+        let tables = engine.schema("polkadot").await.unwrap();
+        assert_eq!(tables.len(), 4);
+        assert!(tables["blocks"]
+            .iter()
+            .any(|column| column.column_name == "spec_version"));
+        assert!(engine.query("kusama", "SELECT 1").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_query_sandbox() {
+        let dir = std::env::temp_dir().join(format!("hyperdot-sqlite-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let engine = SQLiteEngine::new(SQLiteDataEngine {
+            support_chains: vec![SQLiteDataEngineForChain {
+                name: "polkadot".to_string(),
+                path: dir.join("polkadot.db").to_string_lossy().to_string(),
+                enabled: true,
+            }],
+            query_timeout_ms: Some(200),
+            query_max_rows: Some(2),
+            query_statements: Some(vec![
+                "SELECT".to_string(),
+                "WITH".to_string(),
+                "DELETE".to_string(),
+            ]),
+        })
+        .await
+        .unwrap();
+        let blocks = ChainBlocks::Polkadot(vec![
+            new_block(1, true),
+            new_block(2, true),
+            new_block(3, true),
+        ]);
+        engine.write_block("polkadot", &blocks).await.unwrap();
+
+        // Not allowed statements.
+        assert!(engine.query("polkadot", "DROP TABLE blocks").await.is_err());
+        let attached = dir.join("attached.db");
+        assert!(engine
+            .query(
+                "polkadot",
+                &format!("ATTACH DATABASE '{}' AS x", attached.display())
+            )
+            .await
+            .is_err());
+        // Allowed but read-only.
+        assert!(engine
+            .query("polkadot", "DELETE FROM blocks")
+            .await
+            .is_err());
+        // Only the first statement runs.
+        engine
+            .query("polkadot", "SELECT 1; DELETE FROM blocks")
+            .await
+            .unwrap();
+        let rows = engine
+            .query("polkadot", "SELECT count(*) AS n FROM blocks")
+            .await
+            .unwrap();
+        assert_eq!(rows.rows[0]["n"], serde_json::json!(3));
+        assert!(!attached.exists());
+
+        let err = engine
+            .query(
+                "polkadot",
+                "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n) SELECT count(*) FROM n",
+            )
+            .await
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("canceled"), "{}", err);
+
+        // The rows over the max are dropped.
+        let rows = engine
+            .query(
+                "polkadot",
+                r#"SELECT "number" FROM blocks ORDER BY "number""#,
+            )
+            .await
+            .unwrap();
+        assert_eq!(rows.len, 2);
+
+        // The page is capped by the max rows too.
+        let page = engine
+            .query_page(
+                "polkadot",
+                r#"SELECT "number" FROM blocks ORDER BY "number""#,
+                &QueryOptions {
+                    limit: Some(5),
+                    with_total: true,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(page.rows.len, 2);
+        assert_eq!(page.total, Some(3));
+        assert!(page.has_more);
+        let page = engine
+            .query_page(
+                "polkadot",
+                r#"SELECT "number" FROM blocks ORDER BY "number""#,
+                &QueryOptions {
+                    offset: 2,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(page.rows.len, 1);
+        assert_eq!(page.rows.rows[0]["number"], serde_json::json!(3));
+        assert!(!page.has_more);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,342 @@
+use anyhow::anyhow;
+use rusqlite::params;
+use rusqlite::OptionalExtension;
+use rusqlite::Transaction;
+
+use crate::types::block::polkadot_chain;
+
+/// The substrate schema in sqlite types, the json values are stored as
+/// text and the booleans as integers.
+pub(crate) const SUBSTRATE_SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS blocks (
+    "number" INTEGER PRIMARY KEY,
+    "timestamp" INTEGER,
+    "hash" TEXT NOT NULL,
+    parent_hash TEXT NOT NULL,
+    extrinsics_root TEXT NOT NULL,
+    state_root TEXT NOT NULL,
+    is_finalized BOOLEAN NOT NULL,
+    validator TEXT,
+    spec_version INTEGER NOT NULL,
+    hash_bytes BLOB NOT NULL,
+    parent_hash_bytes BLOB NOT NULL,
+    extrinsics_root_bytes BLOB NOT NULL,
+    state_root_bytes BLOB NOT NULL,
+    validator_bytes BLOB
+);
+
+CREATE TABLE IF NOT EXISTS extrinsics (
+    id TEXT PRIMARY KEY,
+    block_number INTEGER NOT NULL,
+    extrinsic_hash TEXT NOT NULL,
+    is_signed BOOLEAN NOT NULL,
+    mod_name TEXT,
+    call_name TEXT,
+    result BOOLEAN,
+    call_params TEXT,
+    extrinsic_hash_bytes BLOB NOT NULL
+);
+CREATE INDEX IF NOT EXISTS extrinsics_block_number_idx ON extrinsics (block_number);
+
+CREATE TABLE IF NOT EXISTS events (
+    id TEXT PRIMARY KEY,
+    block_number INTEGER NOT NULL,
+    extrinsic_id TEXT,
+    mod_name TEXT,
+    event_name TEXT,
+    phase SMALLINT NOT NULL,
+    "values" TEXT
+);
+CREATE INDEX IF NOT EXISTS events_block_number_idx ON events (block_number);
+
+CREATE TABLE IF NOT EXISTS block_logs (
+    id TEXT PRIMARY KEY,
+    block_number INTEGER NOT NULL,
+    "type" TEXT,
+    "data" TEXT,
+    engine TEXT
+);
+CREATE INDEX IF NOT EXISTS block_logs_block_number_idx ON block_logs (block_number);
+"#;
+
+/// Upsert the block, an unfinalized block never replaces the finalized one
+/// at the same number.
+const BLOCK_UPSERT_STMT: &str = r#"
+INSERT INTO blocks (
+    "number",
+    "timestamp",
+    "hash",
+    parent_hash,
+    extrinsics_root,
+    state_root,
+    is_finalized,
+    validator,
+    spec_version,
+    hash_bytes,
+    parent_hash_bytes,
+    extrinsics_root_bytes,
+    state_root_bytes,
+    validator_bytes
+) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+ON CONFLICT ("number") DO UPDATE
+    SET
+        "timestamp" = excluded."timestamp",
+        "hash" = excluded."hash",
+        parent_hash = excluded.parent_hash,
+        extrinsics_root = excluded.extrinsics_root,
+        state_root = excluded.state_root,
+        is_finalized = excluded.is_finalized,
+        validator = excluded.validator,
+        spec_version = excluded.spec_version,
+        hash_bytes = excluded.hash_bytes,
+        parent_hash_bytes = excluded.parent_hash_bytes,
+        extrinsics_root_bytes = excluded.extrinsics_root_bytes,
+        state_root_bytes = excluded.state_root_bytes,
+        validator_bytes = excluded.validator_bytes
+    WHERE excluded.is_finalized OR NOT blocks.is_finalized
+"#;
+
+const LOG_UPSERT_STMT: &str = r#"
+INSERT OR REPLACE INTO block_logs (id, block_number, "type", "data", engine)
+VALUES (?, ?, ?, ?, ?)
+"#;
+
+const EXTRINSICS_UPSERT_STMT: &str = r#"
+INSERT OR REPLACE INTO extrinsics (
+    id,
+    block_number,
+    extrinsic_hash,
+    is_signed,
+    mod_name,
+    call_name,
+    result,
+    call_params,
+    extrinsic_hash_bytes
+) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+"#;
+
+const EVENT_UPSERT_STMT: &str = r#"
+INSERT OR REPLACE INTO events (
+    id,
+    block_number,
+    extrinsic_id,
+    mod_name,
+    event_name,
+    phase,
+    "values"
+) VALUES (?, ?, ?, ?, ?, ?, ?)
+"#;
+
+/// Delete the unfinalized blocks in `[$1, $2]` and their logs, extrinsics
+/// and events, so the orphaned rows of abandoned forks are rolled back.
+const ROLLBACK_UNFINALIZED_STMTS: [&str; 4] = [
+    r#"DELETE FROM block_logs WHERE block_number IN (
+    SELECT "number" FROM blocks WHERE "number" BETWEEN ? AND ? AND NOT is_finalized
+)"#,
+    r#"DELETE FROM extrinsics WHERE block_number IN (
+    SELECT "number" FROM blocks WHERE "number" BETWEEN ? AND ? AND NOT is_finalized
+)"#,
+    r#"DELETE FROM events WHERE block_number IN (
+    SELECT "number" FROM blocks WHERE "number" BETWEEN ? AND ? AND NOT is_finalized
+)"#,
+    r#"DELETE FROM blocks WHERE "number" BETWEEN ? AND ? AND NOT is_finalized"#,
+];
+
+fn hex_string(bytes: &[u8]) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
+
+pub(crate) struct SubstrateWriter;
+
+impl SubstrateWriter {
+    /// Roll back the unfinalized rows replaced by the block. A finalized
+    /// block replaces the unfinalized one at its number, and an unfinalized
+    /// block replaces the unfinalized ones from its number. The finalized
+    /// rows are never deleted.
+    fn rollback_unfinalized(
+        tx: &Transaction<'_>,
+        block: &polkadot_chain::Block,
+    ) -> anyhow::Result<()> {
+        let start = block.header.block_number as i64;
+        let end = if block.header.is_finished {
+            start
+        } else {
+            i64::MAX
+        };
+
+        for stmt in ROLLBACK_UNFINALIZED_STMTS.iter() {
+            tx.execute(stmt, params![start, end]).map_err(|err| {
+                anyhow!(
+                    "rollback unfinalized blocks from #{} error: {}",
+                    block.header.block_number,
+                    err
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Checks if the block is unfinalized but a finalized one is stored at
+    /// its number, the stale block is skipped.
+    fn is_stale(tx: &Transaction<'_>, block: &polkadot_chain::Block) -> anyhow::Result<bool> {
+        if block.header.is_finished {
+            return Ok(false);
+        }
+
+        let finalized = tx
+            .query_row(
+                r#"SELECT is_finalized FROM blocks WHERE "number" = ?"#,
+                params![block.header.block_number as i64],
+                |row| row.get::<_, bool>(0),
+            )
+            .optional()
+            .map_err(|err| anyhow!("query block #{} error: {}", block.header.block_number, err))?;
+        Ok(finalized.unwrap_or(false))
+    }
+
+    fn write_header(tx: &Transaction<'_>, block: &polkadot_chain::Block) -> anyhow::Result<()> {
+        let header = &block.header;
+        tx.execute(BLOCK_UPSERT_STMT, params![
+            header.block_number as i64,
+            header.block_timestamp as i64,
+            hex_string(&header.block_hash),
+            hex_string(&header.parent_hash),
+            hex_string(&header.extrinsics_root),
+            hex_string(&header.state_root),
+            header.is_finished,
+            header.validator.as_ref().map(|v| hex_string(v)),
+            header.spec_version as i32,
+            header.block_hash,
+            header.parent_hash,
+            header.extrinsics_root,
+            header.state_root,
+            header.validator,
+        ])
+        .map_err(|err| anyhow!("insert block #{} error: {}", header.block_number, err))?;
+        Ok(())
+    }
+
+    fn write_log(tx: &Transaction<'_>, block: &polkadot_chain::Block) -> anyhow::Result<()> {
+        let logs = match block.logs.as_ref() {
+            None => return Ok(()),
+            Some(logs) => logs,
+        };
+
+        let mut stmt = tx.prepare(LOG_UPSERT_STMT)?;
+        for log in logs.iter() {
+            stmt.execute(params![
+                log.id,
+                log.block_number as i64,
+                log.r#type,
+                log.data.as_ref().map(|v| hex_string(v)),
+                log.engine,
+            ])
+            .map_err(|err| {
+                anyhow!(
+                    "insert logs of block #{} error: {}",
+                    block.header.block_number,
+                    err
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    fn write_extrinsics(tx: &Transaction<'_>, block: &polkadot_chain::Block) -> anyhow::Result<()> {
+        let exts = match block.body.extrinsics.as_ref() {
+            None => return Ok(()),
+            Some(exts) => exts,
+        };
+
+        let mut stmt = tx.prepare(EXTRINSICS_UPSERT_STMT)?;
+        for ext in exts.iter() {
+            stmt.execute(params![
+                ext.id,
+                ext.block_number as i64,
+                hex_string(&ext.extrinsic_hash),
+                ext.signature.is_some(),
+                ext.mod_name,
+                ext.call_name,
+                ext.result,
+                ext.call_params.as_ref().map(|v| v.to_string()),
+                ext.extrinsic_hash,
+            ])
+            .map_err(|err| {
+                anyhow!(
+                    "insert extrinsics of block #{} error: {}",
+                    block.header.block_number,
+                    err
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    fn write_events(tx: &Transaction<'_>, block: &polkadot_chain::Block) -> anyhow::Result<()> {
+        let events = match block.body.events.as_ref() {
+            None => return Ok(()),
+            Some(events) => events,
+        };
+
+        let mut stmt = tx.prepare(EVENT_UPSERT_STMT)?;
+        for event in events.iter() {
+            stmt.execute(params![
+                event.id,
+                event.block_number as i64,
+                event.extrinsic_id,
+                event.mod_name,
+                event.event_name,
+                event.phase as i16,
+                event.values.as_ref().map(|v| v.to_string()),
+            ])
+            .map_err(|err| {
+                anyhow!(
+                    "insert events of block #{} error: {}",
+                    block.header.block_number,
+                    err
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn write_block(
+        tx: &Transaction<'_>,
+        block: &polkadot_chain::Block,
+    ) -> anyhow::Result<()> {
+        if Self::is_stale(tx, block)? {
+            tracing::debug!(
+                "skip stale unfinalized block #{}, it's finalized",
+                block.header.block_number
+            );
+            return Ok(());
+        }
+        Self::rollback_unfinalized(tx, block)?;
+        Self::write_header(tx, block)?;
+        Self::write_log(tx, block)?;
+        Self::write_extrinsics(tx, block)?;
+        Self::write_events(tx, block)?;
+        Ok(())
+    }
+
+    /// Write the batch of blocks in one transaction, none of them is
+    /// written if any failed.
+    pub(crate) fn write_blocks(
+        conn: &mut rusqlite::Connection,
+        blocks: &[polkadot_chain::Block],
+    ) -> anyhow::Result<()> {
+        let tx = conn
+            .transaction()
+            .map_err(|err| anyhow!("begin transaction error: {}", err))?;
+        for block in blocks.iter() {
+            Self::write_block(&tx, block)?;
+        }
+        tx.commit().map_err(|err| {
+            anyhow!(
+                "commit transaction of {} blocks error: {}",
+                blocks.len(),
+                err
+            )
+        })
+    }
+}
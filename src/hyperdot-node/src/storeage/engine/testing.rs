@@ -0,0 +1,36 @@
+//! The fixtures shared by the tests of the engines.
+
+use crate::types::block::polkadot_chain;
+
+/// A block with the hash of its number and an event, at timestamp 0.
+pub(crate) fn new_block(number: u64, is_finished: bool) -> polkadot_chain::Block {
+    new_block_at(number, 0, is_finished)
+}
+
+/// A block with the hash of its number and an event, at the timestamp in
+/// milliseconds.
+pub(crate) fn new_block_at(
+    number: u64,
+    timestamp: u64,
+    is_finished: bool,
+) -> polkadot_chain::Block {
+    let mut block = polkadot_chain::Block::default();
+    block.header.block_number = number;
+    block.header.block_timestamp = timestamp;
+    block.header.block_hash = vec![number as u8; 32];
+    block.header.is_finished = is_finished;
+    block.body.events = Some(vec![polkadot_chain::Event {
+        id: format!("{}-0", number),
+        block_number: number,
+        block_timestamp: timestamp,
+        extrinsic_index: 0,
+        extrinsic_id: format!("{}-0", number),
+        mod_name: "System".to_string(),
+        event_name: "ExtrinsicSuccess".to_string(),
+        event_index: 0,
+        phase: 0,
+        extrinsic_hash: vec![],
+        values: Some(serde_json::json!({ "weight": number })),
+    }]);
+    block
+}
@@ -1,7 +1,9 @@
 pub mod client;
 mod engine;
 pub mod server;
+mod tls;
 
+pub use engine::PgMigrator;
 pub use server::Server;
 
 // mod channel;
@@ -3,11 +3,13 @@ use std::sync::Arc;
 
 use hyperdot_core::config::StorageNodeConfig;
 // use tokio::sync::RwLock;
+use tokio::net::TcpListener;
 use tokio::task::JoinHandle;
 
 use super::route;
 use super::route::Context;
 use crate::storeage::engine;
+use crate::storeage::tls;
 
 pub struct ApiServer {
     cfg: StorageNodeConfig,
@@ -54,14 +56,38 @@ impl ApiServer {
         let url = self.cfg.apiserver.url.as_str();
         let addr = url.parse()?;
 
-        let handle = tokio::spawn(async move {
-            axum::Server::bind(&addr)
-                .serve(app.into_make_service())
-                .await
-                .map_err(|err| anyhow::anyhow!("{}", err))
-        });
+        let handle = match self.cfg.apiserver.tls.as_ref() {
+            None => tokio::spawn(async move {
+                axum::Server::bind(&addr)
+                    .serve(app.into_make_service())
+                    .await
+                    .map_err(|err| anyhow::anyhow!("{}", err))
+            }),
+            Some(tls_cfg) => {
+                let acceptor = tls::server_tls_acceptor(tls_cfg)?;
+                let listener = TcpListener::bind(addr).await?;
+                let incoming = futures::stream::unfold(
+                    tls::tls_incoming(listener, acceptor),
+                    |mut rx| async move {
+                        rx.recv()
+                            .await
+                            .map(|stream| (Ok::<_, std::io::Error>(stream), rx))
+                    },
+                );
+                tokio::spawn(async move {
+                    axum::Server::builder(hyper::server::accept::from_stream(incoming))
+                        .serve(app.into_make_service())
+                        .await
+                        .map_err(|err| anyhow::anyhow!("{}", err))
+                })
+            }
+        };
 
-        tracing::info!("🏃 http apiserver has been listend at {}", url);
+        tracing::info!(
+            "🏃 http apiserver has been listend at {} (tls: {})",
+            url,
+            self.cfg.apiserver.tls.is_some()
+        );
         self.http_serv_handle = Some(handle);
 
         Ok(())
@@ -1,6 +1,7 @@
-use std::collections::HashMap;
 use std::pin::Pin;
+use std::str::FromStr;
 
+use axum::extract::Path;
 use axum::extract::State;
 use axum::http::StatusCode;
 use axum::routing::get;
@@ -10,7 +11,7 @@ use axum::Router;
 use hyperdot_core::protocols::GetPostgresSchemeRequest;
 use hyperdot_core::protocols::GetPostgresSchemeResponse;
 use hyperdot_core::protocols::ResponseMetadata;
-use hyperdot_core::types::PostgresTableInfo;
+use hyperdot_core::types::DataEngineKind;
 
 // use super::model::dataengine;
 // use super::model::support;
@@ -19,56 +20,32 @@ use super::route::Context;
 use super::API_ROOT_PATH;
 use super::API_VERSION;
 
-struct PostgresSchemeHandle;
+struct SchemeHandle;
 
-impl PostgresSchemeHandle {
+impl SchemeHandle {
     pub async fn get_scheme(
         State(ctx): State<Context>,
+        Path(engine): Path<String>,
         Json(request): Json<GetPostgresSchemeRequest>,
     ) -> Result<Json<GetPostgresSchemeResponse>, StatusCode> {
-        let pg_engine = ctx
-            .engine_controller
-            .get_pg_engine()
-            .await
-            .unwrap(); // TODO: handle error
-        let pg_conn_state = pg_engine
-            .get_conn_state_for_chain(&request.chain)
-            .await
-            .unwrap(); // TODO: hbandle erroor
-        let query_tables =
-            "SELECT table_name FROM information_schema.tables WHERE table_schema = 'public'";
-        // let child = controller.get_child("postgres").await;
-        // let pg_impl = child.downcast::<PolkadotPostgresStorageImpl>().unwrap();
-        let rows = pg_conn_state.client.query(query_tables, &[]).await.unwrap();
-        let mut tables = HashMap::new();
-        for row in rows.into_iter() {
-            let table_name: String = row.get(0);
-
-            let query_table_info = "SELECT column_name, data_type FROM information_schema.columns WHERE table_name = $1";
-
-            let column_rows = pg_conn_state
-                .client
-                .query(query_table_info, &[&table_name])
-                .await
-                .unwrap();
-
-            let mut tables_info = vec![];
-            for column_row in column_rows.into_iter() {
-                let column_name: String = column_row.get(0);
-                let data_type: String = column_row.get(1);
-                tables_info.push(PostgresTableInfo {
-                    column_name,
-                    data_type,
-                });
+        let tables = match DataEngineKind::from_str(&engine) {
+            Err(err) => Err(err),
+            Ok(engine) => ctx.engine_controller.schema(&engine, &request.chain).await,
+        };
+        let tables = match tables {
+            Err(err) => {
+                let mut response = GetPostgresSchemeResponse::default();
+                response.meta.set_error(err.to_string());
+                response.chain = request.chain.clone();
+                return Ok(Json(response));
             }
-
-            tables.insert(table_name, tables_info);
-        }
+            Ok(tables) => tables,
+        };
 
         Ok(Json(GetPostgresSchemeResponse {
             meta: ResponseMetadata::success(&format!(
-                "get {} postgres scheme success",
-                request.chain
+                "get {} {} scheme success",
+                request.chain, engine
             )),
             chain: request.chain.clone(),
             tables,
@@ -90,9 +67,10 @@ impl DataEngineRouteBuilder {
     pub fn build(self, router: Router<Context>) -> anyhow::Result<Router<Context>> {
         let base = self.base_path();
 
-        let api_get_pg_schemes = format!("{}/scheme/postgres", base);
-        tracing::info!("register post api: {}", api_get_pg_schemes);
-        Ok(router.route(&api_get_pg_schemes, post(PostgresSchemeHandle::get_scheme)))
+        // /apis/v1/dataengine/scheme/postgres, /apis/v1/dataengine/scheme/sqlite, ...
+        let api_get_schemes = format!("{}/scheme/:engine", base);
+        tracing::info!("register post api: {}", api_get_schemes);
+        Ok(router.route(&api_get_schemes, post(SchemeHandle::get_scheme)))
     }
 
     fn base_path(&self) -> String {
@@ -1,6 +1,8 @@
 use std::any::Any;
+use std::str::FromStr;
 use std::sync::Arc;
 
+use axum::extract::Path;
 use axum::extract::State;
 use axum::http::StatusCode;
 use axum::routing::get;
@@ -10,6 +12,7 @@ use axum::Router;
 use hyperdot_core::protocols::QueryPostgresRequest;
 use hyperdot_core::protocols::QueryPostgresResponse;
 use hyperdot_core::protocols::ResponseCode;
+use hyperdot_core::types::DataEngineKind;
 
 // use super::model;
 // use super::model::support::ResponseCode;
@@ -19,14 +22,24 @@ use super::API_VERSION;
 // use crate::storeage::engine::postgres::PolkadotPostgresStorageImpl;
 // use crate::storeage::engine::postgres::PostgresRows;
 
-struct PostgresQueryHandle;
+struct QueryHandle;
 
-impl PostgresQueryHandle {
+impl QueryHandle {
+    /// Run the query by the engine of path, the rows of all engines are
+    /// returned in the same format as postgres.
     pub async fn run(
         State(ctx): State<Context>,
+        Path(engine): Path<String>,
         Json(request): Json<QueryPostgresRequest>,
     ) -> Result<Json<QueryPostgresResponse>, (StatusCode, String)> {
         let mut response = QueryPostgresResponse::default();
+        let engine = match DataEngineKind::from_str(&engine) {
+            Err(err) => {
+                response.meta.set_error(err.to_string());
+                return Ok(Json(response));
+            }
+            Ok(engine) => engine,
+        };
         // if !core::model::SUPPORT_DATA_ENGINES.is_support(&request.engine) {
         //     response.meta.set_code(ResponseCode::Error);
         //     response
@@ -50,22 +63,20 @@ impl PostgresQueryHandle {
             return Ok(Json(response));
         }
 
-        let pg_engine = match ctx.engine_controller.get_pg_engine().await {
-            Err(err) => {
-                response.meta.set_error(err.to_string());
-                return Ok(Json(response));
-            }
-            Ok(pg_engine) => pg_engine,
-        };
-
-        match pg_engine.query(&request.chain, &request.query).await {
+        match ctx
+            .engine_controller
+            .query(&engine, &request.chain, &request.query, &request.options)
+            .await
+        {
             Err(err) => {
                 response.meta.set_error(err.to_string());
                 return Ok(Json(response));
             }
 
-            Ok(res) => {
-                response.rows = res;
+            Ok(page) => {
+                response.rows = page.rows;
+                response.total = page.total;
+                response.has_more = page.has_more;
                 return Ok(Json(response));
             }
         }
@@ -113,10 +124,11 @@ impl QueryRouteBuilder {
     pub fn build(self, mut router: Router<Context>) -> anyhow::Result<Router<Context>> {
         let base = self.base_path();
 
-        let run_posrgres = format!("{}/run/postgres", base);
-        tracing::info!("register api: {}", run_posrgres);
+        // /apis/v1/query/run/postgres, /apis/v1/query/run/sqlite, ...
+        let run_engine = format!("{}/run/:engine", base);
+        tracing::info!("register api: {}", run_engine);
 
-        router = router.route(run_posrgres.as_str(), post(PostgresQueryHandle::run));
+        router = router.route(run_engine.as_str(), post(QueryHandle::run));
         Ok(router)
     }
 
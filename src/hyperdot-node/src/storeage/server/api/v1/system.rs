@@ -6,6 +6,7 @@ use axum::routing::get;
 use axum::routing::post;
 use axum::Json;
 use axum::Router;
+use hyperdot_core::protocols::HealthResponse;
 use hyperdot_core::protocols::ListDataEngineResponse;
 use hyperdot_core::protocols::ListDataEngineResquest;
 use hyperdot_core::protocols::ResponseCode;
@@ -32,16 +33,15 @@ async fn list_dataengines(
     }
 
     for de in ctx.cfg.data_engines.iter() {
-        if let Some(pg) = de.postgres.as_ref() {
-            let mut engine_info = EngineInfo::default();
-            for sc in pg.support_chains.iter() {
-                engine_info
-                    .support_chains
-                    .insert(sc.name.clone(), ChainInfo {
-                        name: sc.name.clone(),
-                    });
-            }
-            response.engines.insert("Postgres".to_string(), engine_info); // TODO: make enum
+        for (chain, kind) in de.support_chains() {
+            response
+                .engines
+                .entry(format!("{:?}", kind))
+                .or_insert_with(EngineInfo::default)
+                .support_chains
+                .insert(chain.to_string(), ChainInfo {
+                    name: chain.to_string(),
+                });
         }
     }
     response
@@ -50,6 +50,17 @@ async fn list_dataengines(
     return Ok(Json(response));
 }
 
+async fn health(State(ctx): State<Context>) -> Result<Json<HealthResponse>, StatusCode> {
+    let mut response = HealthResponse::default();
+    match ctx.engine_controller.health().await {
+        Err(err) => response.meta.set_error(err.to_string()),
+        Ok(_) => response
+            .meta
+            .set_success_msg("data engines available".to_string()),
+    }
+    Ok(Json(response))
+}
+
 pub struct SystemRouteBuilder {
     path: String,
 }
@@ -67,7 +78,12 @@ impl SystemRouteBuilder {
         let api_list_dataengines = format!("{}/dataengines", base);
         tracing::info!("register api: {}", api_list_dataengines);
 
-        Ok(router.route(&api_list_dataengines, get(list_dataengines)))
+        let api_health = format!("{}/health", base);
+        tracing::info!("register api: {}", api_health);
+
+        Ok(router
+            .route(&api_list_dataengines, get(list_dataengines))
+            .route(&api_health, get(health)))
     }
 
     fn base_path(&self) -> String {
@@ -10,17 +10,38 @@ use jsonrpsee::types::error::ErrorCode;
 use jsonrpsee::types::error::ErrorObject;
 use jsonrpsee::types::ResponsePayload;
 use jsonrpsee::RpcModule;
+use jsonrpsee::SubscriptionMessage;
+use tokio::net::TcpListener;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tower::ServiceBuilder;
+use tower_http::validate_request::ValidateRequestHeaderLayer;
 use tracing::info;
 
 use crate::storeage::engine;
+use crate::storeage::tls;
+use crate::types::rpc::BlockAck;
+use crate::types::rpc::BlockGaps;
+use crate::types::rpc::BlockGapsResponse;
+use crate::types::rpc::EncodedWriteBlock;
+use crate::types::rpc::SubscribeBlockAcks;
+use crate::types::rpc::WireEncoding;
+use crate::types::rpc::WireEncodingsResponse;
 use crate::types::rpc::WriteBlock;
 use crate::types::rpc::WriteBlockResponse;
+use crate::types::rpc::WRITE_BLOCK_REJECTED_CODE;
+
+/// The max acknowledgements buffered for a slow subscriber.
+const BLOCK_ACKS_CAPACITY: usize = 4096;
 
 #[derive(Clone)]
 pub struct JsonRpcServerContext {
     // controllers: Arc<RwLock<HashMap<String, Arc<StorageController>>>>,
     engine_controlelr: Arc<engine::Controller>, // TODO: make as weak
     cfg: StorageNodeConfig,
+    /// The acknowledgements of blocks written, broadcast to the
+    /// subscribers.
+    acks: broadcast::Sender<BlockAck>,
 }
 
 pub struct JsonRpcServer {
@@ -48,14 +69,44 @@ impl JsonRpcServer {
             return Err(anyhow::anyhow!("server alreay started"));
         }
         let addr = self.cfg.rpc.url.parse::<SocketAddr>()?;
-        let server = ServerBuilder::new().build(addr).await?;
+        let (acks, _) = broadcast::channel(BLOCK_ACKS_CAPACITY);
         let ctx = JsonRpcServerContext {
             engine_controlelr: self.engine_controller.clone(),
             cfg: self.cfg.clone(),
+            acks,
         };
         let rpc_module = register_methods(ctx)?;
-        info!("🌗 storage json-rpc server listening at {}", addr);
-        self.handle = Some(server.start(rpc_module)?);
+        let handle = match self.cfg.rpc.tls.as_ref() {
+            None => ServerBuilder::new().build(addr).await?.start(rpc_module)?,
+            Some(tls_cfg) => {
+                // The json-rpc server serves plain connections only, the TLS
+                // connections are terminated here and forwarded to it on
+                // the loopback. It only serves the requests with the token
+                // of the proxy, the local clients can't skip the TLS.
+                let acceptor = tls::server_tls_acceptor(tls_cfg)?;
+                let listener = TcpListener::bind(addr).await?;
+                let token = tls::proxy_token()?;
+                let middleware =
+                    ServiceBuilder::new().layer(ValidateRequestHeaderLayer::bearer(&token));
+                let server = ServerBuilder::new()
+                    .set_middleware(middleware)
+                    .build("127.0.0.1:0")
+                    .await?;
+                tokio::spawn(tls::serve_tls_proxy(
+                    listener,
+                    acceptor,
+                    server.local_addr()?,
+                    token,
+                ));
+                server.start(rpc_module)?
+            }
+        };
+        info!(
+            "🌗 storage json-rpc server listening at {} (tls: {})",
+            addr,
+            self.cfg.rpc.tls.is_some()
+        );
+        self.handle = Some(handle);
 
         Ok(())
     }
@@ -77,23 +128,144 @@ pub fn register_methods(ctx: JsonRpcServerContext) -> AnyResult<RpcModule<JsonRp
             Ok(req) => req,
         };
 
-        let chain_name = req.chain.clone(); // let block_numbers = req.block_numbers();
+        write_block(&ctx, req).await
+    })?;
+
+    let _ = rpc_module.register_method("wire_encodings", |_, _| {
+        ResponsePayload::result(WireEncodingsResponse {
+            encodings: vec![WireEncoding::Scale, WireEncoding::Json],
+        })
+    })?;
+
+    let _ = rpc_module.register_async_method("write_block_encoded", |params, ctx| async move {
+        let req = match params.parse::<EncodedWriteBlock>() {
+            Err(err) => return ResponsePayload::Error(err),
+            Ok(req) => req,
+        };
 
-        match ctx.engine_controlelr.write_block(req).await {
+        let req = match req.decode() {
             Err(err) => {
-                tracing::error!("⚠️ {}: write block error: {}", chain_name, err);
-                ResponsePayload::Error(ErrorObject::from(ErrorCode::InternalError))
+                tracing::error!("⚠️ decode {:?} write block error: {}", req.encoding, err);
+                return ResponsePayload::Error(ErrorObject::owned(
+                    WRITE_BLOCK_REJECTED_CODE,
+                    format!("decode write block error: {}", err),
+                    None::<()>,
+                ));
+            }
+            Ok(req) => req,
+        };
+
+        write_block(&ctx, req).await
+    })?;
+
+    let _ = rpc_module.register_subscription(
+        "subscribe_block_acks",
+        "block_acks",
+        "unsubscribe_block_acks",
+        |params, pending, ctx| async move {
+            let req = match params.parse::<SubscribeBlockAcks>() {
+                Err(err) => {
+                    pending.reject(err).await;
+                    return;
+                }
+                Ok(req) => req,
+            };
+
+            let mut rx = ctx.acks.subscribe();
+            let sink = match pending.accept().await {
+                Err(_) => return,
+                Ok(sink) => sink,
+            };
+
+            loop {
+                let ack = tokio::select! {
+                    _ = sink.closed() => return,
+                    ack = rx.recv() => ack,
+                };
+                let ack = match ack {
+                    Err(RecvError::Closed) => return,
+                    // Close the subscription rather than skip the acks,
+                    // the subscriber resyncs once resubscribed.
+                    Err(RecvError::Lagged(n)) => {
+                        tracing::warn!(
+                            "⚠️ {}: block acks subscriber lagged {} acks, close it",
+                            req.chain,
+                            n
+                        );
+                        return;
+                    }
+                    Ok(ack) if ack.chain != req.chain => continue,
+                    Ok(ack) => ack,
+                };
+
+                let msg = SubscriptionMessage::from_json(&ack).expect("block ack serialize");
+                if sink.send(msg).await.is_err() {
+                    return;
+                }
             }
-            Ok(_) => {
-                tracing::trace!("🌍 {}: write block success", chain_name);
-                ResponsePayload::result(WriteBlockResponse {})
+        },
+    )?;
+
+    let _ = rpc_module.register_async_method("block_gaps", |params, ctx| async move {
+        let req = match params.parse::<BlockGaps>() {
+            Err(err) => return ResponsePayload::Error(err),
+            Ok(req) => req,
+        };
+
+        let chain_name = req.chain.clone();
+        match ctx.engine_controlelr.block_gaps(req).await {
+            Err(err) => {
+                tracing::error!("⚠️ {}: find block gaps error: {}", chain_name, err);
+                ResponsePayload::Error(ErrorObject::from(ErrorCode::InternalError))
             }
+            Ok(gaps) => ResponsePayload::result(BlockGapsResponse { gaps }),
         }
     })?;
 
     Ok(rpc_module)
 }
 
+async fn write_block(
+    ctx: &JsonRpcServerContext,
+    req: WriteBlock,
+) -> ResponsePayload<'static, WriteBlockResponse> {
+    let chain_name = req.chain.clone();
+    let block_numbers = req.polkadot_blocks.as_ref().map_or(vec![], |blocks| {
+        blocks
+            .iter()
+            .map(|block| (block.header.block_number, block.header.is_finished))
+            .collect()
+    });
+
+    match ctx.engine_controlelr.write_block(req).await {
+        Err(err) => {
+            tracing::error!("⚠️ {}: write block error: {}", chain_name, err);
+            // The request never written is rejected, the others are
+            // retried by the speaker, e.g. the database is down.
+            match err.downcast_ref::<engine::WriteBlockRejected>() {
+                Some(rejected) => ResponsePayload::Error(ErrorObject::owned(
+                    WRITE_BLOCK_REJECTED_CODE,
+                    rejected.to_string(),
+                    None::<()>,
+                )),
+                None => ResponsePayload::Error(ErrorObject::from(ErrorCode::InternalError)),
+            }
+        }
+        Ok(_) => {
+            tracing::trace!("🌍 {}: write block success", chain_name);
+            for (block_number, finalized) in block_numbers.into_iter() {
+                // No subscriber is not an error.
+                let _ = ctx.acks.send(BlockAck {
+                    chain: chain_name.clone(),
+                    block_number,
+                    finalized,
+                });
+            }
+            ResponsePayload::result(WriteBlockResponse {})
+        }
+    }
+}
+
 // fn test() -> impl Fn(Params<'static>, Arc<JsonRpcServerContext>) -> Pin<Box<dyn Future<Output = ()>>>
 // {
 //     todo!()
@@ -0,0 +1,412 @@
+use std::convert::Infallible;
+use std::fs::File;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use hyper::client::HttpConnector;
+use hyper::header::HeaderValue;
+use hyper::header::AUTHORIZATION;
+use hyper::header::UPGRADE;
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::Body;
+use hyper::Client;
+use hyper::Request;
+use hyper::Response;
+use hyper::StatusCode;
+use hyperdot_core::config::ClientTlsConfig;
+use hyperdot_core::config::ServerTlsConfig;
+use hyperdot_core::types::PostgresDataEngineConnection;
+use rustls::client::danger::HandshakeSignatureValid;
+use rustls::client::danger::ServerCertVerified;
+use rustls::client::danger::ServerCertVerifier;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::CertificateDer;
+use rustls::pki_types::PrivateKeyDer;
+use rustls::pki_types::ServerName;
+use rustls::pki_types::UnixTime;
+use rustls::server::WebPkiClientVerifier;
+use rustls::ClientConfig;
+use rustls::DigitallySignedStruct;
+use rustls::RootCertStore;
+use rustls::ServerConfig;
+use rustls::SignatureScheme;
+use tokio::net::TcpListener;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio_postgres::config::SslMode;
+use tokio_postgres_rustls::MakeRustlsConnect;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::TlsConnector;
+
+/// The max seconds of a TLS handshake of the listeners.
+const HANDSHAKE_TIMEOUT_SECS: u64 = 10;
+
+/// The handshaked connections waiting to be served.
+const INCOMING_CAPACITY: usize = 64;
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+fn open(path: &str) -> anyhow::Result<BufReader<File>> {
+    Ok(BufReader::new(
+        File::open(path).map_err(|err| anyhow!("open {} error: {}", path, err))?,
+    ))
+}
+
+pub(crate) fn load_certs(path: &str) -> anyhow::Result<Vec<CertificateDer<'static>>> {
+    let certs = rustls_pemfile::certs(&mut open(path)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| anyhow!("read certificates of {} error: {}", path, err))?;
+    if certs.is_empty() {
+        return Err(anyhow!("no certificates found in {}", path));
+    }
+    Ok(certs)
+}
+
+pub(crate) fn load_private_key(path: &str) -> anyhow::Result<PrivateKeyDer<'static>> {
+    rustls_pemfile::private_key(&mut open(path)?)
+        .map_err(|err| anyhow!("read private key of {} error: {}", path, err))?
+        .ok_or(anyhow!("no private key found in {}", path))
+}
+
+/// The CA certificates of the path, or the system root certificates if
+/// the path is none.
+fn root_store(path: Option<&str>) -> anyhow::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    let certs = match path {
+        Some(path) => load_certs(path)?,
+        None => rustls_native_certs::load_native_certs().certs,
+    };
+    for cert in certs {
+        roots
+            .add(cert)
+            .map_err(|err| anyhow!("add CA certificate error: {}", err))?;
+    }
+    Ok(roots)
+}
+
+/// The ssl mode of the postgres connection, and if the server certificate
+/// is verified.
+pub(crate) fn pg_ssl_mode(
+    connection: &PostgresDataEngineConnection,
+) -> anyhow::Result<(SslMode, bool)> {
+    match connection.sslmode.as_deref().unwrap_or("disable") {
+        "disable" => Ok((SslMode::Disable, false)),
+        "prefer" => Ok((SslMode::Prefer, false)),
+        "require" => Ok((SslMode::Require, false)),
+        "verify-full" => Ok((SslMode::Require, true)),
+        mode => Err(anyhow!(
+            "{}: unknown sslmode({}), expect disable, prefer, require or verify-full",
+            connection.name,
+            mode
+        )),
+    }
+}
+
+/// The TLS connector of the postgres connection. The connections with
+/// sslmode disable never use it.
+pub(crate) fn pg_tls_connector(
+    connection: &PostgresDataEngineConnection,
+) -> anyhow::Result<MakeRustlsConnect> {
+    let (_, verify) = pg_ssl_mode(connection)?;
+    let provider = provider();
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+    let builder = match verify {
+        true => builder.with_root_certificates(root_store(connection.sslrootcert.as_deref())?),
+        false => builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(NoServerVerification(provider))),
+    };
+
+    let config = match (connection.sslcert.as_ref(), connection.sslkey.as_ref()) {
+        (None, None) => builder.with_no_client_auth(),
+        (Some(cert), Some(key)) => {
+            builder.with_client_auth_cert(load_certs(cert)?, load_private_key(key)?)?
+        }
+        _ => {
+            return Err(anyhow!(
+                "{}: sslcert and sslkey should be set together",
+                connection.name
+            ))
+        }
+    };
+    Ok(MakeRustlsConnect::new(config))
+}
+
+/// The TLS connector of a client, the server certificate is verified by
+/// the CA certificates.
+pub(crate) fn client_tls_connector(cfg: &ClientTlsConfig) -> anyhow::Result<TlsConnector> {
+    let builder =
+        ClientConfig::builder_with_provider(provider()).with_safe_default_protocol_versions()?;
+    let builder = builder.with_root_certificates(root_store(cfg.ca.as_deref())?);
+    let config = match (cfg.cert.as_ref(), cfg.key.as_ref()) {
+        (None, None) => builder.with_no_client_auth(),
+        (Some(cert), Some(key)) => {
+            builder.with_client_auth_cert(load_certs(cert)?, load_private_key(key)?)?
+        }
+        _ => return Err(anyhow!("client tls cert and key should be set together")),
+    };
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+/// Accept any server certificate, the same as libpq with sslmode require,
+/// the handshake signatures are still checked.
+#[derive(Debug)]
+struct NoServerVerification(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for NoServerVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// The TLS acceptor of a listener, the clients must present a certificate
+/// signed by the client CA if it's configured.
+pub(crate) fn server_tls_acceptor(cfg: &ServerTlsConfig) -> anyhow::Result<TlsAcceptor> {
+    let builder =
+        ServerConfig::builder_with_provider(provider()).with_safe_default_protocol_versions()?;
+    let builder = match cfg.client_ca.as_ref() {
+        None => builder.with_no_client_auth(),
+        Some(client_ca) => {
+            let roots = Arc::new(root_store(Some(client_ca))?);
+            let verifier = WebPkiClientVerifier::builder_with_provider(roots, provider())
+                .build()
+                .map_err(|err| anyhow!("build client verifier error: {}", err))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+    };
+    let config = builder.with_single_cert(load_certs(&cfg.cert)?, load_private_key(&cfg.key)?)?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Accept the TLS connections of the listener. The handshakes run
+/// concurrently, so a slow client never blocks the others.
+pub(crate) fn tls_incoming(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+) -> mpsc::Receiver<TlsStream<TcpStream>> {
+    let (tx, rx) = mpsc::channel(INCOMING_CAPACITY);
+    tokio::spawn(async move {
+        while !tx.is_closed() {
+            let (stream, peer) = match listener.accept().await {
+                Err(err) => {
+                    tracing::error!("🔒 accept connection error: {}", err);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+                Ok(res) => res,
+            };
+
+            let acceptor = acceptor.clone();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let handshake = tokio::time::timeout(
+                    Duration::from_secs(HANDSHAKE_TIMEOUT_SECS),
+                    acceptor.accept(stream),
+                );
+                match handshake.await {
+                    Err(_) => tracing::debug!("🔒 tls handshake with {} timeout", peer),
+                    Ok(Err(err)) => {
+                        tracing::debug!("🔒 tls handshake with {} error: {}", peer, err)
+                    }
+                    Ok(Ok(stream)) => {
+                        let _ = tx.send(stream).await;
+                    }
+                }
+            });
+        }
+    });
+    rx
+}
+
+/// A random bearer token shared by the TLS proxy and its upstream only.
+pub(crate) fn proxy_token() -> anyhow::Result<String> {
+    let mut bytes = [0_u8; 32];
+    provider()
+        .secure_random
+        .fill(&mut bytes)
+        .map_err(|err| anyhow!("generate proxy token error: {:?}", err))?;
+    Ok(hex::encode(bytes))
+}
+
+/// Forward the request to the plain server at upstream with the bearer
+/// token. The websocket upgrade is forwarded as well, and the upgraded
+/// connections are joined.
+async fn forward(
+    mut request: Request<Body>,
+    upstream: SocketAddr,
+    client: Client<HttpConnector>,
+    authorization: HeaderValue,
+) -> Result<Response<Body>, Infallible> {
+    let path = request
+        .uri()
+        .path_and_query()
+        .map_or("/", |path| path.as_str());
+    *request.uri_mut() = format!("http://{}{}", upstream, path)
+        .parse()
+        .expect("valid upstream uri");
+    request.headers_mut().insert(AUTHORIZATION, authorization);
+
+    let upgrade = match request.headers().contains_key(UPGRADE) {
+        true => Some(hyper::upgrade::on(&mut request)),
+        false => None,
+    };
+    let mut response = match client.request(request).await {
+        Err(err) => {
+            tracing::error!("🔒 forward to tls upstream {} error: {}", upstream, err);
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::BAD_GATEWAY;
+            return Ok(response);
+        }
+        Ok(response) => response,
+    };
+
+    if let (Some(upgrade), StatusCode::SWITCHING_PROTOCOLS) = (upgrade, response.status()) {
+        let upstream_upgrade = hyper::upgrade::on(&mut response);
+        tokio::spawn(async move {
+            match tokio::try_join!(upgrade, upstream_upgrade) {
+                Err(err) => tracing::debug!("🔒 upgrade tls connection error: {}", err),
+                Ok((mut stream, mut upstream_stream)) => {
+                    let _ = tokio::io::copy_bidirectional(&mut stream, &mut upstream_stream).await;
+                }
+            }
+        });
+    }
+    Ok(response)
+}
+
+/// Terminate the TLS connections of the listener and forward their
+/// requests to the plain server at upstream. The upstream listens on the
+/// loopback and only serves the requests with the bearer token, so the
+/// clients not verified by the TLS handshake can't bypass it.
+pub(crate) async fn serve_tls_proxy(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+    upstream: SocketAddr,
+    token: String,
+) {
+    let client = Client::new();
+    let authorization =
+        HeaderValue::from_str(&format!("Bearer {}", token)).expect("valid proxy token");
+    let mut incoming = tls_incoming(listener, acceptor);
+    while let Some(stream) = incoming.recv().await {
+        let client = client.clone();
+        let authorization = authorization.clone();
+        tokio::spawn(async move {
+            let service = service_fn(move |request| {
+                forward(request, upstream, client.clone(), authorization.clone())
+            });
+            if let Err(err) = Http::new()
+                .serve_connection(stream, service)
+                .with_upgrades()
+                .await
+            {
+                tracing::debug!("🔒 serve tls connection error: {}", err);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_connection(sslmode: Option<&str>) -> PostgresDataEngineConnection {
+        PostgresDataEngineConnection {
+            name: "local".to_string(),
+            username: "postgres".to_string(),
+            password: "".to_string(),
+            host: "127.0.0.1".to_string(),
+            port: 5432,
+            pool_size: None,
+            pool_timeout_secs: None,
+            sslmode: sslmode.map(|s| s.to_string()),
+            sslrootcert: None,
+            sslcert: None,
+            sslkey: None,
+            query_username: None,
+            query_password: None,
+        }
+    }
+
+    #[test]
+    fn test_pg_ssl_mode() {
+        assert!(matches!(
+            pg_ssl_mode(&new_connection(None)).unwrap(),
+            (SslMode::Disable, false)
+        ));
+        assert!(matches!(
+            pg_ssl_mode(&new_connection(Some("require"))).unwrap(),
+            (SslMode::Require, false)
+        ));
+        assert!(matches!(
+            pg_ssl_mode(&new_connection(Some("verify-full"))).unwrap(),
+            (SslMode::Require, true)
+        ));
+        assert!(pg_ssl_mode(&new_connection(Some("verify-ca"))).is_err());
+    }
+
+    #[test]
+    fn test_pg_tls_connector_requires_cert_and_key() {
+        let mut connection = new_connection(Some("require"));
+        assert!(pg_tls_connector(&connection).is_ok());
+
+        connection.sslcert = Some("client.crt".to_string());
+        assert!(pg_tls_connector(&connection).is_err());
+    }
+
+    #[test]
+    fn test_client_tls_connector_requires_cert_and_key() {
+        let mut cfg = ClientTlsConfig::default();
+        assert!(client_tls_connector(&cfg).is_ok());
+
+        cfg.key = Some("client.key".to_string());
+        assert!(client_tls_connector(&cfg).is_err());
+    }
+}
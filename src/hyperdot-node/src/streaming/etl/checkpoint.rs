@@ -0,0 +1,167 @@
+//! Persistent sync checkpoints of the chains.
+
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use hyperdot_core::config::BackfillConfig;
+use serde::Deserialize;
+use serde::Serialize;
+
+use super::super::speaker::sync_dir;
+use super::super::speaker::write_sync;
+
+/// The last block acknowledged by all storage nodes of a chain.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub block_number: u64,
+    pub block_hash: String,
+}
+
+impl Checkpoint {
+    /// Get the backfill range to resume the chain from the checkpoint.
+    ///
+    /// The configured backfill start is skipped to the block after
+    /// the checkpoint. The configured backfill end is kept, it's the
+    /// checkpoint if already reached, so the range is empty rather than
+    /// unbounded.
+    pub fn resume(&self, backfill: Option<&BackfillConfig>) -> BackfillConfig {
+        let start = self.block_number + 1;
+        match backfill {
+            None => BackfillConfig { start, end: None },
+            Some(backfill) => {
+                let start = std::cmp::max(backfill.start, start);
+                let end = backfill.end.map(|end| std::cmp::max(end, start - 1));
+                BackfillConfig { start, end }
+            }
+        }
+    }
+}
+
+/// Checkpoints store as one json file per chain in the directory.
+pub struct CheckpointStore {
+    dir: PathBuf,
+}
+
+impl CheckpointStore {
+    pub async fn open(dir: &Path) -> anyhow::Result<Self> {
+        tokio::fs::create_dir_all(dir).await.map_err(|err| {
+            anyhow!(
+                "create checkpoint directory({}) error: {}",
+                dir.display(),
+                err
+            )
+        })?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    /// Load the checkpoint of chain, return none if the chain never synced.
+    pub async fn load(&self, chain: &str) -> anyhow::Result<Option<Checkpoint>> {
+        let path = self.path(chain);
+        let data = match tokio::fs::read(&path).await {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => {
+                return Err(anyhow!(
+                    "{}: read checkpoint({}) error: {}",
+                    chain,
+                    path.display(),
+                    err
+                ))
+            }
+            Ok(data) => data,
+        };
+
+        serde_json::from_slice(&data)
+            .map(Some)
+            .map_err(|err| anyhow!("{}: decode checkpoint error: {}", chain, err))
+    }
+
+    /// Save the checkpoint of chain. The file is synced to disk before
+    /// replaced atomically, and the rename is synced too, so a crash never
+    /// leaves a broken checkpoint.
+    pub async fn save(&self, chain: &str, checkpoint: &Checkpoint) -> anyhow::Result<()> {
+        let path = self.path(chain);
+        let tmp_path = path.with_extension("json.tmp");
+        let data = serde_json::to_vec(checkpoint)?;
+        write_sync(&tmp_path, &data).await.map_err(|err| {
+            anyhow!(
+                "{}: write checkpoint({}) error: {}",
+                chain,
+                tmp_path.display(),
+                err
+            )
+        })?;
+        tokio::fs::rename(&tmp_path, &path).await.map_err(|err| {
+            anyhow!(
+                "{}: rename checkpoint({}) error: {}",
+                chain,
+                path.display(),
+                err
+            )
+        })?;
+        sync_dir(&self.dir).await.map_err(|err| {
+            anyhow!(
+                "{}: sync checkpoint directory({}) error: {}",
+                chain,
+                self.dir.display(),
+                err
+            )
+        })
+    }
+
+    fn path(&self, chain: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", chain))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_checkpoint_store() {
+        let dir = std::env::temp_dir().join(format!("hyperdot-checkpoint-{}", std::process::id()));
+        let store = CheckpointStore::open(&dir).await.unwrap();
+        assert_eq!(store.load("Polkadot").await.unwrap(), None);
+
+        let checkpoint = Checkpoint {
+            block_number: 100,
+            block_hash: "0x01".to_string(),
+        };
+        store.save("Polkadot", &checkpoint).await.unwrap();
+        assert_eq!(store.load("Polkadot").await.unwrap(), Some(checkpoint));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_checkpoint_resume() {
+        let checkpoint = Checkpoint {
+            block_number: 100,
+            block_hash: "0x01".to_string(),
+        };
+
+        let backfill = checkpoint.resume(None);
+        assert_eq!((backfill.start, backfill.end), (101, None));
+
+        let backfill = checkpoint.resume(Some(&BackfillConfig {
+            start: 10,
+            end: Some(200),
+        }));
+        assert_eq!((backfill.start, backfill.end), (101, Some(200)));
+
+        let backfill = checkpoint.resume(Some(&BackfillConfig {
+            start: 10,
+            end: Some(50),
+        }));
+        assert_eq!((backfill.start, backfill.end), (101, Some(100)));
+
+        let backfill = checkpoint.resume(Some(&BackfillConfig {
+            start: 150,
+            end: None,
+        }));
+        assert_eq!((backfill.start, backfill.end), (150, None));
+    }
+}
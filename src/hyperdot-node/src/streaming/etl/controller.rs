@@ -1,9 +1,11 @@
 use std::collections::HashMap;
+use std::path::Path;
 use std::sync::Arc;
 
 use anyhow::anyhow;
 use hyperdot_core::config::Catalog;
 
+use super::checkpoint::CheckpointStore;
 use super::streaming::BlockStreaming2;
 use super::streaming::BlockStreamingHandle2;
 use crate::streaming::speaker;
@@ -15,15 +17,23 @@ struct ChainStreamingState {
 pub struct StreamingController {
     catalog: Catalog,
     speaker_controller: Arc<speaker::Controller>,
+    checkpoint_store: Arc<CheckpointStore>,
     chains: HashMap<String, ChainStreamingState>,
 }
 
 impl StreamingController {
-    pub async fn async_new(catalog: Catalog) -> anyhow::Result<Self> {
-        let speaker_controller = speaker::Controller::async_new(catalog.clone()).await?;
+    pub async fn async_new(
+        catalog: Catalog,
+        checkpoint_dir: &Path,
+        outbox_dir: &Path,
+    ) -> anyhow::Result<Self> {
+        let speaker_controller =
+            speaker::Controller::async_new(catalog.clone(), outbox_dir).await?;
+        let checkpoint_store = CheckpointStore::open(checkpoint_dir).await?;
         Ok(Self {
             catalog,
             speaker_controller: Arc::new(speaker_controller),
+            checkpoint_store: Arc::new(checkpoint_store),
             chains: HashMap::new(),
         })
     }
@@ -62,10 +72,27 @@ impl StreamingController {
                 );
             }
 
+            let mut chain = chain.clone();
+            if let Some(checkpoint) = self.checkpoint_store.load(&chain.name).await? {
+                let backfill = checkpoint.resume(chain.backfill.as_ref());
+                tracing::info!(
+                    "🔖 {}: resume from checkpoint block #{}({}), catch up from #{}",
+                    chain.name,
+                    checkpoint.block_number,
+                    checkpoint.block_hash,
+                    backfill.start
+                );
+                chain.backfill = Some(backfill);
+            }
+
             tracing::info!("🥳 {}: good catalog, start streaming", chain.name);
-            let streming_handle =
-                BlockStreaming2::spawn(chain, &storage_nodes, self.speaker_controller.clone())
-                    .await?;
+            let streming_handle = BlockStreaming2::spawn(
+                &chain,
+                &storage_nodes,
+                self.speaker_controller.clone(),
+                self.checkpoint_store.clone(),
+            )
+            .await?;
             self.chains
                 .insert(chain.name.clone(), ChainStreamingState { streming_handle });
         }
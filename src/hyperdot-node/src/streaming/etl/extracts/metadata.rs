@@ -0,0 +1,153 @@
+//! Runtime metadata of polkadot chain per spec version.
+//!
+//! The block is decoded by the metadata of the runtime which executed
+//! it, that is the runtime at the parent block. The runtime is reused
+//! for the sequential blocks until the `RuntimeEnvironmentUpdated`
+//! digest item appears, then the runtime version is fetched again.
+
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use subxt::blocks::Block as OnlineBlock;
+use subxt::config::substrate::DigestItem;
+use subxt::utils::H256;
+use subxt::OnlineClient;
+use subxt::PolkadotConfig;
+
+use crate::rpc::JseeRpcClient;
+
+/// The last block decoded.
+struct DecodedBlock {
+    block_hash: H256,
+    spec_version: u32,
+    runtime_updated: bool,
+}
+
+pub struct RuntimeDecoders {
+    client: JseeRpcClient<PolkadotConfig>,
+    /// The online clients with metadata of spec version.
+    decoders: HashMap<u32, OnlineClient<PolkadotConfig>>,
+    last_decoded: Option<DecodedBlock>,
+}
+
+impl RuntimeDecoders {
+    pub fn new(client: &JseeRpcClient<PolkadotConfig>) -> Self {
+        let mut decoders = HashMap::new();
+        let online = client.get_online();
+        decoders.insert(online.runtime_version().spec_version, online);
+        Self {
+            client: client.clone(),
+            decoders,
+            last_decoded: None,
+        }
+    }
+
+    /// Get the spec version and the online client to decode the block.
+    pub async fn decoder(
+        &mut self,
+        online_block: &OnlineBlock<PolkadotConfig, OnlineClient<PolkadotConfig>>,
+    ) -> anyhow::Result<(u32, OnlineClient<PolkadotConfig>)> {
+        let header = online_block.header();
+        let block_number = header.number as u64;
+        let spec_version = match self.last_decoded.as_ref() {
+            Some(last) if last.block_hash == header.parent_hash && !last.runtime_updated => {
+                last.spec_version
+            }
+            _ => self.fetch_spec_version(online_block).await?,
+        };
+
+        let runtime_updated = header
+            .digest
+            .logs
+            .iter()
+            .any(|log| matches!(log, DigestItem::RuntimeEnvironmentUpdated));
+        if runtime_updated {
+            tracing::info!(
+                "🧬 block #{} runtime environment updated, fetch runtime version for the next block",
+                block_number
+            );
+        }
+
+        let previous_spec_version = self.last_decoded.as_ref().map(|last| last.spec_version);
+        self.last_decoded = Some(DecodedBlock {
+            block_hash: online_block.hash(),
+            spec_version,
+            runtime_updated,
+        });
+
+        if previous_spec_version.is_some() && previous_spec_version != Some(spec_version) {
+            tracing::info!(
+                "🧬 block #{} switch decoder to runtime spec version {}",
+                block_number,
+                spec_version
+            );
+        }
+
+        let decoder = match self.decoders.get(&spec_version) {
+            Some(decoder) => decoder.clone(),
+            None => self.fetch_decoder(online_block, spec_version).await?,
+        };
+        Ok((spec_version, decoder))
+    }
+
+    /// The hash of the block which holds the runtime executing the block.
+    fn runtime_at(
+        online_block: &OnlineBlock<PolkadotConfig, OnlineClient<PolkadotConfig>>,
+    ) -> H256 {
+        match online_block.header().number {
+            0 => online_block.hash(),
+            _ => online_block.header().parent_hash,
+        }
+    }
+
+    async fn fetch_spec_version(
+        &self,
+        online_block: &OnlineBlock<PolkadotConfig, OnlineClient<PolkadotConfig>>,
+    ) -> anyhow::Result<u32> {
+        let at = Self::runtime_at(online_block);
+        self.client
+            .online
+            .rpc()
+            .runtime_version(Some(at))
+            .await
+            .map(|runtime_version| runtime_version.spec_version)
+            .map_err(|err| {
+                anyhow!(
+                    "block #{} get runtime version error: {}",
+                    online_block.header().number,
+                    err
+                )
+            })
+    }
+
+    async fn fetch_decoder(
+        &mut self,
+        online_block: &OnlineBlock<PolkadotConfig, OnlineClient<PolkadotConfig>>,
+        spec_version: u32,
+    ) -> anyhow::Result<OnlineClient<PolkadotConfig>> {
+        let block_number = online_block.header().number;
+        let at = Self::runtime_at(online_block);
+        let rpc = self.client.online.rpc();
+        let runtime_version = rpc
+            .runtime_version(Some(at))
+            .await
+            .map_err(|err| anyhow!("block #{} get runtime version error: {}", block_number, err))?;
+        let metadata = rpc.metadata_legacy(Some(at)).await.map_err(|err| {
+            anyhow!(
+                "block #{} get metadata of spec version {} error: {}",
+                block_number,
+                spec_version,
+                err
+            )
+        })?;
+
+        tracing::info!(
+            "🧬 block #{} fetched metadata of runtime spec version {}",
+            block_number,
+            spec_version
+        );
+        let decoder = self.client.online_with(runtime_version, metadata)?;
+        self.decoders.insert(spec_version, decoder.clone());
+        Ok(decoder)
+    }
+}
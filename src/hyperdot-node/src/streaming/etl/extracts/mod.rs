@@ -1,3 +1,4 @@
+pub mod metadata;
 pub mod polkadot;
 
 pub use polkadot::BlockExtracter as PolkadotBlockExtracter;
@@ -1,5 +1,7 @@
 //! Substrate chain extracter
 
+use std::collections::HashMap;
+
 use anyhow::anyhow;
 // use hyperdot_core::runtime_api::kusama;
 // use hyperdot_core::runtime_api::polkadot;
@@ -10,15 +12,20 @@ use subxt::blocks::BlockBody;
 use subxt::blocks::ExtrinsicDetails;
 use subxt::blocks::ExtrinsicEvents;
 use subxt::config::substrate::DigestItem;
-use subxt::constants::ConstantsClient;
+use subxt::config::Hasher;
 use subxt::events::EventDetails;
+use subxt::events::Events;
 use subxt::events::Phase;
 use subxt::ext::sp_runtime::key_types;
 use subxt::ext::sp_runtime::ConsensusEngineId;
+use subxt::Config;
 use subxt::OnlineClient;
 use subxt::PolkadotConfig;
+use tokio::sync::Mutex;
 
 // use subxt::SubstrateConfig;
+use super::metadata::RuntimeDecoders;
+use crate::rpc::JseeRpcClient;
 use crate::types::block::polkadot_chain;
 
 struct BodyBuilder {
@@ -60,40 +67,37 @@ impl BodyBuilder {
             .expect("cannot builder body, block_timestamp is none")
     }
 
-    pub(crate) async fn build(
+    /// Build the body from extrinsics and the events of block. The events
+    /// are fetched once per block and grouped by the extrinsic index.
+    pub(crate) fn build(
         &mut self,
         online_body: BlockBody<PolkadotConfig, OnlineClient<PolkadotConfig>>,
+        online_events: Events<PolkadotConfig>,
     ) -> anyhow::Result<()> {
         let block_number = self.get_block_number_uncheck();
-        for (i, online_ext) in online_body.extrinsics().iter().enumerate() {
+        let mut extrinsic_events: HashMap<u32, Vec<EventDetails<PolkadotConfig>>> = HashMap::new();
+        for event in online_events.iter() {
+            let event =
+                event.map_err(|err| anyhow!("block #{} get event error: {}", block_number, err))?;
+            if let Phase::ApplyExtrinsic(extrinsic_index) = event.phase() {
+                extrinsic_events
+                    .entry(extrinsic_index)
+                    .or_default()
+                    .push(event);
+            }
+        }
+
+        for online_ext in online_body.extrinsics().iter() {
             let online_ext = online_ext
                 .map_err(|err| anyhow!("block #{} get extrinsic error: {}", block_number, err))?;
 
-            let online_evs = online_ext.events().await.map_err(|err| {
-                anyhow!(
-                    "block #{} get extrinsic #{} events error: {}",
-                    block_number,
-                    online_ext.index(),
-                    err
-                )
-            })?;
-
-            let extrinsic_index = online_evs.extrinsic_index();
-            let extrinsic_hash = online_evs.extrinsic_hash().as_bytes().to_vec();
-
-            let mut events = vec![];
-            for event in online_evs.iter() {
-                let event = event.map_err(|err| {
-                    anyhow!(
-                        "block #{} get extrinsic #{} event error: {}",
-                        block_number,
-                        extrinsic_index,
-                        err
-                    )
-                })?;
-                events.push(event);
-            }
-            println!("extrisic_index = {} -> {}", extrinsic_index, events.len());
+            let extrinsic_index = online_ext.index();
+            let extrinsic_hash = <PolkadotConfig as Config>::Hasher::hash_of(&online_ext.bytes())
+                .as_bytes()
+                .to_vec();
+            let events = extrinsic_events
+                .remove(&extrinsic_index)
+                .unwrap_or_default();
 
             self.computing_event_state(&events);
             self.add_extrinisc(&extrinsic_hash, &online_ext);
@@ -247,9 +251,11 @@ impl StorageExtracter {
         &self,
         online_block: &OnlineBlock<PolkadotConfig, OnlineClient<PolkadotConfig>>,
     ) -> anyhow::Result<u64> {
+        // The static address is generated from the latest runtime, skip
+        // validation to fetch it with metadata of older runtimes.
         online_block
             .storage()
-            .fetch(&default_runtime::storage().timestamp().now())
+            .fetch(&default_runtime::storage().timestamp().now().unvalidated())
             .await?
             .map_or(Ok(0), |v| Ok(v))
     }
@@ -275,55 +281,77 @@ impl StorageExtracter {
 
         online_block
             .storage()
-            .fetch(&default_runtime::storage().authorship().author())
+            .fetch(
+                &default_runtime::storage()
+                    .authorship()
+                    .author()
+                    .unvalidated(),
+            )
             .await?
             .map_or(Ok(None), |v| Ok(Some(v.0.to_vec())))
     }
 }
 
-pub struct ConstantExtracter {
-    constrants: ConstantsClient<PolkadotConfig, OnlineClient<PolkadotConfig>>,
-}
-
-impl ConstantExtracter {
-    pub fn new(online_clinet: &OnlineClient<PolkadotConfig>) -> Self {
-        Self {
-            constrants: online_clinet.constants(),
-        }
-    }
-
-    pub fn runtime_version(
-        &self,
-    ) -> anyhow::Result<default_runtime::runtime_types::sp_version::RuntimeVersion> {
-        self.constrants
-            .at(&default_runtime::constants().system().version())
-            .map_err(|err| anyhow!("{}", err))
-    }
-}
-
+/// The block extracter is shared by the concurrent extractions, only
+/// the decoder resolving is serialized.
 pub struct BlockExtracter {
     online_client: OnlineClient<PolkadotConfig>,
-    body_builder: BodyBuilder,
     storage: StorageExtracter,
-    constant: ConstantExtracter,
+    decoders: Mutex<RuntimeDecoders>,
 }
 
 impl BlockExtracter {
-    pub fn new(online_client: &OnlineClient<PolkadotConfig>) -> Self {
+    pub fn new(client: &JseeRpcClient<PolkadotConfig>) -> Self {
         Self {
-            online_client: online_client.clone(),
+            online_client: client.get_online(),
             storage: StorageExtracter::new(),
-            constant: ConstantExtracter::new(&online_client),
-            body_builder: BodyBuilder::new(),
+            decoders: Mutex::new(RuntimeDecoders::new(client)),
         }
     }
 
     pub async fn extract(
-        &mut self,
+        &self,
+        online_block: OnlineBlock<PolkadotConfig, OnlineClient<PolkadotConfig>>,
+    ) -> anyhow::Result<polkadot_chain::Block> {
+        let decoder = self.decoder(&online_block).await?;
+        self.extract_with(online_block, decoder).await
+    }
+
+    /// Resolve the spec version and decoder of the block. Resolving the
+    /// blocks in order lets the runtime of the parent block be reused.
+    pub async fn decoder(
+        &self,
+        online_block: &OnlineBlock<PolkadotConfig, OnlineClient<PolkadotConfig>>,
+    ) -> anyhow::Result<(u32, OnlineClient<PolkadotConfig>)> {
+        self.decoders.lock().await.decoder(online_block).await
+    }
+
+    /// Extract the block with the decoder resolved, it can run concurrently.
+    pub async fn extract_with(
+        &self,
         online_block: OnlineBlock<PolkadotConfig, OnlineClient<PolkadotConfig>>,
+        (spec_version, decoder): (u32, OnlineClient<PolkadotConfig>),
     ) -> anyhow::Result<polkadot_chain::Block> {
+        // The block is decoded by the metadata of the runtime executed it,
+        // re-fetch it if the runtime differs from the online client.
+        let online_block = if spec_version == self.online_client.runtime_version().spec_version {
+            online_block
+        } else {
+            decoder
+                .blocks()
+                .at(online_block.hash())
+                .await
+                .map_err(|err| {
+                    anyhow!(
+                        "block #{} get block with spec version {} error: {}",
+                        online_block.header().number,
+                        spec_version,
+                        err
+                    )
+                })?
+        };
+
         let block_timestamp = self.storage.block_timestamp(&online_block).await?;
-        let runtime_version = self.constant.runtime_version()?;
         let validator = self.storage.validator(&online_block).await?;
 
         println!(
@@ -341,7 +369,7 @@ impl BlockExtracter {
             state_root: online_block.header().state_root.as_bytes().to_vec(),
             is_finished: true,
             validator,
-            spec_version: runtime_version.spec_version,
+            spec_version,
         };
 
         // extract logs
@@ -352,16 +380,21 @@ impl BlockExtracter {
             .await
             .map_err(|err| anyhow!("block #{} get body error: {}", header.block_number, err))?;
 
-        self.body_builder.set_block_number(header.block_number);
-        self.body_builder
-            .set_block_timestamp(header.block_timestamp);
-        self.body_builder.build(online_body).await?;
+        let online_events = online_block
+            .events()
+            .await
+            .map_err(|err| anyhow!("block #{} get events error: {}", header.block_number, err))?;
+
+        let mut body_builder = BodyBuilder::new();
+        body_builder.set_block_number(header.block_number);
+        body_builder.set_block_timestamp(header.block_timestamp);
+        body_builder.build(online_body, online_events)?;
 
-        // if self.body_builder.block_is_finish() {
+        // if body_builder.block_is_finish() {
         //     header.is_finished = true;
         // }
 
-        let body = self.body_builder.finish();
+        let body = body_builder.finish();
         Ok(polkadot_chain::Block {
             header,
             body,
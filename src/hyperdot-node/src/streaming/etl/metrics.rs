@@ -0,0 +1,96 @@
+//! Throughput metrics of the streaming.
+
+use std::time::Duration;
+use std::time::Instant;
+
+use tokio::sync::mpsc::Sender;
+
+/// The default interval of reporting throughput.
+pub const DEFAULT_REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Count the blocks passed a stage, and report the blocks per second
+/// periodically.
+pub struct Throughput {
+    chain: String,
+    stage: &'static str,
+    interval: Duration,
+    total: u64,
+    window_blocks: u64,
+    window_start: Instant,
+}
+
+impl Throughput {
+    pub fn new(chain: &str, stage: &'static str, interval: Duration) -> Self {
+        Self {
+            chain: chain.to_string(),
+            stage,
+            interval,
+            total: 0,
+            window_blocks: 0,
+            window_start: Instant::now(),
+        }
+    }
+
+    /// Record the blocks passed. Return the blocks per second of the
+    /// window if the report interval elapsed.
+    pub fn record(&mut self, blocks: u64) -> Option<f64> {
+        self.total += blocks;
+        self.window_blocks += blocks;
+
+        let elapsed = self.window_start.elapsed();
+        if elapsed < self.interval {
+            return None;
+        }
+
+        let rate = self.window_blocks as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+        tracing::info!(
+            "📈 {}: {} {:.2} blocks/s, total {} blocks",
+            self.chain,
+            self.stage,
+            rate,
+            self.total
+        );
+        self.window_blocks = 0;
+        self.window_start = Instant::now();
+        Some(rate)
+    }
+
+    /// The total blocks passed.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+}
+
+/// The number of messages queued in the bounded channel.
+pub fn queue_depth<T>(tx: &Sender<T>) -> usize {
+    tx.max_capacity() - tx.capacity()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throughput_record() {
+        let mut throughput = Throughput::new("Polkadot", "extracted", Duration::from_secs(3600));
+        assert_eq!(throughput.record(10), None);
+        assert_eq!(throughput.record(5), None);
+        assert_eq!(throughput.total(), 15);
+
+        let mut throughput = Throughput::new("Polkadot", "extracted", Duration::ZERO);
+        assert!(throughput.record(10).unwrap() > 0.0);
+        assert_eq!(throughput.record(0), Some(0.0));
+        assert_eq!(throughput.total(), 10);
+    }
+
+    #[tokio::test]
+    async fn test_queue_depth() {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(4);
+        assert_eq!(queue_depth(&tx), 0);
+        tx.send(1).await.unwrap();
+        tx.send(2).await.unwrap();
+        assert_eq!(queue_depth(&tx), 2);
+        rx.recv().await.unwrap();
+        assert_eq!(queue_depth(&tx), 1);
+    }
+}
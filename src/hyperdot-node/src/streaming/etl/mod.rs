@@ -1,5 +1,7 @@
+pub mod checkpoint;
 pub mod controller;
 pub mod extracts;
+pub mod metrics;
 pub mod streaming;
 pub mod sync;
 pub use controller::StreamingController;
@@ -1,5 +1,9 @@
 // use std::marker::PhantomData;
+use std::collections::BTreeMap;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::anyhow;
 use hyperdot_core::config::ChainConfig;
@@ -8,12 +12,17 @@ use hyperdot_core::types::ChainKind;
 // use subxt::Config;
 // use subxt::PolkadotConfig;
 // use subxt::SubstrateConfig;
-use tokio::sync::mpsc::unbounded_channel;
-use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::mpsc::channel;
+use tokio::sync::mpsc::Receiver;
 use tokio::task::JoinHandle;
 
+use super::checkpoint::Checkpoint;
+use super::checkpoint::CheckpointStore;
+use super::metrics::Throughput;
+use super::metrics::DEFAULT_REPORT_INTERVAL;
 use super::sync::PolkadotSyncer;
 use super::sync::PolkadotSyncerHandle;
+use super::sync::Repairer;
 // use super::Syncer;
 use crate::streaming::speaker;
 // use crate::streaming::speaker::SpeakerController;
@@ -21,21 +30,45 @@ use crate::types::block::polkadot_chain;
 // use crate::types::polkadot;
 use crate::types::rpc::WriteBlock;
 
+/// The default interval seconds of gap repair.
+const DEFAULT_GAP_REPAIR_INTERVAL: u64 = 60;
+
+/// The interval of advancing the checkpoint over the acknowledged blocks.
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The default max blocks queued between the syncer and the streamer.
+const DEFAULT_BLOCK_QUEUE_SIZE: usize = 256;
+
 pub struct BlockStreamingHandle2 {
     sync_handle: PolkadotSyncerHandle,
     streaming_tg: JoinHandle<anyhow::Result<()>>,
+    repair_tg: Option<JoinHandle<()>>,
 }
 
 impl BlockStreamingHandle2 {
     pub async fn stopped(self) -> anyhow::Result<()> {
         self.sync_handle.stopped().await?;
-        self.streaming_tg.await?
+        let result = self.streaming_tg.await?;
+        if let Some(repair_tg) = self.repair_tg {
+            repair_tg.abort();
+        }
+        result
     }
 }
 
 pub struct BlockStreaming2 {
     chain: ChainConfig,
     storage_nodes: Vec<StorageNodeConfig>,
+    checkpoint_store: Arc<CheckpointStore>,
+    /// The highest finalized block number written to the outboxes of all
+    /// storage nodes plus one, zero means nothing written.
+    written_until: Arc<AtomicU64>,
+    /// The highest unfinalized best block number written plus one, the
+    /// best blocks could be orphaned so they're not counted as written.
+    best_until: Arc<AtomicU64>,
+    /// The first finalized block number streamed plus one, it's the
+    /// backfill start if configured, zero means not known yet.
+    origin: Arc<AtomicU64>,
 }
 
 impl BlockStreaming2 {
@@ -43,10 +76,20 @@ impl BlockStreaming2 {
         chain: &ChainConfig,
         storage_nodes: &Vec<StorageNodeConfig>,
         speaker_controller: Arc<crate::streaming::speaker::Controller>,
+        checkpoint_store: Arc<CheckpointStore>,
     ) -> anyhow::Result<BlockStreamingHandle2> {
         let bs = BlockStreaming2 {
             chain: chain.clone(),
             storage_nodes: storage_nodes.clone(),
+            checkpoint_store,
+            written_until: Arc::new(AtomicU64::new(0)),
+            best_until: Arc::new(AtomicU64::new(0)),
+            origin: Arc::new(AtomicU64::new(
+                chain
+                    .backfill
+                    .as_ref()
+                    .map_or(0, |backfill| backfill.start + 1),
+            )),
         };
         match chain.kind {
             ChainKind::Ethereum => {
@@ -66,36 +109,194 @@ impl BlockStreaming2 {
         };
 
         tracing::info!("🤔 {}: spawn polkadot chain", self.chain.name);
-        let (tx, rx) = unbounded_channel();
+        // The syncer waits when the queue is full, so a slow storage node
+        // slows down the fetching instead of buffering without limit.
+        let queue_size = self
+            .chain
+            .block_queue_size
+            .unwrap_or(DEFAULT_BLOCK_QUEUE_SIZE)
+            .max(1);
+        let (tx, rx) = channel(queue_size);
         let sync_handle = PolkadotSyncer::spawn(&self.chain, tx).await?;
         // let sync_handle = match runtime {
         //     "polkadot" => PolkadotSyncer::spawn_polkadot(&self.chain, tx).await?,
         //     _ => PolkadotSyncer::spawn_substrate(&self.chain, tx).await?,
         // };
 
+        let repair_interval = self
+            .chain
+            .gap_repair_interval
+            .unwrap_or(DEFAULT_GAP_REPAIR_INTERVAL);
+        let repair_tg = if repair_interval == 0 {
+            tracing::info!("💁 {}: gap repair disabled", self.chain.name);
+            None
+        } else {
+            let chain_name = self.chain.name.clone();
+            let written_until = self.written_until.clone();
+            let origin = self.origin.clone();
+            let speaker_controller = speaker_controller.clone();
+            let repairer = sync_handle.repairer();
+            Some(tokio::spawn(async move {
+                Self::gap_repair_loop(
+                    chain_name,
+                    Duration::from_secs(repair_interval),
+                    written_until,
+                    origin,
+                    speaker_controller,
+                    repairer,
+                )
+                .await
+            }))
+        };
+
         let tg =
             tokio::spawn(async move { self.polkadot_runtime_loop(rx, speaker_controller).await });
         return Ok(BlockStreamingHandle2 {
             streaming_tg: tg,
             sync_handle,
+            repair_tg,
         });
     }
 
+    /// Periodically find the blocks missing from the storage nodes from
+    /// the origin up to the highest finalized written block, and re-queue
+    /// them to the syncer.
+    async fn gap_repair_loop(
+        chain_name: String,
+        interval: Duration,
+        written_until: Arc<AtomicU64>,
+        origin: Arc<AtomicU64>,
+        speaker_controller: Arc<speaker::Controller>,
+        repairer: Repairer,
+    ) {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let mut end = match written_until.load(Ordering::Acquire) {
+                0 => continue,
+                n => n - 1,
+            };
+            let start = match origin.load(Ordering::Acquire) {
+                0 => continue,
+                n => n - 1,
+            };
+
+            // The blocks still in outboxes are not gaps, only find the
+            // gaps below them.
+            let lags = match speaker_controller.lags(&chain_name).await {
+                Err(err) => {
+                    tracing::warn!("🩹 {}: get storage node lags error: {}", chain_name, err);
+                    continue;
+                }
+                Ok(lags) => lags,
+            };
+            if let Some(pending) = lags.iter().filter_map(|lag| lag.pending_block_number).min() {
+                match pending.checked_sub(1) {
+                    None => continue,
+                    Some(pending_end) => end = std::cmp::min(end, pending_end),
+                }
+            }
+
+            if start > end {
+                continue;
+            }
+
+            let gaps = match speaker_controller
+                .block_gaps(&chain_name, Some(start), end)
+                .await
+            {
+                Err(err) => {
+                    tracing::warn!("🩹 {}: find block gaps error: {}", chain_name, err);
+                    continue;
+                }
+                Ok(gaps) => gaps,
+            };
+
+            let queued = match repairer.repair(gaps) {
+                Err(err) => {
+                    tracing::error!("🩹 {}: {}, stop gap repair", chain_name, err);
+                    return;
+                }
+                Ok(queued) => queued,
+            };
+            for gap in queued.into_iter() {
+                tracing::warn!(
+                    "🩹 {}: found missing blocks #{}..#{}, re-queue them",
+                    chain_name,
+                    gap.start,
+                    gap.end
+                );
+            }
+        }
+    }
+
+    fn report_written(&self) {
+        let written_until = self.written_until.load(Ordering::Acquire);
+        let best_until = self.best_until.load(Ordering::Acquire);
+        if best_until > written_until {
+            tracing::info!(
+                "⏩ {}: written finalized until #{}, best until #{}",
+                self.chain.name,
+                written_until.saturating_sub(1),
+                best_until - 1
+            );
+        }
+    }
+
+    async fn report_lags(chain_name: &str, speaker_controller: &speaker::Controller) {
+        let lags = match speaker_controller.lags(chain_name).await {
+            Err(_) => return,
+            Ok(lags) => lags,
+        };
+        for lag in lags.iter() {
+            tracing::info!(
+                "📮 {}: storage node({}) lag {} requests, delivered until #{:?}, stored {} blocks, finalized acked {:?}",
+                chain_name,
+                lag.node,
+                lag.pending,
+                lag.delivered_block_number,
+                lag.acked_blocks,
+                lag.acked
+            );
+        }
+    }
+
     async fn polkadot_runtime_loop(
         self,
-        mut rx: UnboundedReceiver<polkadot_chain::Block>,
+        mut rx: Receiver<polkadot_chain::Block>,
         speaker_controller: Arc<speaker::Controller>,
     ) -> anyhow::Result<()> {
+        // The checkpoint advances over the finalized blocks acknowledged
+        // by all storage nodes, so a restart resumes from the first block
+        // not stored, it's unblocked once the block repaired.
+        let mut checkpoint_from = self.chain.backfill.as_ref().map(|backfill| backfill.start);
+        let mut finalized_hashes = BTreeMap::new();
+        let mut checkpoint_ticker = tokio::time::interval(CHECKPOINT_INTERVAL);
+        let mut throughput = Throughput::new(&self.chain.name, "written", DEFAULT_REPORT_INTERVAL);
         loop {
-            let block = match rx.recv().await {
-                None => {
-                    tracing::error!("block channel closed");
-                    return Err(anyhow!("channel of syncer closed"));
+            let block = tokio::select! {
+                block = rx.recv() => match block {
+                    None => {
+                        tracing::error!("block channel closed");
+                        return Err(anyhow!("channel of syncer closed"));
+                    }
+                    Some(block) => block,
+                },
+                _ = checkpoint_ticker.tick() => {
+                    if let Some(from) = checkpoint_from {
+                        checkpoint_from = Some(
+                            self.advance_checkpoint(from, &mut finalized_hashes, &speaker_controller)
+                                .await,
+                        );
+                    }
+                    continue;
                 }
-                Some(block) => block,
             };
 
             let block_number = block.header.block_number;
+            let block_hash = format!("0x{}", hex::encode(&block.header.block_hash));
+            let finalized = block.header.is_finished;
 
             println!(
                 "{} \n block #{}, size {}",
@@ -124,6 +325,78 @@ impl BlockStreaming2 {
                     tracing::info!("{}: write block #{} success", self.chain.name, block_number);
                 }
             }
+            match finalized {
+                true => self
+                    .written_until
+                    .fetch_max(block_number + 1, Ordering::AcqRel),
+                false => self
+                    .best_until
+                    .fetch_max(block_number + 1, Ordering::AcqRel),
+            };
+            if throughput.record(1).is_some() {
+                self.report_written();
+                Self::report_lags(&self.chain.name, &speaker_controller).await;
+            }
+
+            // Repaired blocks behind the checkpoint never move it back.
+            if finalized {
+                let _ = self.origin.compare_exchange(
+                    0,
+                    block_number + 1,
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                );
+            }
+            if finalized && checkpoint_from.is_none_or(|from| block_number >= from) {
+                checkpoint_from.get_or_insert(block_number);
+                finalized_hashes.insert(block_number, block_hash);
+            }
+        }
+    }
+
+    /// Save the checkpoint at the last finalized block that all blocks
+    /// from `from` to it acknowledged by all storage nodes, return the
+    /// block number to advance from next time.
+    async fn advance_checkpoint(
+        &self,
+        from: u64,
+        finalized_hashes: &mut BTreeMap<u64, String>,
+        speaker_controller: &speaker::Controller,
+    ) -> u64 {
+        let acked_until = match speaker_controller.acked_until(&self.chain.name, from).await {
+            Err(err) => {
+                tracing::warn!("🔖 {}: get acked blocks error: {}", self.chain.name, err);
+                return from;
+            }
+            Ok(None) => return from,
+            Ok(Some(acked_until)) => acked_until,
+        };
+
+        // The acknowledged blocks may be written by other speakers, the
+        // checkpoint is the last one written by this.
+        let (block_number, block_hash) =
+            match finalized_hashes.range(from..=acked_until).next_back() {
+                None => return from,
+                Some((block_number, block_hash)) => (*block_number, block_hash.clone()),
+            };
+        let checkpoint = Checkpoint {
+            block_number,
+            block_hash,
+        };
+        if let Err(err) = self
+            .checkpoint_store
+            .save(&self.chain.name, &checkpoint)
+            .await
+        {
+            tracing::error!("{}: save checkpoint error: {}", self.chain.name, err);
+            return from;
+        }
+
+        let from = block_number + 1;
+        *finalized_hashes = finalized_hashes.split_off(&from);
+        if let Err(err) = speaker_controller.prune_acked(&self.chain.name, from).await {
+            tracing::warn!("🔖 {}: prune acked blocks error: {}", self.chain.name, err);
         }
+        from
     }
 }
@@ -1,80 +1,657 @@
+use std::collections::BTreeMap;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
 use anyhow::anyhow;
+use futures::Stream;
 use futures::StreamExt;
+use hyperdot_core::config::BackfillConfig;
 use hyperdot_core::config::ChainConfig;
+use subxt::blocks::Block as OnlineBlock;
+use subxt::OnlineClient;
 use subxt::PolkadotConfig;
-use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::mpsc::channel;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::Receiver;
+use tokio::sync::mpsc::Sender;
 use tokio::task::JoinHandle;
-use url::Url;
 
 use super::extracts::PolkadotBlockExtracter;
+use super::metrics::queue_depth;
+use super::metrics::Throughput;
+use super::metrics::DEFAULT_REPORT_INTERVAL;
 use crate::rpc::JseeRpcClient;
 use crate::rpc::JseeRpcClientParams;
 use crate::types::block::polkadot_chain;
+use crate::types::rpc::BlockRange;
+
+/// The default max blocks extracted at once when syncing a range.
+const DEFAULT_EXTRACT_CONCURRENCY: usize = 8;
+
+/// The min backoff of retrying a failed rpc call.
+const MIN_RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The max backoff of retrying a failed rpc call.
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(60);
+
+/// The times a block failed to extract is retried before skipped, the
+/// skipped block is left to the gap repair.
+const MAX_EXTRACT_ATTEMPTS: usize = 5;
+
+/// The max ranges of missing blocks queued for repair.
+const REPAIR_QUEUE_SIZE: usize = 16;
+
+type OnlineBlockStream = Pin<
+    Box<
+        dyn Stream<
+                Item = Result<
+                    OnlineBlock<PolkadotConfig, OnlineClient<PolkadotConfig>>,
+                    subxt::Error,
+                >,
+            > + Send,
+    >,
+>;
 
 pub struct PolkadotSyncerHandle {
     tg: JoinHandle<anyhow::Result<()>>,
+    repair_tg: JoinHandle<anyhow::Result<()>>,
+    repairer: Repairer,
 }
 
 impl PolkadotSyncerHandle {
+    /// Get the repairer to re-queue the missing blocks for extraction.
+    pub fn repairer(&self) -> Repairer {
+        self.repairer.clone()
+    }
+
     pub async fn stopped(self) -> anyhow::Result<()> {
-        self.tg.await?
+        let result = self.tg.await?;
+        self.repair_tg.abort();
+        result
+    }
+}
+
+/// Queues the missing blocks to the repair task of the syncer, the
+/// ranges in flight are not queued again until repaired.
+#[derive(Clone)]
+pub struct Repairer {
+    tx: Sender<BlockRange>,
+    in_flight: Arc<Mutex<Vec<BlockRange>>>,
+}
+
+impl Repairer {
+    /// Queue the missing blocks not in flight, return the ranges queued.
+    /// The ranges left when the queue is full are found again later.
+    pub fn repair(&self, gaps: Vec<BlockRange>) -> anyhow::Result<Vec<BlockRange>> {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        let mut queued = vec![];
+        for gap in BlockRange::subtract(gaps, &in_flight) {
+            match self.tx.try_send(gap.clone()) {
+                Err(TrySendError::Full(_)) => break,
+                Err(TrySendError::Closed(_)) => return Err(anyhow!("repair task closed")),
+                Ok(_) => {
+                    in_flight.push(gap.clone());
+                    queued.push(gap);
+                }
+            }
+        }
+        Ok(queued)
+    }
+
+    /// Mark the range repaired.
+    fn done(&self, range: &BlockRange) {
+        self.in_flight
+            .lock()
+            .unwrap()
+            .retain(|in_flight| in_flight != range);
     }
 }
 
 pub struct PolkadotSyncer {
+    chain: String,
+    /// The rpc endpoints of chain and the index of connected one.
+    endpoints: Vec<String>,
+    endpoint_index: usize,
     client: JseeRpcClient<PolkadotConfig>,
-    block_extractor: PolkadotBlockExtracter,
+    block_extractor: Arc<PolkadotBlockExtracter>,
+    backfill: Option<BackfillConfig>,
+    follow_best: bool,
+    /// The max blocks extracted at once when syncing a range.
+    concurrency: usize,
+    throughput: Throughput,
+    /// The number of the last finalized block followed.
+    finalized_block_number: Option<u64>,
+    /// The hashes of unfinalized best blocks sent, used to detect reorgs.
+    best_block_hashes: BTreeMap<u64, Vec<u8>>,
 }
 
 impl PolkadotSyncer {
     pub async fn spawn(
         chain: &ChainConfig,
-        tx: UnboundedSender<polkadot_chain::Block>,
+        tx: Sender<polkadot_chain::Block>,
     ) -> anyhow::Result<PolkadotSyncerHandle> {
-        // TODO: move to util
-        let url = Url::parse(&chain.url)
-            .map_err(|err| anyhow!("{} parse url({}) error: {}", chain.name, chain.url, err))?;
-        let client =
-            JseeRpcClient::<PolkadotConfig>::async_new(&chain.url, &JseeRpcClientParams::default())
-                .await
-                .map_err(|err| anyhow!("{}: new rpc client error: {}", chain.name, err))?;
-
-        let block_extractor = PolkadotBlockExtracter::new(&client.online);
-        let syncer = PolkadotSyncer {
-            client,
-            block_extractor,
-        };
+        // The repairs run on their own connection, so they never hold
+        // up following the chain.
+        let syncer = Self::connect(chain, "extracted").await?;
+        let repair_syncer = Self::connect(chain, "repaired").await?;
 
+        let (repair_tx, repair_rx) = channel(REPAIR_QUEUE_SIZE);
+        let repairer = Repairer {
+            tx: repair_tx,
+            in_flight: Arc::new(Mutex::new(vec![])),
+        };
+        let repair_tg = tokio::spawn({
+            let tx = tx.clone();
+            let repairer = repairer.clone();
+            async move { repair_syncer.repair_loop(tx, repair_rx, repairer).await }
+        });
         let tg = tokio::spawn(async move { syncer.main_loop(tx).await });
 
-        Ok(PolkadotSyncerHandle { tg })
+        Ok(PolkadotSyncerHandle {
+            tg,
+            repair_tg,
+            repairer,
+        })
+    }
+
+    async fn connect(chain: &ChainConfig, throughput_name: &'static str) -> anyhow::Result<Self> {
+        let endpoints = chain.endpoints();
+        let (endpoint_index, client) = JseeRpcClient::<PolkadotConfig>::async_new_failover(
+            &endpoints,
+            0,
+            &JseeRpcClientParams::default(),
+            Some(1),
+        )
+        .await
+        .map_err(|err| anyhow!("{}: new rpc client error: {}", chain.name, err))?;
+
+        let block_extractor = Arc::new(PolkadotBlockExtracter::new(&client));
+        Ok(PolkadotSyncer {
+            chain: chain.name.clone(),
+            endpoints,
+            endpoint_index,
+            client,
+            block_extractor,
+            backfill: chain.backfill.clone(),
+            follow_best: chain.follow_best.unwrap_or(false),
+            concurrency: chain
+                .extract_concurrency
+                .unwrap_or(DEFAULT_EXTRACT_CONCURRENCY)
+                .max(1),
+            throughput: Throughput::new(&chain.name, throughput_name, DEFAULT_REPORT_INTERVAL),
+            finalized_block_number: None,
+            best_block_hashes: BTreeMap::new(),
+        })
+    }
+
+    /// Extract the missing blocks queued and send them to the streamer.
+    async fn repair_loop(
+        mut self,
+        tx: Sender<polkadot_chain::Block>,
+        mut repair_rx: Receiver<BlockRange>,
+        repairer: Repairer,
+    ) -> anyhow::Result<()> {
+        while let Some(range) = repair_rx.recv().await {
+            tracing::info!(
+                "🩹 {}: repair blocks #{}..#{}",
+                self.chain,
+                range.start,
+                range.end
+            );
+            let sent = self.sync_range(range.start, range.end, &tx).await;
+            repairer.done(&range);
+            if !sent? {
+                return Ok(());
+            }
+        }
+        Ok(())
     }
 
-    async fn main_loop(mut self, tx: UnboundedSender<polkadot_chain::Block>) -> anyhow::Result<()> {
-        let mut blocks_sub = self.client.online.blocks().subscribe_finalized().await?;
-        while let Some(online_block) = blocks_sub.next().await {
-            let online_block = match online_block {
+    async fn main_loop(mut self, tx: Sender<polkadot_chain::Block>) -> anyhow::Result<()> {
+        // The next block number expected to be sent, none means
+        // following the finalized head from anywhere.
+        let mut next_block_number = None;
+        if let Some(backfill) = self.backfill.take() {
+            let end = match backfill.end {
+                Some(end) => end,
+                None => {
+                    let mut backoff = MIN_RETRY_BACKOFF;
+                    loop {
+                        match self.fetch_finalized_block_number().await {
+                            Err(err) => {
+                                tracing::warn!(
+                                    "{}: fetch finalized head error: {}",
+                                    self.chain,
+                                    err
+                                );
+                                self.recover(&mut backoff).await?;
+                            }
+                            Ok(end) => break end,
+                        }
+                    }
+                }
+            };
+
+            if backfill.start <= end {
+                tracing::info!(
+                    "⏪ {}: backfill blocks #{}..#{}",
+                    self.chain,
+                    backfill.start,
+                    end
+                );
+            }
+            if !self.sync_range(backfill.start, end, &tx).await? {
+                return Ok(());
+            }
+            next_block_number = Some(end + 1);
+        }
+
+        // Resubscribe after reconnected, the blocks finalized while
+        // disconnected are filled from the next block number.
+        let mut backoff = MIN_RETRY_BACKOFF;
+        loop {
+            let (mut blocks_sub, mut best_blocks_sub) = match self.subscribe().await {
                 Err(err) => {
-                    tracing::warn!("sub block body: {}", err);
+                    tracing::warn!("{}: subscribe blocks error: {}", self.chain, err);
+                    self.recover(&mut backoff).await?;
                     continue;
                 }
-                Ok(b) => b,
+                Ok(subs) => subs,
             };
+            backoff = MIN_RETRY_BACKOFF;
+
+            loop {
+                tokio::select! {
+                    online_block = blocks_sub.next() => {
+                        let online_block = match online_block {
+                            None => break,
+                            Some(Err(err)) => {
+                                tracing::warn!("sub block body: {}", err);
+                                if !self.client.is_connected() {
+                                    break;
+                                }
+                                continue;
+                            }
+                            Some(Ok(b)) => b,
+                        };
+
+                        if !self.follow_block(online_block, &mut next_block_number, &tx).await? {
+                            return Ok(());
+                        }
+                    }
+                    online_block = best_blocks_sub.next() => {
+                        let online_block = match online_block {
+                            None => break,
+                            Some(Err(err)) => {
+                                tracing::warn!("sub best block body: {}", err);
+                                if !self.client.is_connected() {
+                                    break;
+                                }
+                                continue;
+                            }
+                            Some(Ok(b)) => b,
+                        };
+
+                        if !self.follow_best_block(online_block, &tx).await? {
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+
+            // The subscription is dropped by the node if lagged too far
+            // behind while waiting for the full block queue, resubscribe
+            // without reconnecting in that case.
+            if self.client.is_connected() {
+                tracing::warn!("{}: block subscription closed, resubscribe", self.chain);
+                continue;
+            }
+            self.reconnect().await?;
+        }
+    }
+
+    /// Subscribe the finalized blocks, and the best blocks if follow them.
+    async fn subscribe(&mut self) -> anyhow::Result<(OnlineBlockStream, OnlineBlockStream)> {
+        let blocks_sub = self.client.online.blocks().subscribe_finalized().await?;
+        let best_blocks_sub: OnlineBlockStream = if self.follow_best {
+            tracing::info!("⏩ {}: follow best blocks", self.chain);
+            self.finalized_block_number = Some(self.fetch_finalized_block_number().await?);
+            self.client.online.blocks().subscribe_best().await?
+        } else {
+            Box::pin(futures::stream::pending())
+        };
+        Ok((blocks_sub, best_blocks_sub))
+    }
+
+    /// Reconnect if disconnected, otherwise wait the backoff, before
+    /// retrying a failed rpc call.
+    async fn recover(&mut self, backoff: &mut Duration) -> anyhow::Result<()> {
+        if !self.client.is_connected() {
+            *backoff = MIN_RETRY_BACKOFF;
+            return self.reconnect().await;
+        }
+
+        tokio::time::sleep(*backoff).await;
+        *backoff = std::cmp::min(*backoff * 2, MAX_RETRY_BACKOFF);
+        Ok(())
+    }
+
+    /// Reconnect to the next endpoint in rotation with backoff until connected.
+    async fn reconnect(&mut self) -> anyhow::Result<()> {
+        tracing::warn!(
+            "🔌 {}: rpc endpoint({}) disconnected, reconnecting",
+            self.chain,
+            self.endpoints[self.endpoint_index]
+        );
+        let (endpoint_index, client) = JseeRpcClient::<PolkadotConfig>::async_new_failover(
+            &self.endpoints,
+            self.endpoint_index + 1,
+            &JseeRpcClientParams::default(),
+            None,
+        )
+        .await
+        .map_err(|err| anyhow!("{}: reconnect rpc endpoints error: {}", self.chain, err))?;
+
+        self.endpoint_index = endpoint_index;
+        self.block_extractor = Arc::new(PolkadotBlockExtracter::new(&client));
+        self.client = client;
+        Ok(())
+    }
+
+    /// Extract and send the finalized block. Return false if the
+    /// streaming channel closed.
+    async fn follow_block(
+        &mut self,
+        online_block: OnlineBlock<PolkadotConfig, OnlineClient<PolkadotConfig>>,
+        next_block_number: &mut Option<u64>,
+        tx: &Sender<polkadot_chain::Block>,
+    ) -> anyhow::Result<bool> {
+        // Skip the blocks already synced and fill the blocks
+        // between the last synced and the finalized head.
+        let block_number = online_block.header().number as u64;
+        self.finalized_block_number = Some(block_number);
+        self.best_block_hashes = self.best_block_hashes.split_off(&(block_number + 1));
+        if let Some(next_block_number) = *next_block_number {
+            if block_number < next_block_number {
+                return Ok(true);
+            }
 
-            let extracted_block = match self.block_extractor.extract(online_block).await {
+            if block_number > next_block_number
+                && !self
+                    .sync_range(next_block_number, block_number - 1, tx)
+                    .await?
+            {
+                return Ok(false);
+            }
+        }
+        match self.sync_block(online_block, true, tx).await {
+            Err(err) => {
+                // Fill it with the next finalized block.
+                tracing::warn!(
+                    "{}: extract block #{} error: {}, retry later",
+                    self.chain,
+                    block_number,
+                    err
+                );
+                *next_block_number = Some(block_number);
+                Ok(true)
+            }
+            Ok(sent) => {
+                *next_block_number = Some(block_number + 1);
+                Ok(sent)
+            }
+        }
+    }
+
+    /// Extract and send the unfinalized best block. If the parent hash not
+    /// match the best block sent before, or the parent was never sent, the
+    /// abandoned fork is replaced by re-sending the blocks from the fork
+    /// point. Return false if the streaming channel closed.
+    async fn follow_best_block(
+        &mut self,
+        online_block: OnlineBlock<PolkadotConfig, OnlineClient<PolkadotConfig>>,
+        tx: &Sender<polkadot_chain::Block>,
+    ) -> anyhow::Result<bool> {
+        let block_number = online_block.header().number as u64;
+        let finalized = match self.finalized_block_number {
+            Some(finalized) if block_number > finalized => finalized,
+            // already finalized or not know the finalized yet.
+            _ => return Ok(true),
+        };
+
+        // Walk back to the fork point, the finalized blocks are sent by
+        // following the finalized heads.
+        let mut fork_blocks = vec![];
+        let mut parent_hash = online_block.header().parent_hash;
+        let mut parent_number = block_number - 1;
+        while parent_number > finalized {
+            if let Some(sent_hash) = self.best_block_hashes.get(&parent_number) {
+                if sent_hash.as_slice() == parent_hash.as_bytes() {
+                    break;
+                }
+            }
+
+            let parent_block = match self.client.online.blocks().at(parent_hash).await {
                 Err(err) => {
-                    tracing::warn!("handle block ext error: {}", err);
-                    continue;
+                    tracing::warn!(
+                        "{}: fetch fork block #{} error: {}",
+                        self.chain,
+                        parent_number,
+                        err
+                    );
+                    return Ok(true);
                 }
                 Ok(b) => b,
             };
+            parent_hash = parent_block.header().parent_hash;
+            fork_blocks.push(parent_block);
+            parent_number -= 1;
+        }
+
+        if !fork_blocks.is_empty() {
+            tracing::warn!(
+                "🔀 {}: reorg or unsent blocks at #{}, send {} blocks",
+                self.chain,
+                parent_number + 1,
+                fork_blocks.len()
+            );
+        }
+
+        for online_block in fork_blocks.into_iter().rev().chain(Some(online_block)) {
+            let block_number = online_block.header().number as u64;
+            let block_hash = online_block.hash().as_bytes().to_vec();
+            // Forget the blocks sent on the abandoned fork. The block is
+            // recorded once sent, so the next best block walks back to it
+            // if it failed.
+            let _ = self.best_block_hashes.split_off(&block_number);
+            match self.sync_block(online_block, false, tx).await {
+                // The block is sent once finalized.
+                Err(err) => {
+                    tracing::warn!(
+                        "{}: extract best block #{} error: {}",
+                        self.chain,
+                        block_number,
+                        err
+                    );
+                    return Ok(true);
+                }
+                Ok(false) => return Ok(false),
+                Ok(true) => {
+                    self.best_block_hashes.insert(block_number, block_hash);
+                }
+            }
+        }
+
+        Ok(true)
+    }
 
-            if let Err(err) = tx.send(extracted_block) {
-                tracing::error!("streaming channel closed");
-                break;
+    /// Extract and send blocks from `start` to `end` inclusive. Return false
+    /// if the streaming channel closed.
+    ///
+    /// The blocks are fetched and extracted concurrently in a pipeline,
+    /// and re-ordered by block number before sending.
+    async fn sync_range(
+        &mut self,
+        start: u64,
+        end: u64,
+        tx: &Sender<polkadot_chain::Block>,
+    ) -> anyhow::Result<bool> {
+        let mut block_number = start;
+        let mut backoff = MIN_RETRY_BACKOFF;
+        // The block failed to extract and the times.
+        let mut failed = (start, 0);
+        while block_number <= end {
+            // The block number to resume from after reconnected.
+            let mut resume_block_number = None;
+            {
+                let online = self.client.online.clone();
+                let block_extractor = self.block_extractor.clone();
+                let blocks = futures::stream::iter(block_number..=end)
+                    .map(|block_number| {
+                        let online = online.clone();
+                        async move {
+                            let online_block = Self::fetch_block(&online, block_number).await;
+                            (block_number, online_block)
+                        }
+                    })
+                    .buffered(self.concurrency)
+                    // Resolve decoders in order to reuse the runtime of parent block.
+                    .then(|(block_number, online_block)| {
+                        let block_extractor = block_extractor.clone();
+                        async move {
+                            let decoded = match online_block {
+                                Err(err) => Err(err),
+                                Ok(online_block) => block_extractor
+                                    .decoder(&online_block)
+                                    .await
+                                    .map(|decoder| (online_block, decoder)),
+                            };
+                            (block_number, decoded)
+                        }
+                    })
+                    .map(|(block_number, decoded)| {
+                        let block_extractor = block_extractor.clone();
+                        async move {
+                            let block = match decoded {
+                                Err(err) => Err(err),
+                                Ok((online_block, decoder)) => {
+                                    block_extractor.extract_with(online_block, decoder).await
+                                }
+                            };
+                            (block_number, block)
+                        }
+                    })
+                    .buffered(self.concurrency);
+                futures::pin_mut!(blocks);
+
+                while let Some((number, block)) = blocks.next().await {
+                    let mut block = match block {
+                        Err(err) => {
+                            tracing::warn!(
+                                "{}: extract block #{} error: {}",
+                                self.chain,
+                                number,
+                                err
+                            );
+                            // Retry from the block after recovered.
+                            resume_block_number = Some(number);
+                            break;
+                        }
+                        Ok(block) => block,
+                    };
+
+                    block.header.is_finished = true;
+                    // Wait if the queue is full, the pipeline stops fetching
+                    // more blocks until the streamer catches up.
+                    if tx.send(block).await.is_err() {
+                        tracing::error!("streaming channel closed");
+                        return Ok(false);
+                    }
+                    self.record_throughput(tx);
+                }
+            }
+
+            let number = match resume_block_number {
+                None => break,
+                Some(number) => number,
+            };
+            failed = match failed {
+                (failed_number, attempts) if failed_number == number => (number, attempts + 1),
+                _ => (number, 1),
+            };
+            if failed.1 >= MAX_EXTRACT_ATTEMPTS && self.client.is_connected() {
+                tracing::error!(
+                    "{}: extract block #{} failed {} times, skip it for gap repair",
+                    self.chain,
+                    number,
+                    failed.1
+                );
+                block_number = number + 1;
+                continue;
             }
+            self.recover(&mut backoff).await?;
+            block_number = number;
         }
 
-        Ok(())
+        Ok(true)
+    }
+
+    /// Extract and send a block. Return false if the streaming channel
+    /// closed, or error if failed to extract.
+    async fn sync_block(
+        &mut self,
+        online_block: OnlineBlock<PolkadotConfig, OnlineClient<PolkadotConfig>>,
+        is_finalized: bool,
+        tx: &Sender<polkadot_chain::Block>,
+    ) -> anyhow::Result<bool> {
+        let mut extracted_block = self.block_extractor.extract(online_block).await?;
+        extracted_block.header.is_finished = is_finalized;
+
+        if tx.send(extracted_block).await.is_err() {
+            tracing::error!("streaming channel closed");
+            return Ok(false);
+        }
+        self.record_throughput(tx);
+
+        Ok(true)
+    }
+
+    /// Record a block sent, report the depth of the block queue along
+    /// with the throughput.
+    fn record_throughput(&mut self, tx: &Sender<polkadot_chain::Block>) {
+        if self.throughput.record(1).is_some() {
+            tracing::info!(
+                "📦 {}: block queue depth {}/{}",
+                self.chain,
+                queue_depth(tx),
+                tx.max_capacity()
+            );
+        }
+    }
+
+    /// Fetch the block by number.
+    async fn fetch_block(
+        online: &OnlineClient<PolkadotConfig>,
+        block_number: u64,
+    ) -> anyhow::Result<OnlineBlock<PolkadotConfig, OnlineClient<PolkadotConfig>>> {
+        let block_hash = online
+            .rpc()
+            .block_hash(Some(block_number.into()))
+            .await?
+            .ok_or(anyhow!("block #{} hash not found", block_number))?;
+        online
+            .blocks()
+            .at(block_hash)
+            .await
+            .map_err(|err| anyhow!("{}", err))
+    }
+
+    /// Get the number of the finalized head.
+    async fn fetch_finalized_block_number(&self) -> anyhow::Result<u64> {
+        let block_hash = self.client.online.rpc().finalized_head().await?;
+        let online_block = self.client.online.blocks().at(block_hash).await?;
+        Ok(online_block.header().number as u64)
     }
 }
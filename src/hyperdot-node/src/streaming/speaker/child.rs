@@ -1,7 +1,17 @@
+use std::str::FromStr;
+
 use hyperdot_core::config::StorageNodeConfig;
+use jsonrpsee::core::client::Subscription;
+use tokio::sync::OnceCell;
 
 use crate::storeage::client::JsonRpcClientParams;
 use crate::storeage::client::JsonRpcClinet;
+use crate::types::rpc::BlockAck;
+use crate::types::rpc::BlockGaps;
+use crate::types::rpc::BlockGapsResponse;
+use crate::types::rpc::EncodedWriteBlock;
+use crate::types::rpc::SubscribeBlockAcks;
+use crate::types::rpc::WireEncoding;
 use crate::types::rpc::WriteBlock;
 // use crate::types::rpc::WriteBlockRequest;
 use crate::types::rpc::WriteBlockResponse;
@@ -11,28 +21,78 @@ pub struct JsonRpcChild {
     name: String,
     node_cfg: StorageNodeConfig,
     remote_server_clinet: JsonRpcClinet,
+    /// The preferred wire encoding of config.
+    preferred_encoding: WireEncoding,
+    /// The wire encoding negotiated with the node at the first write.
+    encoding: OnceCell<WireEncoding>,
 }
 
 impl JsonRpcChild {
     /// Opens a child speaker for JSON-RPC communication.
     pub async fn open(node_cfg: &StorageNodeConfig) -> anyhow::Result<Self> {
-        let url = node_cfg
-            .rpc
-            .scheme
-            .as_ref()
-            .map_or(format!("ws://{}", node_cfg.rpc.url), |s| {
-                format!("{}://{}", s, node_cfg.rpc.url)
-            });
-        let client = JsonRpcClinet::new(&url, JsonRpcClientParams::default())?;
+        let url = node_cfg.rpc.endpoint();
+        let client = JsonRpcClinet::new(&url, JsonRpcClientParams {
+            tls: node_cfg.rpc.client_tls.clone(),
+        })?;
+        let preferred_encoding = match node_cfg.rpc.encoding.as_ref() {
+            None => WireEncoding::default(),
+            Some(encoding) => WireEncoding::from_str(encoding)?,
+        };
         Ok(Self {
             name: format!("speaker_jsonrpc_child_{}", node_cfg.name),
             node_cfg: node_cfg.clone(),
             remote_server_clinet: client,
+            preferred_encoding,
+            encoding: OnceCell::new(),
         })
     }
 
     pub async fn write_block(&self, request: WriteBlock) -> anyhow::Result<WriteBlockResponse> {
-        self.remote_server_clinet.write_block2(request).await
+        let encoding = self
+            .encoding
+            .get_or_try_init(|| async {
+                let encoding = self
+                    .remote_server_clinet
+                    .negotiate_encoding(self.preferred_encoding)
+                    .await?;
+                tracing::info!(
+                    "🤝 storage node {}: negotiated {:?} wire encoding",
+                    self.node_cfg.name,
+                    encoding
+                );
+                anyhow::Ok(encoding)
+            })
+            .await?;
+
+        match encoding {
+            WireEncoding::Json => self.remote_server_clinet.write_block2(request).await,
+            _ => {
+                let request = EncodedWriteBlock::encode(*encoding, &request)?;
+                self.remote_server_clinet.write_block_encoded(request).await
+            }
+        }
+    }
+
+    /// Subscribe the acknowledgements of the chain blocks stored by the
+    /// node, it's none if the transport is not websocket.
+    pub async fn subscribe_block_acks(
+        &self,
+        chain: &str,
+    ) -> anyhow::Result<Option<Subscription<BlockAck>>> {
+        if !self.remote_server_clinet.is_websocket() {
+            return Ok(None);
+        }
+
+        self.remote_server_clinet
+            .subscribe_block_acks(SubscribeBlockAcks {
+                chain: chain.to_string(),
+            })
+            .await
+            .map(Some)
+    }
+
+    pub async fn block_gaps(&self, request: BlockGaps) -> anyhow::Result<BlockGapsResponse> {
+        self.remote_server_clinet.block_gaps(request).await
     }
 
     pub fn name(&self) -> String {
@@ -1,11 +1,18 @@
 use std::collections::HashMap;
+use std::path::Path;
 use std::sync::Arc;
 
 use hyperdot_core::config::Catalog;
 use tokio::sync::RwLock;
 
 use super::child::JsonRpcChild;
+use super::outbox::BatchLimits;
+use super::outbox::NodeLag;
+use super::outbox::Outbox;
+use super::outbox::DEFAULT_OUTBOX_CAPACITY;
 // use super::child::SpeakerJsonRpcChild;
+use crate::types::rpc::BlockGaps;
+use crate::types::rpc::BlockRange;
 use crate::types::rpc::WriteBlock;
 // use super::SpeakerChild;
 // use super::SpeakerOps;
@@ -15,15 +22,19 @@ use crate::types::rpc::WriteBlockResponse;
 pub struct Controller {
     // childs: Arc<RwLock<Vec<SpeakerJsonRpcChild>>>,
     multi_chain: RwLock<HashMap<String, Vec<Arc<JsonRpcChild>>>>,
+    /// The outboxes of storage nodes per chain, the blocks are written
+    /// to outboxes first and delivered in background.
+    outboxes: RwLock<HashMap<String, Vec<Arc<Outbox>>>>,
 }
 
 impl Controller {
-    pub async fn async_new(catalog: Catalog) -> anyhow::Result<Self> {
+    pub async fn async_new(catalog: Catalog, outbox_dir: &Path) -> anyhow::Result<Self> {
         // let childs = super::url::parse_childs(urls).await?;
         // Ok(Self {
         //     childs: Arc::new(RwLock::new(childs)),
         // })
         let mut multi_chain = HashMap::new();
+        let mut outboxes = HashMap::new();
         for chain in catalog.chain.iter() {
             if (!chain.enabled) {
                 tracing::info!("💁 {}: skipped not enabled", chain.name);
@@ -87,6 +98,7 @@ impl Controller {
             }
 
             let mut chian_jsonrpc_childs = vec![];
+            let mut chain_outboxes = vec![];
             let mut not_available_childs = vec![];
             for snode_cfg in snode_cfg.iter() {
                 match JsonRpcChild::open(snode_cfg).await {
@@ -100,7 +112,15 @@ impl Controller {
                         continue;
                     }
                     Ok(child) => {
-                        chian_jsonrpc_childs.push(Arc::new(child));
+                        let child = Arc::new(child);
+                        let outbox_dir = outbox_dir.join(&chain.name).join(&snode_cfg.name);
+                        let capacity = snode_cfg.outbox_capacity.unwrap_or(DEFAULT_OUTBOX_CAPACITY);
+                        let outbox =
+                            Arc::new(Outbox::open(&snode_cfg.name, &outbox_dir, capacity).await?);
+                        outbox.spawn_delivery(child.clone(), BatchLimits::new(snode_cfg));
+                        outbox.spawn_acks(&chain.name, child.clone());
+                        chian_jsonrpc_childs.push(child);
+                        chain_outboxes.push(outbox);
                         not_available_childs.push(snode_cfg.name.clone());
                     }
                 }
@@ -114,30 +134,98 @@ impl Controller {
             }
 
             multi_chain.insert(chain.name.clone(), chian_jsonrpc_childs);
+            outboxes.insert(chain.name.clone(), chain_outboxes);
         }
 
         return Ok(Self {
             multi_chain: RwLock::new(multi_chain),
+            outboxes: RwLock::new(outboxes),
         });
     }
 
-    pub async fn write_block(&self, request: WriteBlock) -> anyhow::Result<WriteBlockResponse> {
-        let chain_name = &request.chain;
-        let childs = {
-            let rl = self.multi_chain.read().await;
-            if !rl.contains_key(chain_name) {
-                return Err(anyhow::anyhow!(
-                    "{}: no available storage node exists in the chain",
-                    chain_name
-                ));
-            }
-            rl.get(chain_name).unwrap().clone()
-        };
+    async fn get_childs(&self, chain_name: &str) -> anyhow::Result<Vec<Arc<JsonRpcChild>>> {
+        let rl = self.multi_chain.read().await;
+        if !rl.contains_key(chain_name) {
+            return Err(anyhow::anyhow!(
+                "{}: no available storage node exists in the chain",
+                chain_name
+            ));
+        }
+        Ok(rl.get(chain_name).unwrap().clone())
+    }
 
+    /// Find the missing blocks of the chain in any storage node.
+    pub async fn block_gaps(
+        &self,
+        chain_name: &str,
+        start: Option<u64>,
+        end: u64,
+    ) -> anyhow::Result<Vec<BlockRange>> {
+        let childs = self.get_childs(chain_name).await?;
+        let mut gaps = vec![];
         for child in childs.iter() {
-            child.write_block(request.clone()).await?;
+            let response = child
+                .block_gaps(BlockGaps {
+                    chain: chain_name.to_string(),
+                    start,
+                    end,
+                })
+                .await
+                .map_err(|err| anyhow::anyhow!("{}: {}", child.name(), err))?;
+            gaps.extend(response.gaps);
+        }
+
+        Ok(BlockRange::merge(gaps))
+    }
+
+    async fn get_outboxes(&self, chain_name: &str) -> anyhow::Result<Vec<Arc<Outbox>>> {
+        let rl = self.outboxes.read().await;
+        rl.get(chain_name).cloned().ok_or(anyhow::anyhow!(
+            "{}: no available storage node exists in the chain",
+            chain_name
+        ))
+    }
+
+    /// Write the block to the outboxes of all storage nodes. It returns
+    /// once the block stored durably, and is delivered in background.
+    pub async fn write_block(&self, request: WriteBlock) -> anyhow::Result<WriteBlockResponse> {
+        let outboxes = self.get_outboxes(&request.chain).await?;
+
+        for outbox in outboxes.iter() {
+            outbox.push(&request).await?;
         }
 
         Ok(WriteBlockResponse {})
     }
+
+    /// Get the highest block number that all finalized blocks from `from`
+    /// to it are acknowledged by all storage nodes of the chain.
+    pub async fn acked_until(&self, chain_name: &str, from: u64) -> anyhow::Result<Option<u64>> {
+        let outboxes = self.get_outboxes(chain_name).await?;
+        let mut acked_until = None;
+        for outbox in outboxes.iter() {
+            match outbox.acked_until(from) {
+                None => return Ok(None),
+                Some(end) => {
+                    acked_until = Some(acked_until.map_or(end, |until: u64| until.min(end)))
+                }
+            }
+        }
+        Ok(acked_until)
+    }
+
+    /// Forget the acknowledgements of the chain blocks before `before`.
+    pub async fn prune_acked(&self, chain_name: &str, before: u64) -> anyhow::Result<()> {
+        let outboxes = self.get_outboxes(chain_name).await?;
+        for outbox in outboxes.iter() {
+            outbox.prune_acked(before);
+        }
+        Ok(())
+    }
+
+    /// Get the lag of each storage node of the chain.
+    pub async fn lags(&self, chain_name: &str) -> anyhow::Result<Vec<NodeLag>> {
+        let outboxes = self.get_outboxes(chain_name).await?;
+        Ok(outboxes.iter().map(|outbox| outbox.lag()).collect())
+    }
 }
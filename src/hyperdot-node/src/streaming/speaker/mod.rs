@@ -1,10 +1,13 @@
 mod child;
 mod controller;
+mod outbox;
 // mod ops;
 // mod url;
 
 // pub use child::SpeakerChild;
 // pub use child::SpeakerJsonRpcChild;
 pub use controller::Controller;
+pub(crate) use outbox::sync_dir;
+pub(crate) use outbox::write_sync;
 // pub use controller::SpeakerController;
 // pub use ops::SpeakerOps;
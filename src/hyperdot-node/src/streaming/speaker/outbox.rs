@@ -0,0 +1,864 @@
+//! Durable write-ahead outbox of the storage nodes.
+//!
+//! Each request is stored as a file named by a increasing sequence
+//! before delivering, and removed after the storage node acknowledged
+//! it. The requests are delivered one by one in order, a failed one
+//! is retried with backoff until success, so the backlog of a down
+//! node is replayed once it comes back.
+//!
+//! A request rejected by the node too many times as never writable, or a
+//! file that can't be read back, is moved to the `dead` directory of the
+//! outbox so the requests behind it are still delivered. The failures of
+//! the node, e.g. its database is down, are never rejections. The dead
+//! letters are kept for inspection and could be moved back to be
+//! replayed.
+//!
+//! The requests are synced to disk before `push` returns, and `push`
+//! waits once the outbox is full, so a node down long holds back the
+//! streaming instead of growing the disk backlog without limit.
+//!
+//! A successful delivery acknowledges the blocks stored by all data
+//! engines of the node. Over websocket, the node also publishes the
+//! acknowledgements of the blocks written by any speaker, a lagged
+//! subscription is closed by the node and resubscribed. The outbox
+//! tracks the ranges of the finalized blocks acknowledged, so the holes
+//! are not hidden behind the highest one.
+
+use std::collections::VecDeque;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use hyperdot_core::config::StorageNodeConfig;
+use jsonrpsee::types::error::ErrorCode;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+
+use super::child::JsonRpcChild;
+use crate::types::rpc::BlockRange;
+use crate::types::rpc::WriteBlock;
+use crate::types::rpc::WRITE_BLOCK_REJECTED_CODE;
+
+/// The min backoff of retrying a failed delivery.
+const MIN_RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The max backoff of retrying a failed delivery.
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(60);
+
+/// The default max blocks sent in one write_block call.
+const DEFAULT_WRITE_BATCH_SIZE: usize = 64;
+
+/// The default max milliseconds waiting for a batch to fill up.
+const DEFAULT_WRITE_BATCH_TIMEOUT: u64 = 200;
+
+/// The default times a rejected request is retried.
+const DEFAULT_MAX_DELIVERY_ATTEMPTS: u32 = 10;
+
+/// The default max requests kept in the outbox.
+pub const DEFAULT_OUTBOX_CAPACITY: usize = 4096;
+
+/// The directory of the dead letters in the outbox.
+const DEAD_LETTER_DIR: &str = "dead";
+
+/// The limits of accumulating blocks into one write_block call, a batch
+/// is sent when either is reached.
+#[derive(Debug, Clone)]
+pub struct BatchLimits {
+    pub max_blocks: usize,
+    pub max_wait: Duration,
+    /// The times a rejected request is retried before dead lettered.
+    pub max_attempts: u32,
+}
+
+impl BatchLimits {
+    pub fn new(node_cfg: &StorageNodeConfig) -> Self {
+        Self {
+            max_blocks: node_cfg
+                .write_batch_size
+                .unwrap_or(DEFAULT_WRITE_BATCH_SIZE)
+                .max(1),
+            max_wait: Duration::from_millis(
+                node_cfg
+                    .write_batch_timeout
+                    .unwrap_or(DEFAULT_WRITE_BATCH_TIMEOUT),
+            ),
+            max_attempts: node_cfg
+                .max_delivery_attempts
+                .unwrap_or(DEFAULT_MAX_DELIVERY_ATTEMPTS)
+                .max(1),
+        }
+    }
+}
+
+/// The lag of a storage node behind the speaker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeLag {
+    pub node: String,
+    /// The number of requests not delivered.
+    pub pending: usize,
+    /// The lowest block number not delivered.
+    pub pending_block_number: Option<u64>,
+    /// The highest block number delivered since started.
+    pub delivered_block_number: Option<u64>,
+    /// The ranges of finalized blocks acknowledged stored, those behind
+    /// the checkpoint are pruned.
+    pub acked: Vec<BlockRange>,
+    /// The number of blocks delivered and stored since started.
+    pub acked_blocks: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct OutboxEntry {
+    seq: u64,
+    block_number: u64,
+}
+
+impl OutboxEntry {
+    fn file_name(&self) -> String {
+        format!("{:020}-{:020}.json", self.seq, self.block_number)
+    }
+
+    fn parse(file_name: &str) -> Option<Self> {
+        let (seq, block_number) = file_name.strip_suffix(".json")?.split_once('-')?;
+        Some(Self {
+            seq: seq.parse().ok()?,
+            block_number: block_number.parse().ok()?,
+        })
+    }
+}
+
+struct OutboxState {
+    next_seq: u64,
+    pending: VecDeque<OutboxEntry>,
+    delivered_block_number: Option<u64>,
+    acked: Vec<BlockRange>,
+    acked_blocks: u64,
+}
+
+pub struct Outbox {
+    node: String,
+    dir: PathBuf,
+    /// The max requests kept, `push` waits once reached.
+    capacity: usize,
+    state: Mutex<OutboxState>,
+    notify: Notify,
+    /// Notified when requests removed from the outbox.
+    popped: Notify,
+}
+
+impl Outbox {
+    /// Open the outbox in the directory, the requests left by the last
+    /// run are loaded in order.
+    pub async fn open(node: &str, dir: &Path, capacity: usize) -> anyhow::Result<Self> {
+        tokio::fs::create_dir_all(dir).await.map_err(|err| {
+            anyhow!(
+                "{}: create outbox directory({}) error: {}",
+                node,
+                dir.display(),
+                err
+            )
+        })?;
+
+        let mut pending = vec![];
+        let mut read_dir = tokio::fs::read_dir(dir).await?;
+        while let Some(dir_entry) = read_dir.next_entry().await? {
+            if dir_entry.file_type().await?.is_dir() {
+                continue;
+            }
+            let file_name = dir_entry.file_name();
+            let file_name = file_name.to_string_lossy();
+            match OutboxEntry::parse(&file_name) {
+                Some(entry) => pending.push(entry),
+                // The request not stored completely.
+                None => tokio::fs::remove_file(dir_entry.path()).await?,
+            }
+        }
+        pending.sort_by_key(|entry| entry.seq);
+
+        let next_seq = pending.last().map_or(0, |entry| entry.seq + 1);
+        if !pending.is_empty() {
+            tracing::info!(
+                "📮 {}: outbox loaded {} requests not delivered",
+                node,
+                pending.len()
+            );
+        }
+
+        Ok(Self {
+            node: node.to_string(),
+            dir: dir.to_path_buf(),
+            capacity: capacity.max(1),
+            state: Mutex::new(OutboxState {
+                next_seq,
+                pending: pending.into(),
+                delivered_block_number: None,
+                acked: vec![],
+                acked_blocks: 0,
+            }),
+            notify: Notify::new(),
+            popped: Notify::new(),
+        })
+    }
+
+    /// Store the request durably and wake up the delivery, it waits
+    /// while the outbox is full.
+    pub async fn push(&self, request: &WriteBlock) -> anyhow::Result<()> {
+        let mut waiting = false;
+        loop {
+            let popped = self.popped.notified();
+            let pending = self.state.lock().unwrap().pending.len();
+            if pending < self.capacity {
+                break;
+            }
+            if !waiting {
+                tracing::warn!(
+                    "📮 {}: outbox full of {} requests, wait for the delivery",
+                    self.node,
+                    pending
+                );
+                waiting = true;
+            }
+            popped.await;
+        }
+
+        let block_number = request
+            .polkadot_blocks
+            .as_ref()
+            .and_then(|blocks| blocks.iter().map(|block| block.header.block_number).min())
+            .unwrap_or_default();
+
+        // Only one writer per chain, the sequence is reserved when
+        // the request stored.
+        let seq = self.state.lock().unwrap().next_seq;
+        let entry = OutboxEntry { seq, block_number };
+        let path = self.dir.join(entry.file_name());
+        let tmp_path = path.with_extension("json.tmp");
+        let data = serde_json::to_vec(request)?;
+        write_sync(&tmp_path, &data).await.map_err(|err| {
+            anyhow!(
+                "{}: write outbox({}) error: {}",
+                self.node,
+                tmp_path.display(),
+                err
+            )
+        })?;
+        tokio::fs::rename(&tmp_path, &path).await.map_err(|err| {
+            anyhow!(
+                "{}: rename outbox({}) error: {}",
+                self.node,
+                path.display(),
+                err
+            )
+        })?;
+        // Sync the directory, or the renamed file may be lost on crash.
+        sync_dir(&self.dir).await.map_err(|err| {
+            anyhow!(
+                "{}: sync outbox directory({}) error: {}",
+                self.node,
+                self.dir.display(),
+                err
+            )
+        })?;
+
+        {
+            let mut state = self.state.lock().unwrap();
+            state.next_seq = seq + 1;
+            state.pending.push_back(entry);
+        }
+        self.notify.notify_one();
+        Ok(())
+    }
+
+    /// Get the lag of the storage node.
+    pub fn lag(&self) -> NodeLag {
+        let state = self.state.lock().unwrap();
+        NodeLag {
+            node: self.node.clone(),
+            pending: state.pending.len(),
+            pending_block_number: state.pending.iter().map(|entry| entry.block_number).min(),
+            delivered_block_number: state.delivered_block_number,
+            acked: state.acked.clone(),
+            acked_blocks: state.acked_blocks,
+        }
+    }
+
+    /// Record the blocks acknowledged stored by the node, only the
+    /// finalized ones are tracked.
+    fn ack(&self, blocks: &[(u64, bool)]) {
+        let mut state = self.state.lock().unwrap();
+        let finalized = blocks
+            .iter()
+            .filter(|(_, finalized)| *finalized)
+            .map(|(block_number, _)| BlockRange {
+                start: *block_number,
+                end: *block_number,
+            })
+            .collect::<Vec<_>>();
+        if !finalized.is_empty() {
+            let mut acked = std::mem::take(&mut state.acked);
+            acked.extend(finalized);
+            state.acked = BlockRange::merge(acked);
+        }
+    }
+
+    /// Get the highest block number that all finalized blocks from
+    /// `from` to it are acknowledged, it's none if `from` not acknowledged.
+    pub fn acked_until(&self, from: u64) -> Option<u64> {
+        let state = self.state.lock().unwrap();
+        state
+            .acked
+            .iter()
+            .find(|range| range.start <= from && from <= range.end)
+            .map(|range| range.end)
+    }
+
+    /// Forget the acknowledgements of the blocks before `before`.
+    pub fn prune_acked(&self, before: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.acked.retain(|range| range.end >= before);
+        if let Some(first) = state.acked.first_mut() {
+            first.start = std::cmp::max(first.start, before);
+        }
+    }
+
+    /// Read the first requests not delivered in order, and merge them
+    /// into one request of at most `max_blocks` blocks.
+    async fn front_batch(
+        &self,
+        max_blocks: usize,
+    ) -> anyhow::Result<Option<(Vec<OutboxEntry>, WriteBlock)>> {
+        // Each request has one block at least.
+        let pending = self
+            .state
+            .lock()
+            .unwrap()
+            .pending
+            .iter()
+            .take(max_blocks)
+            .copied()
+            .collect::<Vec<_>>();
+
+        let mut entries = vec![];
+        let mut batch: Option<WriteBlock> = None;
+        let mut batch_blocks = 0;
+        for entry in pending.into_iter() {
+            let request = match self.read(&entry).await {
+                Err(err) => {
+                    tracing::error!("📮 {}, move it to dead letters", err);
+                    self.dead_letter(&entry).await?;
+                    continue;
+                }
+                Ok(request) => request,
+            };
+            let blocks = request.polkadot_blocks.as_ref().map_or(0, |bs| bs.len());
+            if !entries.is_empty() && batch_blocks + blocks > max_blocks {
+                break;
+            }
+
+            batch_blocks += blocks;
+            entries.push(entry);
+            match batch.as_mut() {
+                None => batch = Some(request),
+                Some(batch) => {
+                    if let Some(blocks) = request.polkadot_blocks {
+                        batch
+                            .polkadot_blocks
+                            .get_or_insert_with(Vec::new)
+                            .extend(blocks);
+                    }
+                }
+            }
+            if batch_blocks >= max_blocks {
+                break;
+            }
+        }
+
+        Ok(batch.map(|batch| (entries, batch)))
+    }
+
+    async fn read(&self, entry: &OutboxEntry) -> anyhow::Result<WriteBlock> {
+        let path = self.dir.join(entry.file_name());
+        let data = tokio::fs::read(&path).await.map_err(|err| {
+            anyhow!(
+                "{}: read outbox({}) error: {}",
+                self.node,
+                path.display(),
+                err
+            )
+        })?;
+        serde_json::from_slice(&data)
+            .map_err(|err| anyhow!("{}: decode outbox request error: {}", self.node, err))
+    }
+
+    /// Remove the first requests after delivered.
+    async fn pop_batch(&self, entries: &[OutboxEntry]) -> anyhow::Result<()> {
+        for entry in entries.iter() {
+            let path = self.dir.join(entry.file_name());
+            tokio::fs::remove_file(&path).await.map_err(|err| {
+                anyhow!(
+                    "{}: remove outbox({}) error: {}",
+                    self.node,
+                    path.display(),
+                    err
+                )
+            })?;
+
+            let mut state = self.state.lock().unwrap();
+            state.pending.retain(|pending| pending.seq != entry.seq);
+            state.delivered_block_number =
+                std::cmp::max(state.delivered_block_number, Some(entry.block_number));
+        }
+        self.popped.notify_waiters();
+        Ok(())
+    }
+
+    /// Move the request to the dead letters, it's never delivered again.
+    async fn dead_letter(&self, entry: &OutboxEntry) -> anyhow::Result<()> {
+        let dead_dir = self.dir.join(DEAD_LETTER_DIR);
+        tokio::fs::create_dir_all(&dead_dir).await?;
+        let path = self.dir.join(entry.file_name());
+        let dead_path = dead_dir.join(entry.file_name());
+        tokio::fs::rename(&path, &dead_path).await.map_err(|err| {
+            anyhow!(
+                "{}: move outbox({}) to dead letters error: {}",
+                self.node,
+                path.display(),
+                err
+            )
+        })?;
+
+        self.state
+            .lock()
+            .unwrap()
+            .pending
+            .retain(|pending| pending.seq != entry.seq);
+        self.popped.notify_waiters();
+        Ok(())
+    }
+
+    /// Spawn the delivery of the outbox to the storage node.
+    pub fn spawn_delivery(
+        self: &Arc<Self>,
+        child: Arc<JsonRpcChild>,
+        limits: BatchLimits,
+    ) -> JoinHandle<()> {
+        let outbox = self.clone();
+        tokio::spawn(async move { outbox.delivery_loop(child, limits).await })
+    }
+
+    /// Spawn the listening of block acknowledgements of the chain from
+    /// the storage node, resubscribe with backoff if failed.
+    pub fn spawn_acks(self: &Arc<Self>, chain: &str, child: Arc<JsonRpcChild>) -> JoinHandle<()> {
+        let outbox = self.clone();
+        let chain = chain.to_string();
+        tokio::spawn(async move {
+            let mut backoff = MIN_RETRY_BACKOFF;
+            loop {
+                let mut subscription = match child.subscribe_block_acks(&chain).await {
+                    Ok(None) => {
+                        tracing::info!(
+                            "📮 {}: block acks not available without websocket transport",
+                            outbox.node
+                        );
+                        return;
+                    }
+                    Ok(Some(subscription)) => subscription,
+                    Err(err) => {
+                        tracing::warn!(
+                            "📮 {}: subscribe block acks error: {}, retry after {:?}",
+                            outbox.node,
+                            err,
+                            backoff
+                        );
+                        tokio::time::sleep(backoff).await;
+                        backoff = std::cmp::min(backoff * 2, MAX_RETRY_BACKOFF);
+                        continue;
+                    }
+                };
+
+                backoff = MIN_RETRY_BACKOFF;
+                while let Some(ack) = subscription.next().await {
+                    match ack {
+                        Ok(ack) => outbox.ack(&[(ack.block_number, ack.finalized)]),
+                        Err(err) => {
+                            tracing::warn!("📮 {}: receive block ack error: {}", outbox.node, err);
+                            break;
+                        }
+                    }
+                }
+                tracing::warn!("📮 {}: block acks subscription closed", outbox.node);
+                tokio::time::sleep(backoff).await;
+            }
+        })
+    }
+
+    /// Wait until the outbox has `max_blocks` requests or `max_wait`
+    /// elapsed since a request pending.
+    async fn wait_batch(&self, limits: &BatchLimits) {
+        while self.lag().pending == 0 {
+            self.notify.notified().await;
+        }
+
+        let deadline = tokio::time::Instant::now() + limits.max_wait;
+        while self.lag().pending < limits.max_blocks {
+            if tokio::time::timeout_at(deadline, self.notify.notified())
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+    }
+
+    /// Count the rejection of the failed delivery, the front request
+    /// rejected `max_attempts` times alone is moved to the dead letters.
+    /// Returns true if it's moved, the other failures are retried.
+    async fn reject_front(
+        &self,
+        entries: &[OutboxEntry],
+        rejects: &mut u32,
+        limits: &BatchLimits,
+        err: &anyhow::Error,
+    ) -> bool {
+        if !is_rejected(err) {
+            return false;
+        }
+        *rejects += 1;
+        if entries.len() > 1 || *rejects < limits.max_attempts {
+            return false;
+        }
+
+        tracing::error!(
+            "📮 {}: deliver #{} rejected {} times: {}, move it to dead letters",
+            self.node,
+            entries[0].block_number,
+            rejects,
+            err
+        );
+        if let Err(err) = self.dead_letter(&entries[0]).await {
+            tracing::error!("📮 {}", err);
+        }
+        *rejects = 0;
+        true
+    }
+
+    async fn delivery_loop(&self, child: Arc<JsonRpcChild>, limits: BatchLimits) {
+        let mut backoff = MIN_RETRY_BACKOFF;
+        // The times the front request rejected. The front request is sent
+        // alone once rejected, so a bad one is found out of the batch.
+        let mut rejects = 0;
+        loop {
+            // Not wait for more requests when retrying the backlog.
+            if backoff == MIN_RETRY_BACKOFF {
+                self.wait_batch(&limits).await;
+            }
+
+            let max_blocks = if rejects > 0 { 1 } else { limits.max_blocks };
+            let (entries, request) = match self.front_batch(max_blocks).await {
+                Err(err) => {
+                    tracing::error!("📮 {}", err);
+                    tokio::time::sleep(backoff).await;
+                    backoff = std::cmp::min(backoff * 2, MAX_RETRY_BACKOFF);
+                    continue;
+                }
+                Ok(None) => continue,
+                Ok(Some(batch)) => batch,
+            };
+
+            let acks = request.polkadot_blocks.as_ref().map_or(vec![], |blocks| {
+                blocks
+                    .iter()
+                    .map(|block| (block.header.block_number, block.header.is_finished))
+                    .collect::<Vec<_>>()
+            });
+            let blocks = acks.len();
+            match child.write_block(request).await {
+                Err(err) => {
+                    if self
+                        .reject_front(&entries, &mut rejects, &limits, &err)
+                        .await
+                    {
+                        backoff = MIN_RETRY_BACKOFF;
+                        continue;
+                    }
+                    tracing::warn!(
+                        "📮 {}: deliver {} blocks from #{} error: {}, retry after {:?}",
+                        self.node,
+                        blocks,
+                        entries[0].block_number,
+                        err,
+                        backoff
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = std::cmp::min(backoff * 2, MAX_RETRY_BACKOFF);
+                }
+                Ok(_) => {
+                    tracing::debug!(
+                        "📮 {}: delivered {} blocks from #{}",
+                        self.node,
+                        blocks,
+                        entries[0].block_number
+                    );
+                    // The node responses once all blocks stored.
+                    self.state.lock().unwrap().acked_blocks += blocks as u64;
+                    self.ack(&acks);
+                    if let Err(err) = self.pop_batch(&entries).await {
+                        tracing::error!("📮 {}", err);
+                    }
+                    if backoff > MIN_RETRY_BACKOFF {
+                        tracing::info!(
+                            "📮 {}: storage node back, replay {} requests",
+                            self.node,
+                            self.lag().pending
+                        );
+                    }
+                    rejects = 0;
+                    backoff = MIN_RETRY_BACKOFF;
+                }
+            }
+        }
+    }
+}
+
+/// Write the file and sync it to disk.
+pub(crate) async fn write_sync(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let mut file = tokio::fs::File::create(path).await?;
+    file.write_all(data).await?;
+    file.sync_all().await
+}
+
+/// Sync the entries of the directory to disk.
+pub(crate) async fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    tokio::fs::File::open(dir).await?.sync_all().await
+}
+
+/// Checks if the request is rejected by the node as never writable, the
+/// other errors, e.g. the node or its database is unavailable, are
+/// retried.
+fn is_rejected(err: &anyhow::Error) -> bool {
+    match err.downcast_ref::<jsonrpsee::core::Error>() {
+        Some(jsonrpsee::core::Error::Call(err)) => {
+            err.code() == WRITE_BLOCK_REJECTED_CODE || err.code() == ErrorCode::InvalidParams.code()
+        }
+        Some(jsonrpsee::core::Error::ParseError(_)) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyperdot_core::types::ChainKind;
+    use jsonrpsee::types::ErrorObject;
+
+    use super::*;
+    use crate::types::block::polkadot_chain;
+
+    fn write_block(block_number: u64) -> WriteBlock {
+        let mut block = polkadot_chain::Block::default();
+        block.header.block_number = block_number;
+        WriteBlock {
+            chain: "Polkadot".to_string(),
+            chain_kind: ChainKind::Polkadot,
+            polkadot_blocks: Some(vec![block]),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_outbox_replay_in_order() {
+        let dir = std::env::temp_dir().join(format!("hyperdot-outbox-{}", std::process::id()));
+        let outbox = Outbox::open("node", &dir, DEFAULT_OUTBOX_CAPACITY)
+            .await
+            .unwrap();
+        outbox.push(&write_block(10)).await.unwrap();
+        outbox.push(&write_block(5)).await.unwrap();
+        outbox.push(&write_block(11)).await.unwrap();
+
+        let (entries, request) = outbox.front_batch(1).await.unwrap().unwrap();
+        let blocks = request.polkadot_blocks.unwrap();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].header.block_number, 10);
+        outbox.pop_batch(&entries).await.unwrap();
+        assert_eq!(outbox.lag(), NodeLag {
+            node: "node".to_string(),
+            pending: 2,
+            pending_block_number: Some(5),
+            delivered_block_number: Some(10),
+            acked: vec![],
+            acked_blocks: 0,
+        });
+
+        // The hole of #6 is kept, the unfinalized #7 not tracked.
+        outbox.ack(&[(10, true), (5, true), (8, true), (9, true), (7, false)]);
+        outbox.ack(&[(4, true)]);
+        assert_eq!(outbox.lag().acked, vec![
+            BlockRange { start: 4, end: 5 },
+            BlockRange { start: 8, end: 10 },
+        ]);
+        assert_eq!(outbox.acked_until(4), Some(5));
+        assert_eq!(outbox.acked_until(6), None);
+        outbox.prune_acked(9);
+        assert_eq!(outbox.lag().acked, vec![BlockRange { start: 9, end: 10 }]);
+        drop(outbox);
+
+        // Reopen to replay the requests left.
+        let outbox = Outbox::open("node", &dir, DEFAULT_OUTBOX_CAPACITY)
+            .await
+            .unwrap();
+        let (entries, request) = outbox.front_batch(64).await.unwrap().unwrap();
+        let block_numbers = request
+            .polkadot_blocks
+            .unwrap()
+            .iter()
+            .map(|block| block.header.block_number)
+            .collect::<Vec<_>>();
+        assert_eq!(block_numbers, vec![5, 11]);
+        outbox.pop_batch(&entries).await.unwrap();
+        assert!(outbox.front_batch(64).await.unwrap().is_none());
+
+        outbox.push(&write_block(12)).await.unwrap();
+        let (entries, _) = outbox.front_batch(64).await.unwrap().unwrap();
+        assert_eq!((entries[0].seq, entries[0].block_number), (3, 12));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_outbox_dead_letter_corrupt_request() {
+        let dir = std::env::temp_dir().join(format!("hyperdot-outbox-dead-{}", std::process::id()));
+        let outbox = Outbox::open("node", &dir, DEFAULT_OUTBOX_CAPACITY)
+            .await
+            .unwrap();
+        outbox.push(&write_block(1)).await.unwrap();
+        outbox.push(&write_block(2)).await.unwrap();
+        outbox.push(&write_block(3)).await.unwrap();
+        let corrupt = OutboxEntry {
+            seq: 1,
+            block_number: 2,
+        };
+        std::fs::write(dir.join(corrupt.file_name()), b"{").unwrap();
+
+        // The corrupt request is moved aside, the others still delivered.
+        let (entries, request) = outbox.front_batch(64).await.unwrap().unwrap();
+        let block_numbers = request
+            .polkadot_blocks
+            .unwrap()
+            .iter()
+            .map(|block| block.header.block_number)
+            .collect::<Vec<_>>();
+        assert_eq!(block_numbers, vec![1, 3]);
+        assert!(dir.join(DEAD_LETTER_DIR).join(corrupt.file_name()).exists());
+        outbox.pop_batch(&entries).await.unwrap();
+        assert_eq!(outbox.lag().pending, 0);
+        drop(outbox);
+
+        // The dead letters are not loaded again.
+        let outbox = Outbox::open("node", &dir, DEFAULT_OUTBOX_CAPACITY)
+            .await
+            .unwrap();
+        assert_eq!(outbox.lag().pending, 0);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_outbox_push_wait_full() {
+        let dir = std::env::temp_dir().join(format!("hyperdot-outbox-full-{}", std::process::id()));
+        let outbox = Arc::new(Outbox::open("node", &dir, 2).await.unwrap());
+        outbox.push(&write_block(1)).await.unwrap();
+        outbox.push(&write_block(2)).await.unwrap();
+
+        let push = tokio::spawn({
+            let outbox = outbox.clone();
+            async move { outbox.push(&write_block(3)).await }
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!push.is_finished());
+        assert_eq!(outbox.lag().pending, 2);
+
+        let (entries, _) = outbox.front_batch(1).await.unwrap().unwrap();
+        outbox.pop_batch(&entries).await.unwrap();
+        push.await.unwrap().unwrap();
+        assert_eq!(outbox.lag().pending, 2);
+        assert_eq!(outbox.lag().pending_block_number, Some(2));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_is_rejected() {
+        let call = |err| anyhow::Error::from(jsonrpsee::core::Error::Call(err));
+        assert!(is_rejected(&call(ErrorObject::owned(
+            WRITE_BLOCK_REJECTED_CODE,
+            "no data engine supports the chain",
+            None::<()>
+        ))));
+        assert!(is_rejected(&call(ErrorObject::from(
+            ErrorCode::InvalidParams
+        ))));
+        let parse = serde_json::from_str::<u64>("x").unwrap_err();
+        assert!(is_rejected(&anyhow::Error::from(
+            jsonrpsee::core::Error::ParseError(parse)
+        )));
+
+        // The failures of the node are retried, e.g. its database is down.
+        assert!(!is_rejected(&call(ErrorObject::from(
+            ErrorCode::InternalError
+        ))));
+        assert!(!is_rejected(&anyhow!("encode error")));
+        assert!(!is_rejected(&anyhow::Error::from(
+            jsonrpsee::core::Error::RequestTimeout
+        )));
+    }
+
+    #[tokio::test]
+    async fn test_internal_error_never_dead_lettered() {
+        let dir =
+            std::env::temp_dir().join(format!("hyperdot-outbox-internal-{}", std::process::id()));
+        let outbox = Outbox::open("node", &dir, DEFAULT_OUTBOX_CAPACITY)
+            .await
+            .unwrap();
+        outbox.push(&write_block(1)).await.unwrap();
+        let limits = BatchLimits {
+            max_blocks: 1,
+            max_wait: Duration::from_millis(1),
+            max_attempts: 1,
+        };
+        let (entries, _) = outbox.front_batch(1).await.unwrap().unwrap();
+        let mut rejects = 0;
+        let err = anyhow::Error::from(jsonrpsee::core::Error::Call(ErrorObject::from(
+            ErrorCode::InternalError,
+        )));
+        for _ in 0..20 {
+            assert!(
+                !outbox
+                    .reject_front(&entries, &mut rejects, &limits, &err)
+                    .await
+            );
+        }
+        assert_eq!(rejects, 0);
+        assert_eq!(outbox.lag().pending, 1);
+        assert!(!dir.join(DEAD_LETTER_DIR).exists());
+
+        // The request never writable is dead lettered.
+        let err = anyhow::Error::from(jsonrpsee::core::Error::Call(ErrorObject::owned(
+            WRITE_BLOCK_REJECTED_CODE,
+            "no data engine supports the chain",
+            None::<()>,
+        )));
+        assert!(
+            outbox
+                .reject_front(&entries, &mut rejects, &limits, &err)
+                .await
+        );
+        assert!(dir
+            .join(DEAD_LETTER_DIR)
+            .join(entries[0].file_name())
+            .exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
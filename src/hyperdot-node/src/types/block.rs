@@ -1,7 +1,11 @@
 pub mod polkadot_chain {
+    use codec::Decode;
+    use codec::Encode;
     use serde::Deserialize;
     use serde::Serialize;
 
+    use super::ScaleJson;
+
     #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
     pub enum EventPhase {
         /// Applying an extrinsic.
@@ -77,7 +81,8 @@ pub mod polkadot_chain {
         pub events: Option<Vec<EventDescribe>>,
     }
 
-    #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+    #[derive(Clone, Debug, serde::Serialize, serde::Deserialize, Encode, Decode)]
+    #[codec(crate = codec)]
     pub struct Log {
         pub id: String,
         pub block_number: u64,
@@ -86,7 +91,8 @@ pub mod polkadot_chain {
         pub data: Option<Vec<u8>>,
     }
 
-    #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+    #[derive(Clone, Debug, serde::Serialize, serde::Deserialize, Encode, Decode)]
+    #[codec(crate = codec)]
     pub struct Event {
         pub id: String,
         pub block_number: u64,
@@ -98,10 +104,12 @@ pub mod polkadot_chain {
         pub event_index: u32,
         pub phase: u16,
         pub extrinsic_hash: Vec<u8>,
+        #[codec(encoded_as = "ScaleJson")]
         pub values: Option<serde_json::Value>,
     }
 
-    #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+    #[derive(Clone, Debug, serde::Serialize, serde::Deserialize, Encode, Decode)]
+    #[codec(crate = codec)]
     pub struct Extrinsic {
         pub id: String,
         pub block_number: u64,
@@ -109,6 +117,7 @@ pub mod polkadot_chain {
         pub block_timestamp: u64,
         pub mod_name: String,
         pub call_name: String,
+        #[codec(encoded_as = "ScaleJson")]
         pub call_params: Option<serde_json::Value>,
         pub signature: Option<Vec<u8>>,
         pub result: bool,
@@ -121,13 +130,15 @@ pub mod polkadot_chain {
         pub extrinsics: Vec<ExtrinsicDetails>,
     }
 
-    #[derive(Debug, Default, Clone, Serialize, Deserialize)]
+    #[derive(Debug, Default, Clone, Serialize, Deserialize, Encode, Decode)]
+    #[codec(crate = codec)]
     pub struct Body {
         pub extrinsics: Option<Vec<Extrinsic>>,
         pub events: Option<Vec<Event>>,
     }
 
-    #[derive(Debug, Default, Clone, Serialize, Deserialize)]
+    #[derive(Debug, Default, Clone, Serialize, Deserialize, Encode, Decode)]
+    #[codec(crate = codec)]
     pub struct Header {
         pub block_number: u64,
         pub block_timestamp: u64,
@@ -140,7 +151,8 @@ pub mod polkadot_chain {
         pub spec_version: u32,
     }
 
-    #[derive(Debug, Default, Clone, Serialize, Deserialize)]
+    #[derive(Debug, Default, Clone, Serialize, Deserialize, Encode, Decode)]
+    #[codec(crate = codec)]
     pub struct Block {
         pub header: Header,
         pub body: Body,
@@ -148,3 +160,44 @@ pub mod polkadot_chain {
         // pub body: Option<BlockGenericBody>,
     }
 }
+
+/// SCALE encoding of the optional json value, encoded as the json
+/// bytes since the json value has no SCALE codec.
+pub struct ScaleJson(Option<serde_json::Value>);
+
+pub struct ScaleJsonRef<'a>(&'a Option<serde_json::Value>);
+
+impl<'a> From<&'a Option<serde_json::Value>> for ScaleJsonRef<'a> {
+    fn from(value: &'a Option<serde_json::Value>) -> Self {
+        Self(value)
+    }
+}
+
+impl codec::Encode for ScaleJsonRef<'_> {
+    fn encode_to<T: codec::Output + ?Sized>(&self, dest: &mut T) {
+        self.0
+            .as_ref()
+            .map(|value| serde_json::to_vec(value).expect("json value serialize"))
+            .encode_to(dest)
+    }
+}
+
+impl<'a> codec::EncodeAsRef<'a, Option<serde_json::Value>> for ScaleJson {
+    type RefType = ScaleJsonRef<'a>;
+}
+
+impl codec::Decode for ScaleJson {
+    fn decode<I: codec::Input>(input: &mut I) -> Result<Self, codec::Error> {
+        Option::<Vec<u8>>::decode(input)?
+            .map(|bytes| serde_json::from_slice(&bytes))
+            .transpose()
+            .map(ScaleJson)
+            .map_err(|_| codec::Error::from("invalid json value"))
+    }
+}
+
+impl From<ScaleJson> for Option<serde_json::Value> {
+    fn from(value: ScaleJson) -> Self {
+        value.0
+    }
+}
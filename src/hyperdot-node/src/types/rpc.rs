@@ -1,3 +1,9 @@
+use std::str::FromStr;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use codec::Decode;
+use codec::Encode;
 use hyperdot_core::types::ChainKind;
 use jsonrpsee_core::traits::ToRpcParams;
 use jsonrpsee_core::Error;
@@ -10,6 +16,11 @@ use super::block::polkadot_chain;
 #[derive(Clone, Serialize, Deserialize)]
 pub struct WriteBlockResponse {}
 
+/// The json-rpc error code of a write request the node can never write,
+/// e.g. it can't be decoded or no data engine stores the chain. The
+/// speaker dead letters it, the other errors are retried.
+pub const WRITE_BLOCK_REJECTED_CODE: i32 = -32020;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WriteBlock {
     pub chain: String,
@@ -25,3 +36,286 @@ impl ToRpcParams for WriteBlock {
             .map_err(jsonrpsee_core::Error::ParseError)
     }
 }
+
+impl Encode for WriteBlock {
+    fn encode_to<T: codec::Output + ?Sized>(&self, dest: &mut T) {
+        self.chain.encode_to(dest);
+        let kind: u8 = match self.chain_kind {
+            ChainKind::Ethereum => 0,
+            ChainKind::Polkadot => 1,
+        };
+        kind.encode_to(dest);
+        self.polkadot_blocks.encode_to(dest);
+    }
+}
+
+impl Decode for WriteBlock {
+    fn decode<I: codec::Input>(input: &mut I) -> Result<Self, codec::Error> {
+        let chain = String::decode(input)?;
+        let chain_kind = match u8::decode(input)? {
+            0 => ChainKind::Ethereum,
+            1 => ChainKind::Polkadot,
+            _ => return Err("invalid chain kind".into()),
+        };
+        let polkadot_blocks = Option::<Vec<polkadot_chain::Block>>::decode(input)?;
+        Ok(Self {
+            chain,
+            chain_kind,
+            polkadot_blocks,
+        })
+    }
+}
+
+/// The encoding of blocks sent to the storage node.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WireEncoding {
+    /// The plain json, readable for debugging.
+    Json,
+    /// The SCALE codec, the bytes are sent as base64 string.
+    #[default]
+    Scale,
+}
+
+impl FromStr for WireEncoding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(Self::Json),
+            "scale" => Ok(Self::Scale),
+            _ => Err(anyhow::anyhow!("unknown wire encoding: {}", s)),
+        }
+    }
+}
+
+/// The encoded `WriteBlock`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncodedWriteBlock {
+    pub encoding: WireEncoding,
+    pub data: String,
+}
+
+impl EncodedWriteBlock {
+    pub fn encode(encoding: WireEncoding, block: &WriteBlock) -> anyhow::Result<Self> {
+        let data = match encoding {
+            WireEncoding::Json => serde_json::to_string(block)?,
+            WireEncoding::Scale => BASE64.encode(block.encode()),
+        };
+        Ok(Self { encoding, data })
+    }
+
+    pub fn decode(&self) -> anyhow::Result<WriteBlock> {
+        match self.encoding {
+            WireEncoding::Json => Ok(serde_json::from_str(&self.data)?),
+            WireEncoding::Scale => {
+                let bytes = BASE64.decode(&self.data)?;
+                WriteBlock::decode(&mut bytes.as_slice())
+                    .map_err(|err| anyhow::anyhow!("decode scale write block error: {}", err))
+            }
+        }
+    }
+}
+
+impl ToRpcParams for EncodedWriteBlock {
+    fn to_rpc_params(self) -> Result<Option<Box<RawValue>>, Error> {
+        let s = String::from_utf8(serde_json::to_vec(&self)?).expect("valid UTF8 format");
+        serde_json::value::RawValue::from_string(s)
+            .map(Some)
+            .map_err(jsonrpsee_core::Error::ParseError)
+    }
+}
+
+/// The encodings supported by the storage node.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WireEncodingsResponse {
+    pub encodings: Vec<WireEncoding>,
+}
+
+/// Subscribe the acknowledgements of blocks stored by the node.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubscribeBlockAcks {
+    pub chain: String,
+}
+
+impl ToRpcParams for SubscribeBlockAcks {
+    fn to_rpc_params(self) -> Result<Option<Box<RawValue>>, Error> {
+        let s = String::from_utf8(serde_json::to_vec(&self)?).expect("valid UTF8 format");
+        serde_json::value::RawValue::from_string(s)
+            .map(Some)
+            .map_err(jsonrpsee_core::Error::ParseError)
+    }
+}
+
+/// The block stored durably by all data engines of the node.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockAck {
+    pub chain: String,
+    pub block_number: u64,
+    /// The block stored is finalized, an unfinalized one could be
+    /// replaced later.
+    #[serde(default)]
+    pub finalized: bool,
+}
+
+/// The inclusive range of block numbers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl BlockRange {
+    /// Merge the overlapping or adjacent ranges.
+    pub fn merge(mut ranges: Vec<BlockRange>) -> Vec<BlockRange> {
+        ranges.sort_by_key(|range| range.start);
+        let mut merged: Vec<BlockRange> = vec![];
+        for range in ranges.into_iter() {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end + 1 => {
+                    last.end = std::cmp::max(last.end, range.end);
+                }
+                _ => merged.push(range),
+            }
+        }
+        merged
+    }
+
+    /// Remove the blocks in `other` from the ranges.
+    pub fn subtract(ranges: Vec<BlockRange>, other: &[BlockRange]) -> Vec<BlockRange> {
+        let mut ranges = ranges;
+        for hole in other.iter() {
+            let mut rest = vec![];
+            for range in ranges.into_iter() {
+                if hole.end < range.start || hole.start > range.end {
+                    rest.push(range);
+                    continue;
+                }
+                if range.start < hole.start {
+                    rest.push(BlockRange {
+                        start: range.start,
+                        end: hole.start - 1,
+                    });
+                }
+                if range.end > hole.end {
+                    rest.push(BlockRange {
+                        start: hole.end + 1,
+                        end: range.end,
+                    });
+                }
+            }
+            ranges = rest;
+        }
+        ranges
+    }
+}
+
+/// Find the block numbers in `[start, end]` missing from the storage.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockGaps {
+    pub chain: String,
+    /// If none, start from the lowest block number in the storage.
+    pub start: Option<u64>,
+    pub end: u64,
+}
+
+impl ToRpcParams for BlockGaps {
+    fn to_rpc_params(self) -> Result<Option<Box<RawValue>>, Error> {
+        let s = String::from_utf8(serde_json::to_vec(&self)?).expect("valid UTF8 format");
+        serde_json::value::RawValue::from_string(s)
+            .map(Some)
+            .map_err(jsonrpsee_core::Error::ParseError)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockGapsResponse {
+    pub gaps: Vec<BlockRange>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encoded_write_block() {
+        let block = polkadot_chain::Block {
+            header: polkadot_chain::Header {
+                block_number: 7,
+                block_hash: vec![1_u8; 32],
+                spec_version: 9430,
+                ..Default::default()
+            },
+            body: polkadot_chain::Body {
+                extrinsics: Some(vec![polkadot_chain::Extrinsic {
+                    id: "7-0".to_string(),
+                    block_number: 7,
+                    extrinsic_hash: vec![2_u8; 32],
+                    block_timestamp: 0,
+                    mod_name: "Timestamp".to_string(),
+                    call_name: "set".to_string(),
+                    call_params: Some(serde_json::json!({ "now": 1 })),
+                    signature: None,
+                    result: true,
+                }]),
+                events: Some(vec![]),
+            },
+            logs: None,
+        };
+        let write_block = WriteBlock {
+            chain: "polkadot".to_string(),
+            chain_kind: ChainKind::Polkadot,
+            polkadot_blocks: Some(vec![block]),
+        };
+
+        let json = EncodedWriteBlock::encode(WireEncoding::Json, &write_block).unwrap();
+        let scale = EncodedWriteBlock::encode(WireEncoding::Scale, &write_block).unwrap();
+        assert!(scale.data.len() < json.data.len());
+
+        for encoded in [json, scale] {
+            let decoded = encoded.decode().unwrap();
+            assert_eq!(decoded.chain, "polkadot");
+            let blocks = decoded.polkadot_blocks.unwrap();
+            assert_eq!(blocks[0].header.block_hash, vec![1_u8; 32]);
+            assert_eq!(blocks[0].header.spec_version, 9430);
+            let extrinsics = blocks[0].body.extrinsics.as_ref().unwrap();
+            assert_eq!(
+                extrinsics[0].call_params,
+                Some(serde_json::json!({ "now": 1 }))
+            );
+        }
+    }
+
+    #[test]
+    fn test_merge_block_range() {
+        let merged = BlockRange::merge(vec![
+            BlockRange { start: 10, end: 12 },
+            BlockRange { start: 1, end: 3 },
+            BlockRange { start: 4, end: 5 },
+            BlockRange { start: 11, end: 20 },
+        ]);
+        assert_eq!(merged, vec![BlockRange { start: 1, end: 5 }, BlockRange {
+            start: 10,
+            end: 20
+        },]);
+    }
+
+    #[test]
+    fn test_subtract_block_range() {
+        let ranges = BlockRange::subtract(
+            vec![BlockRange { start: 1, end: 10 }, BlockRange {
+                start: 20,
+                end: 30,
+            }],
+            &[BlockRange { start: 3, end: 4 }, BlockRange {
+                start: 8,
+                end: 22,
+            }],
+        );
+        assert_eq!(ranges, vec![
+            BlockRange { start: 1, end: 2 },
+            BlockRange { start: 5, end: 7 },
+            BlockRange { start: 23, end: 30 },
+        ]);
+    }
+}